@@ -0,0 +1,565 @@
+pub mod header;
+mod huc1;
+mod huc3;
+mod m161;
+mod mbc1;
+mod mbc3;
+mod mbc5;
+mod mbc6;
+mod mbc7;
+mod mmm01;
+mod tama5;
+mod unlicensed;
+
+use header::{CartridgeHeader, CgbSupport, RamSize, CGB_FLAG, LOGO_START, TITLE_START};
+use huc1::Huc1;
+use huc3::Huc3;
+use m161::M161;
+use mbc1::Mbc1;
+use mbc3::Mbc3;
+use mbc5::Mbc5;
+use mbc6::Mbc6;
+use mbc7::Mbc7;
+use mmm01::Mmm01;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tama5::Tama5;
+use unlicensed::{BitSwappedMbc5, LiCheng, NtOld, Sachen, WisdomTree};
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+pub const RAM_BANK_SIZE: usize = 0x2000;
+
+const ROM_BANK_0_END: u16 = 0x4000;
+//mmm01 multicarts boot into a menu whose header sits in the last 32KB
+const MMM01_HEADER_FROM_END: usize = 0x8000;
+//the mani 4 in 1 carts claim to be mbc3 in the header
+const M161_TITLE: &[u8] = b"TETRIS SET";
+//unlicensed carts ignore the header, these are the strings that give them away
+const WISDOM_TREE_SIGNATURES: [&[u8]; 2] = [b"WISDOM TREE", b"WISDOM\x00TREE"];
+//mbc1m multicarts repeat the header at the start of every 256KB game
+const MBC1_MULTICART_SIZE: usize = 0x100000;
+const MBC1_MULTICART_GAME_SIZE: usize = 0x40000;
+
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+const RAM_START: u16 = 0xA000;
+//infrared port reads return 0xC0 with bit 0 set when light is seen
+const IR_READ_BASE: u8 = 0xC0;
+
+//called with the new motor state every time a rumble cartridge toggles it
+pub type RumbleCallback = Box<dyn FnMut(bool)>;
+
+//the other side of an infrared link: another console, a toy, a remote
+pub trait InfraredPeer {
+    //our led was switched on or off
+    fn set_led(&mut self, on: bool);
+    //whether the peer is currently lighting our sensor
+    fn light_detected(&self) -> bool;
+}
+
+//a peer that sees our own led, like pointing the port at a mirror
+#[derive(Default)]
+pub struct InfraredLoopback {
+    led: bool,
+}
+
+impl InfraredPeer for InfraredLoopback {
+    fn set_led(&mut self, on: bool) {
+        self.led = on;
+    }
+
+    fn light_detected(&self) -> bool {
+        self.led
+    }
+}
+
+//common interface of every cartridge, addresses are the ones seen on the bus
+pub trait Mapper {
+    //the whole rom, every bank the mapper switches between
+    fn rom(&self) -> &[u8];
+    //0x0000-0x7FFF
+    fn read_rom(&self, address: u16) -> u8;
+    //rom read that isn't a cpu fetch, like dma, which mappers counting reads don't see
+    fn peek_rom(&self, address: u16) -> u8 {
+        self.read_rom(address)
+    }
+    //writes to 0x0000-0x7FFF go to the mapper registers
+    fn write_rom(&mut self, address: u16, value: u8);
+    //0xA000-0xBFFF
+    fn read_ram(&self, _address: u16) -> u8 {
+        0xFF
+    }
+    fn write_ram(&mut self, _address: u16, _value: u8) {}
+    //contents that survive power off: battery ram, eeprom, ...
+    fn save_data(&self) -> &[u8] {
+        &[]
+    }
+    fn load_save_data(&mut self, _data: &[u8]) {}
+    //whether the save data changed since the last call, so unchanged saves aren't written
+    fn take_dirty(&mut self) -> bool {
+        false
+    }
+    //clock state stored after the save data, in the format other emulators use
+    fn rtc_footer(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    fn load_rtc_footer(&mut self, _footer: &[u8]) {}
+    //only rumble cartridges ever call it
+    fn set_rumble_callback(&mut self, _callback: RumbleCallback) {}
+    //tilt in g for cartridges with an accelerometer, positive x is right and positive y is down
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
+    //only cartridges with an infrared port talk to it
+    fn set_infrared_peer(&mut self, _peer: Box<dyn InfraredPeer>) {}
+    //advance cartridge clocks by cpu cycles
+    fn tick(&mut self, _cycles: u32) {}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MapperKind {
+    RomOnly,
+    Mbc1,
+    Mbc1Multicart,
+    Mbc3,
+    Mbc3Rtc,
+    Mbc5,
+    Mbc5Rumble,
+    Mbc6,
+    Mbc7,
+    Mmm01,
+    Tama5,
+    Huc1,
+    Huc3,
+    M161,
+    WisdomTree,
+    SachenMmc1,
+    SachenMmc2,
+    LiCheng,
+    Bbd,
+    Hitek,
+    NtOld,
+    //a cartridge type byte none of the above handle, like mbc2 or the pocket camera
+    Unsupported,
+}
+
+impl MapperKind {
+    pub const ALL: [MapperKind; 21] = [
+        MapperKind::RomOnly,
+        MapperKind::Mbc1,
+        MapperKind::Mbc1Multicart,
+        MapperKind::Mbc3,
+        MapperKind::Mbc3Rtc,
+        MapperKind::Mbc5,
+        MapperKind::Mbc5Rumble,
+        MapperKind::Mbc6,
+        MapperKind::Mbc7,
+        MapperKind::Mmm01,
+        MapperKind::Tama5,
+        MapperKind::Huc1,
+        MapperKind::Huc3,
+        MapperKind::M161,
+        MapperKind::WisdomTree,
+        MapperKind::SachenMmc1,
+        MapperKind::SachenMmc2,
+        MapperKind::LiCheng,
+        MapperKind::Bbd,
+        MapperKind::Hitek,
+        MapperKind::NtOld,
+    ];
+
+    //name accepted by the mapper override
+    pub fn from_name(name: &str) -> Option<MapperKind> {
+        Some(match name.to_lowercase().as_str() {
+            "rom" => MapperKind::RomOnly,
+            "mbc1" => MapperKind::Mbc1,
+            "mbc1m" => MapperKind::Mbc1Multicart,
+            "mbc3" => MapperKind::Mbc3,
+            "mbc3-rtc" => MapperKind::Mbc3Rtc,
+            "mbc5" => MapperKind::Mbc5,
+            "mbc5-rumble" => MapperKind::Mbc5Rumble,
+            "mbc6" => MapperKind::Mbc6,
+            "mbc7" => MapperKind::Mbc7,
+            "mmm01" => MapperKind::Mmm01,
+            "tama5" => MapperKind::Tama5,
+            "huc1" => MapperKind::Huc1,
+            "huc3" => MapperKind::Huc3,
+            "m161" => MapperKind::M161,
+            "wisdom-tree" => MapperKind::WisdomTree,
+            "sachen-mmc1" => MapperKind::SachenMmc1,
+            "sachen-mmc2" => MapperKind::SachenMmc2,
+            "li-cheng" => MapperKind::LiCheng,
+            "bbd" => MapperKind::Bbd,
+            "hitek" => MapperKind::Hitek,
+            "nt-old" => MapperKind::NtOld,
+            _ => return None,
+        })
+    }
+
+    //name accepted by from_name
+    pub fn name(&self) -> &'static str {
+        match self {
+            MapperKind::RomOnly => "rom",
+            MapperKind::Mbc1 => "mbc1",
+            MapperKind::Mbc1Multicart => "mbc1m",
+            MapperKind::Mbc3 => "mbc3",
+            MapperKind::Mbc3Rtc => "mbc3-rtc",
+            MapperKind::Mbc5 => "mbc5",
+            MapperKind::Mbc5Rumble => "mbc5-rumble",
+            MapperKind::Mbc6 => "mbc6",
+            MapperKind::Mbc7 => "mbc7",
+            MapperKind::Mmm01 => "mmm01",
+            MapperKind::Tama5 => "tama5",
+            MapperKind::Huc1 => "huc1",
+            MapperKind::Huc3 => "huc3",
+            MapperKind::M161 => "m161",
+            MapperKind::WisdomTree => "wisdom-tree",
+            MapperKind::SachenMmc1 => "sachen-mmc1",
+            MapperKind::SachenMmc2 => "sachen-mmc2",
+            MapperKind::LiCheng => "li-cheng",
+            MapperKind::Bbd => "bbd",
+            MapperKind::Hitek => "hitek",
+            MapperKind::NtOld => "nt-old",
+            MapperKind::Unsupported => "unsupported",
+        }
+    }
+
+    //mapper for the cartridge type byte at 0x147
+    pub fn from_c_type(c_type: u8) -> MapperKind {
+        match c_type {
+            0x00 | 0x08 | 0x09 => MapperKind::RomOnly,
+            0x01..=0x03 => MapperKind::Mbc1,
+            0x0B..=0x0D => MapperKind::Mmm01,
+            0x0F..=0x10 => MapperKind::Mbc3Rtc,
+            0x11..=0x13 => MapperKind::Mbc3,
+            0x19..=0x1B => MapperKind::Mbc5,
+            0x1C..=0x1E => MapperKind::Mbc5Rumble,
+            0x20 => MapperKind::Mbc6,
+            0x22 => MapperKind::Mbc7,
+            0xFD => MapperKind::Tama5,
+            0xFE => MapperKind::Huc3,
+            0xFF => MapperKind::Huc1,
+            _ => MapperKind::Unsupported,
+        }
+    }
+}
+
+//pick the mapper from the rom contents first, since multicarts and unlicensed carts
+//don't tell the truth in the header, then from the cartridge type byte at 0x147
+//li cheng, bbd, hitek and nt carts have nothing that sets them apart and need the override
+pub fn detect(rom: &[u8], c_type: u8) -> MapperKind {
+    if mmm01_menu_ram_size(rom).is_some() {
+        return MapperKind::Mmm01;
+    }
+    if rom.get(TITLE_START..TITLE_START + M161_TITLE.len()) == Some(M161_TITLE) {
+        return MapperKind::M161;
+    }
+    if is_sachen(rom) {
+        //only the mmc2 made it into color carts
+        return match CgbSupport::from_byte(rom[CGB_FLAG]) {
+            CgbSupport::None => MapperKind::SachenMmc1,
+            _ => MapperKind::SachenMmc2,
+        };
+    }
+    let kind = MapperKind::from_c_type(c_type);
+    if kind == MapperKind::RomOnly && rom.len() > 2 * ROM_BANK_SIZE && is_wisdom_tree(rom) {
+        return MapperKind::WisdomTree;
+    }
+    if kind == MapperKind::Mbc1 && is_mbc1_multicart(rom) {
+        return MapperKind::Mbc1Multicart;
+    }
+    kind
+}
+
+//the logo is only where the boot rom expects it once unscrambled from the upper half of the page
+fn is_sachen(rom: &[u8]) -> bool {
+    if rom.len() < 0x200 || rom[LOGO_START..LOGO_START + NINTENDO_LOGO.len()] == NINTENDO_LOGO {
+        return false;
+    }
+    (0..NINTENDO_LOGO.len()).all(|i| {
+        let address = unlicensed::unscramble_sachen((LOGO_START + i) as u16 | 0x80);
+        rom[address as usize] == NINTENDO_LOGO[i]
+    })
+}
+
+fn is_wisdom_tree(rom: &[u8]) -> bool {
+    let bank_0 = &rom[..ROM_BANK_SIZE];
+    WISDOM_TREE_SIGNATURES.iter().any(|signature| {
+        bank_0
+            .windows(signature.len())
+            .any(|window| window == *signature)
+    })
+}
+
+fn is_mbc1_multicart(rom: &[u8]) -> bool {
+    if rom.len() != MBC1_MULTICART_SIZE {
+        return false;
+    }
+    //the menu and at least one more game
+    let games = (0..rom.len())
+        .step_by(MBC1_MULTICART_GAME_SIZE)
+        .filter(|game| {
+            let logo = game + LOGO_START;
+            rom[logo..logo + NINTENDO_LOGO.len()] == NINTENDO_LOGO
+        })
+        .count();
+    games > 1
+}
+
+//build the mapper picked by detect or by the user
+pub fn new(rom: Vec<u8>, kind: MapperKind, ram_size: RamSize) -> Box<dyn Mapper> {
+    let ram_size = match kind {
+        MapperKind::Mmm01 => mmm01_menu_ram_size(&rom).unwrap_or(ram_size),
+        _ => ram_size,
+    };
+    let ram = vec![0; ram_size.bytes()];
+    match kind {
+        //unsupported carts at least get their first 32KB
+        MapperKind::RomOnly | MapperKind::Unsupported => Box::new(RomOnly {
+            rom,
+            ram,
+            dirty: false,
+        }),
+        MapperKind::Mbc1 => Box::new(Mbc1::new(rom, ram)),
+        MapperKind::Mbc1Multicart => Box::new(Mbc1::new_multicart(rom, ram)),
+        MapperKind::Mbc3 => Box::new(Mbc3::new(rom, ram, false)),
+        MapperKind::Mbc3Rtc => Box::new(Mbc3::new(rom, ram, true)),
+        MapperKind::Mbc5 => Box::new(Mbc5::new(rom, ram, false)),
+        MapperKind::Mbc5Rumble => Box::new(Mbc5::new(rom, ram, true)),
+        MapperKind::Mbc6 => Box::new(Mbc6::new(rom)),
+        MapperKind::Mbc7 => Box::new(Mbc7::new(rom)),
+        MapperKind::Mmm01 => Box::new(Mmm01::new(rom, ram)),
+        MapperKind::Tama5 => Box::new(Tama5::new(rom)),
+        MapperKind::Huc1 => Box::new(Huc1::new(rom, ram)),
+        MapperKind::Huc3 => Box::new(Huc3::new(rom, ram)),
+        MapperKind::M161 => Box::new(M161::new(rom)),
+        MapperKind::WisdomTree => Box::new(WisdomTree::new(rom)),
+        MapperKind::SachenMmc1 => Box::new(Sachen::new(rom, false)),
+        MapperKind::SachenMmc2 => Box::new(Sachen::new(rom, true)),
+        MapperKind::LiCheng => Box::new(LiCheng::new(rom, ram)),
+        MapperKind::Bbd => Box::new(BitSwappedMbc5::new_bbd(rom, ram)),
+        MapperKind::Hitek => Box::new(BitSwappedMbc5::new_hitek(rom, ram)),
+        MapperKind::NtOld => Box::new(NtOld::new(rom, ram)),
+    }
+}
+
+//ram size of the menu header when the last 32KB hold an mmm01 menu, which has a header
+//the boot rom would accept like any game
+fn mmm01_menu_ram_size(rom: &[u8]) -> Option<RamSize> {
+    if rom.len() <= MMM01_HEADER_FROM_END {
+        return None;
+    }
+    let menu = CartridgeHeader::parse(&rom[rom.len() - MMM01_HEADER_FROM_END..]).ok()?;
+    if !menu.logo_valid() || !menu.header_checksum_valid() {
+        return None;
+    }
+    match menu.cartridge_type.code {
+        0x0B..=0x0D => Some(menu.ram_size),
+        _ => None,
+    }
+}
+
+//read from rom with the 0x4000-0x7FFF window pointing to bank, out of range banks wrap around
+fn read_banked_rom(rom: &[u8], bank: usize, address: u16) -> u8 {
+    let bank = if address < ROM_BANK_0_END { 0 } else { bank };
+    read_rom_bank(rom, bank, ROM_BANK_SIZE, address)
+}
+
+//read address from the bank_size sized bank mapped at it
+fn read_rom_bank(rom: &[u8], bank: usize, bank_size: usize, address: u16) -> u8 {
+    if rom.is_empty() {
+        return 0xFF;
+    }
+    let offset = bank * bank_size + (address as usize & (bank_size - 1));
+    rom[offset % rom.len()]
+}
+
+//offset inside ram of an address in 0xA000-0xBFFF, None if there is no ram
+fn banked_ram_offset(ram: &[u8], bank: usize, address: u16) -> Option<usize> {
+    if ram.is_empty() {
+        return None;
+    }
+    let offset = bank * RAM_BANK_SIZE + (address - RAM_START) as usize;
+    Some(offset % ram.len())
+}
+
+//copy a save file into ram, ignoring what doesn't fit
+fn load_ram(ram: &mut [u8], data: &[u8]) {
+    let len = data.len().min(ram.len());
+    ram[..len].copy_from_slice(&data[..len]);
+}
+
+//seconds since the epoch, for clocks that keep running while the emulator is closed
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0)
+}
+
+//infrared port read, nothing is seen without a peer
+fn read_ir(peer: &Option<Box<dyn InfraredPeer>>) -> u8 {
+    match peer {
+        Some(peer) if peer.light_detected() => IR_READ_BASE | 1,
+        _ => IR_READ_BASE,
+    }
+}
+
+//bit 0 of the written value drives our led
+fn write_ir(peer: &mut Option<Box<dyn InfraredPeer>>, value: u8) {
+    if let Some(peer) = peer.as_mut() {
+        peer.set_led(value & 1 == 1);
+    }
+}
+
+//plain 32KB rom, with optional unbanked ram
+pub struct RomOnly {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    dirty: bool,
+}
+
+impl Mapper for RomOnly {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn read_rom(&self, address: u16) -> u8 {
+        self.rom.get(address as usize).copied().unwrap_or(0xFF)
+    }
+
+    fn write_rom(&mut self, _address: u16, _value: u8) {}
+
+    fn read_ram(&self, address: u16) -> u8 {
+        match banked_ram_offset(&self.ram, 0, address) {
+            Some(offset) => self.ram[offset],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if let Some(offset) = banked_ram_offset(&self.ram, 0, address) {
+            self.ram[offset] = value;
+            self.dirty = true;
+        }
+    }
+
+    fn save_data(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //header the boot rom would accept, at offset
+    fn valid_header(rom: &mut [u8], offset: usize, c_type: u8) {
+        let header = &mut rom[offset..];
+        header[LOGO_START..LOGO_START + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        header[header::C_TYPE] = c_type;
+        header[header::HEADER_CHECKSUM] = header::header_checksum(header);
+    }
+
+    #[test]
+    fn infrared_loopback_sees_its_own_led() {
+        let mut peer: Option<Box<dyn InfraredPeer>> = Some(Box::new(InfraredLoopback::default()));
+        assert_eq!(read_ir(&peer), IR_READ_BASE);
+        write_ir(&mut peer, 1);
+        assert_eq!(read_ir(&peer), IR_READ_BASE | 1);
+        write_ir(&mut peer, 0);
+        assert_eq!(read_ir(&peer), IR_READ_BASE);
+    }
+
+    #[test]
+    fn mmm01_menu_in_the_last_32kb() {
+        let mut rom = vec![0; 0x20000];
+        valid_header(&mut rom, 0, 0x01);
+        rom[0x18000 + header::RAM_SIZE] = 0x03;
+        valid_header(&mut rom, 0x18000, 0x0D);
+        assert_eq!(detect(&rom, 0x01), MapperKind::Mmm01);
+        assert_eq!(mmm01_menu_ram_size(&rom), Some(RamSize::from_byte(0x03)));
+    }
+
+    #[test]
+    fn mmm01_needs_a_valid_menu_header() {
+        //just the cartridge type byte, as plenty of ordinary roms have there
+        let mut rom = vec![0; 0x20000];
+        valid_header(&mut rom, 0, 0x01);
+        rom[0x18000 + header::C_TYPE] = 0x0B;
+        assert_eq!(detect(&rom, 0x01), MapperKind::Mbc1);
+        //logo but a bad checksum
+        valid_header(&mut rom, 0x18000, 0x0B);
+        rom[0x18000 + header::HEADER_CHECKSUM] ^= 0xFF;
+        assert_eq!(detect(&rom, 0x01), MapperKind::Mbc1);
+    }
+
+    #[test]
+    fn m161_from_the_title() {
+        let mut rom = vec![0; 0x40000];
+        valid_header(&mut rom, 0, 0x10);
+        rom[TITLE_START..TITLE_START + M161_TITLE.len()].copy_from_slice(M161_TITLE);
+        assert_eq!(detect(&rom, 0x10), MapperKind::M161);
+    }
+
+    #[test]
+    fn wisdom_tree_from_its_signature() {
+        let mut rom = vec![0; 0x20000];
+        rom[0x200..0x200 + 11].copy_from_slice(b"WISDOM TREE");
+        assert_eq!(detect(&rom, 0x00), MapperKind::WisdomTree);
+        //32KB carts can't bank
+        assert_eq!(detect(&rom[..0x8000], 0x00), MapperKind::RomOnly);
+    }
+
+    #[test]
+    fn sachen_from_the_scrambled_logo() {
+        let mut rom = vec![0; 0x8000];
+        for (i, &byte) in NINTENDO_LOGO.iter().enumerate() {
+            let address = unlicensed::unscramble_sachen((LOGO_START + i) as u16 | 0x80);
+            rom[address as usize] = byte;
+        }
+        assert_eq!(detect(&rom, 0x00), MapperKind::SachenMmc1);
+        rom[CGB_FLAG] = 0x80;
+        assert_eq!(detect(&rom, 0x00), MapperKind::SachenMmc2);
+    }
+
+    #[test]
+    fn mbc1_multicart_from_the_game_headers() {
+        let mut rom = vec![0; MBC1_MULTICART_SIZE];
+        valid_header(&mut rom, 0, 0x01);
+        assert_eq!(detect(&rom, 0x01), MapperKind::Mbc1);
+        valid_header(&mut rom, MBC1_MULTICART_GAME_SIZE, 0x01);
+        assert_eq!(detect(&rom, 0x01), MapperKind::Mbc1Multicart);
+    }
+
+    #[test]
+    fn mapper_names() {
+        for kind in MapperKind::ALL {
+            assert_eq!(MapperKind::from_name(kind.name()), Some(kind));
+        }
+        assert_eq!(MapperKind::from_name("MBC5"), Some(MapperKind::Mbc5));
+        assert_eq!(MapperKind::from_name("mbc4"), None);
+    }
+
+    #[test]
+    fn cartridge_type_byte() {
+        let mut rom = vec![0; 0x8000];
+        valid_header(&mut rom, 0, 0x1B);
+        assert_eq!(detect(&rom, 0x1B), MapperKind::Mbc5);
+        assert_eq!(detect(&rom, 0x00), MapperKind::RomOnly);
+        assert_eq!(detect(&rom, 0xFE), MapperKind::Huc3);
+        //mbc2, the pocket camera and garbage aren't passed off as rom only
+        for c_type in [0x05, 0x06, 0xFC, 0x42] {
+            assert_eq!(detect(&rom, c_type), MapperKind::Unsupported);
+        }
+        assert_eq!(detect(&rom, 0x09), MapperKind::RomOnly);
+    }
+}
@@ -0,0 +1,710 @@
+use std::fmt;
+
+use super::{MapperKind, NINTENDO_LOGO, RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+pub(crate) const LOGO_START: usize = 0x104;
+pub(crate) const TITLE_START: usize = 0x134;
+//color carts give the last bytes of the title to the manufacturer code and the cgb flag
+const TITLE_END: usize = 0x144;
+const CGB_TITLE_END: usize = 0x13F;
+pub(crate) const CGB_FLAG: usize = 0x143;
+const NEW_LICENSEE: usize = 0x144;
+const SGB_FLAG: usize = 0x146;
+pub(crate) const C_TYPE: usize = 0x147;
+const ROM_SIZE: usize = 0x148;
+pub(crate) const RAM_SIZE: usize = 0x149;
+const DESTINATION: usize = 0x14A;
+pub(crate) const OLD_LICENSEE: usize = 0x14B;
+const MASK_ROM_VERSION: usize = 0x14C;
+pub(crate) const HEADER_CHECKSUM: usize = 0x14D;
+const GLOBAL_CHECKSUM: usize = 0x14E;
+pub const HEADER_END: usize = 0x150;
+
+//old licensee value telling to look at the new licensee code instead
+const USE_NEW_LICENSEE: u8 = 0x33;
+const SGB_SUPPORTED: u8 = 0x03;
+const CGB_ENHANCED: u8 = 0b10000000;
+const CGB_ONLY: u8 = 0b01000000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeaderError {
+    //the file ends before the header does
+    TooShort { len: usize },
+    BadLogo,
+    HeaderChecksum { expected: u8, computed: u8 },
+    GlobalChecksum { expected: u16, computed: u16 },
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeaderError::TooShort { len } => write!(
+                f,
+                "rom is {} bytes, too short to hold a header ({} bytes)",
+                len, HEADER_END
+            ),
+            HeaderError::BadLogo => write!(f, "nintendo logo doesn't match"),
+            HeaderError::HeaderChecksum { expected, computed } => write!(
+                f,
+                "header checksum is {:02X} but the header adds up to {:02X}",
+                expected, computed
+            ),
+            HeaderError::GlobalChecksum { expected, computed } => write!(
+                f,
+                "global checksum is {:04X} but the rom adds up to {:04X}",
+                expected, computed
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HeaderError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CgbSupport {
+    //plain dmg cart, 0x143 is part of the title
+    None,
+    //runs on both, with color on cgb
+    Enhanced,
+    //refuses to run on dmg
+    Only,
+}
+
+impl CgbSupport {
+    //the cgb boot rom only looks at bit 7, bit 6 on top marks games that need color
+    pub fn from_byte(byte: u8) -> CgbSupport {
+        match (byte & CGB_ENHANCED != 0, byte & CGB_ONLY != 0) {
+            (false, _) => CgbSupport::None,
+            (true, false) => CgbSupport::Enhanced,
+            (true, true) => CgbSupport::Only,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RomSize {
+    Kib32,
+    Kib64,
+    Kib128,
+    Kib256,
+    Kib512,
+    Mib1,
+    Mib2,
+    Mib4,
+    Mib8,
+    //only found in the docs, no known cart uses them
+    Mib1_1,
+    Mib1_2,
+    Mib1_5,
+    Unknown(u8),
+}
+
+impl RomSize {
+    pub fn from_byte(value: u8) -> RomSize {
+        match value {
+            0x00 => RomSize::Kib32,
+            0x01 => RomSize::Kib64,
+            0x02 => RomSize::Kib128,
+            0x03 => RomSize::Kib256,
+            0x04 => RomSize::Kib512,
+            0x05 => RomSize::Mib1,
+            0x06 => RomSize::Mib2,
+            0x07 => RomSize::Mib4,
+            0x08 => RomSize::Mib8,
+            0x52 => RomSize::Mib1_1,
+            0x53 => RomSize::Mib1_2,
+            0x54 => RomSize::Mib1_5,
+            _ => RomSize::Unknown(value),
+        }
+    }
+
+    pub fn banks(&self) -> Option<usize> {
+        Some(match self {
+            RomSize::Kib32 => 2,
+            RomSize::Kib64 => 4,
+            RomSize::Kib128 => 8,
+            RomSize::Kib256 => 16,
+            RomSize::Kib512 => 32,
+            RomSize::Mib1 => 64,
+            RomSize::Mib2 => 128,
+            RomSize::Mib4 => 256,
+            RomSize::Mib8 => 512,
+            RomSize::Mib1_1 => 72,
+            RomSize::Mib1_2 => 80,
+            RomSize::Mib1_5 => 96,
+            RomSize::Unknown(_) => return None,
+        })
+    }
+
+    pub fn bytes(&self) -> Option<usize> {
+        self.banks().map(|banks| banks * ROM_BANK_SIZE)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RamSize {
+    None,
+    //only used by homebrew and some docs
+    Kib2,
+    Kib8,
+    Kib32,
+    Kib128,
+    Kib64,
+    Unknown(u8),
+}
+
+impl RamSize {
+    pub fn from_byte(value: u8) -> RamSize {
+        match value {
+            0x00 => RamSize::None,
+            0x01 => RamSize::Kib2,
+            0x02 => RamSize::Kib8,
+            0x03 => RamSize::Kib32,
+            0x04 => RamSize::Kib128,
+            0x05 => RamSize::Kib64,
+            _ => RamSize::Unknown(value),
+        }
+    }
+
+    pub fn bytes(&self) -> usize {
+        match self {
+            RamSize::Kib2 => 0x800,
+            RamSize::Kib8 => RAM_BANK_SIZE,
+            RamSize::Kib32 => RAM_BANK_SIZE * 4,
+            RamSize::Kib128 => RAM_BANK_SIZE * 16,
+            RamSize::Kib64 => RAM_BANK_SIZE * 8,
+            RamSize::None | RamSize::Unknown(_) => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Destination {
+    Japan,
+    Overseas,
+    Unknown(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Licensee {
+    //single byte code at 0x14B
+    Old(u8),
+    //two ascii characters at 0x144-0x145, used when the old code is 0x33
+    New([u8; 2]),
+}
+
+impl Licensee {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Licensee::Old(code) => old_licensee_name(*code),
+            Licensee::New(code) => new_licensee_name(code),
+        }
+    }
+}
+
+//cartridge type byte at 0x147 split into the mapper and what else is on the board
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CartridgeType {
+    pub code: u8,
+    pub mapper: MapperKind,
+    pub ram: bool,
+    pub battery: bool,
+    pub timer: bool,
+    pub rumble: bool,
+}
+
+impl CartridgeType {
+    pub fn from_byte(code: u8) -> CartridgeType {
+        CartridgeType {
+            code,
+            mapper: MapperKind::from_c_type(code),
+            ram: matches!(
+                code,
+                0x02 | 0x03
+                    | 0x08
+                    | 0x09
+                    | 0x0C
+                    | 0x0D
+                    | 0x10
+                    | 0x12
+                    | 0x13
+                    | 0x1A
+                    | 0x1B
+                    | 0x1D
+                    | 0x1E
+                    | 0x20
+                    | 0x22
+                    | 0xFE
+                    | 0xFF
+            ),
+            battery: matches!(
+                code,
+                0x03 | 0x06
+                    | 0x09
+                    | 0x0D
+                    | 0x0F
+                    | 0x10
+                    | 0x13
+                    | 0x1B
+                    | 0x1E
+                    | 0x20
+                    | 0x22
+                    | 0xFD
+                    | 0xFE
+                    | 0xFF
+            ),
+            timer: matches!(code, 0x0F | 0x10 | 0xFE),
+            rumble: matches!(code, 0x1C..=0x1E | 0x22),
+        }
+    }
+}
+
+pub struct CartridgeHeader {
+    pub title: String,
+    pub cgb: CgbSupport,
+    pub sgb: bool,
+    pub licensee: Licensee,
+    pub cartridge_type: CartridgeType,
+    pub rom_size: RomSize,
+    pub ram_size: RamSize,
+    pub destination: Destination,
+    pub mask_rom_version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    //the logo the boot rom shows, and the raw title bytes the cgb boot rom sums up
+    logo: [u8; NINTENDO_LOGO.len()],
+    title_bytes: [u8; TITLE_END - TITLE_START],
+    computed_header_checksum: u8,
+    computed_global_checksum: u16,
+}
+
+impl CartridgeHeader {
+    pub fn parse(rom: &[u8]) -> Result<CartridgeHeader, HeaderError> {
+        if rom.len() < HEADER_END {
+            return Err(HeaderError::TooShort { len: rom.len() });
+        }
+
+        let cgb = CgbSupport::from_byte(rom[CGB_FLAG]);
+        let title_end = match cgb {
+            CgbSupport::None => TITLE_END,
+            _ => CGB_TITLE_END,
+        };
+        let title = rom[TITLE_START..title_end]
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as char)
+            .collect::<String>();
+
+        let licensee = match rom[OLD_LICENSEE] {
+            USE_NEW_LICENSEE => Licensee::New([rom[NEW_LICENSEE], rom[NEW_LICENSEE + 1]]),
+            code => Licensee::Old(code),
+        };
+        let destination = match rom[DESTINATION] {
+            0x00 => Destination::Japan,
+            0x01 => Destination::Overseas,
+            value => Destination::Unknown(value),
+        };
+
+        Ok(CartridgeHeader {
+            title,
+            cgb,
+            //the sgb ignores its flag on carts still using the old licensee code
+            sgb: rom[SGB_FLAG] == SGB_SUPPORTED && rom[OLD_LICENSEE] == USE_NEW_LICENSEE,
+            licensee,
+            cartridge_type: CartridgeType::from_byte(rom[C_TYPE]),
+            rom_size: RomSize::from_byte(rom[ROM_SIZE]),
+            ram_size: RamSize::from_byte(rom[RAM_SIZE]),
+            destination,
+            mask_rom_version: rom[MASK_ROM_VERSION],
+            header_checksum: rom[HEADER_CHECKSUM],
+            global_checksum: u16::from_be_bytes([rom[GLOBAL_CHECKSUM], rom[GLOBAL_CHECKSUM + 1]]),
+            logo: rom[LOGO_START..LOGO_START + NINTENDO_LOGO.len()]
+                .try_into()
+                .unwrap(),
+            title_bytes: rom[TITLE_START..TITLE_END].try_into().unwrap(),
+            computed_header_checksum: header_checksum(rom),
+            computed_global_checksum: global_checksum(rom),
+        })
+    }
+
+    //the boot rom locks up unless this holds
+    pub fn logo_valid(&self) -> bool {
+        self.logo == NINTENDO_LOGO
+    }
+
+    pub fn logo(&self) -> &[u8; NINTENDO_LOGO.len()] {
+        &self.logo
+    }
+
+    //all 16 title bytes added up, cgb flag included, whatever the title itself ends up as
+    pub fn title_checksum(&self) -> u8 {
+        self.title_bytes
+            .iter()
+            .fold(0, |sum, &byte| sum.wrapping_add(byte))
+    }
+
+    //the boot rom locks up unless this holds
+    pub fn header_checksum_valid(&self) -> bool {
+        self.header_checksum == self.computed_header_checksum
+    }
+
+    //nothing checks this one on hardware
+    pub fn global_checksum_valid(&self) -> bool {
+        self.global_checksum == self.computed_global_checksum
+    }
+
+    //first problem found, in the order the boot rom would notice them
+    pub fn verify(&self) -> Result<(), HeaderError> {
+        if !self.logo_valid() {
+            return Err(HeaderError::BadLogo);
+        }
+        if !self.header_checksum_valid() {
+            return Err(HeaderError::HeaderChecksum {
+                expected: self.header_checksum,
+                computed: self.computed_header_checksum,
+            });
+        }
+        if !self.global_checksum_valid() {
+            return Err(HeaderError::GlobalChecksum {
+                expected: self.global_checksum,
+                computed: self.computed_global_checksum,
+            });
+        }
+        Ok(())
+    }
+}
+
+//what the boot rom computes over 0x134-0x14C
+pub fn header_checksum(rom: &[u8]) -> u8 {
+    rom[TITLE_START..HEADER_CHECKSUM]
+        .iter()
+        .fold(0u8, |x, &byte| x.wrapping_sub(byte).wrapping_sub(1))
+}
+
+//sum of every byte of the rom except the checksum itself
+pub fn global_checksum(rom: &[u8]) -> u16 {
+    rom.iter()
+        .enumerate()
+        .filter(|(i, _)| *i != GLOBAL_CHECKSUM && *i != GLOBAL_CHECKSUM + 1)
+        .fold(0u16, |sum, (_, &byte)| sum.wrapping_add(byte as u16))
+}
+
+fn new_licensee_name(code: &[u8; 2]) -> &'static str {
+    match code {
+        b"00" => "None",
+        b"01" => "Nintendo R&D1",
+        b"08" => "Capcom",
+        b"13" => "Electronic Arts",
+        b"18" => "Hudson Soft",
+        b"19" => "b-ai",
+        b"20" => "kss",
+        b"22" => "pow",
+        b"24" => "PCM Complete",
+        b"25" => "san-x",
+        b"28" => "Kemco Japan",
+        b"29" => "seta",
+        b"30" => "Viacom",
+        b"31" => "Nintendo",
+        b"32" => "Bandai",
+        b"33" => "Ocean/Acclaim",
+        b"34" => "Konami",
+        b"35" => "Hector",
+        b"37" => "Taito",
+        b"38" => "Hudson",
+        b"39" => "Banpresto",
+        b"41" => "Ubi Soft",
+        b"42" => "Atlus",
+        b"44" => "Malibu",
+        b"46" => "angel",
+        b"47" => "Bullet-Proof",
+        b"49" => "irem",
+        b"50" => "Absolute",
+        b"51" => "Acclaim",
+        b"52" => "Activision",
+        b"53" => "American sammy",
+        b"54" => "Konami",
+        b"55" => "Hi tech entertainment",
+        b"56" => "LJN",
+        b"57" => "Matchbox",
+        b"58" => "Mattel",
+        b"59" => "Milton Bradley",
+        b"60" => "Titus",
+        b"61" => "Virgin",
+        b"64" => "LucasArts",
+        b"67" => "Ocean",
+        b"69" => "Electronic Arts",
+        b"70" => "Infogrames",
+        b"71" => "Interplay",
+        b"72" => "Broderbund",
+        b"73" => "sculptured",
+        b"75" => "sci",
+        b"78" => "THQ",
+        b"79" => "Accolade",
+        b"80" => "misawa",
+        b"83" => "lozc",
+        b"86" => "Tokuma Shoten Intermedia",
+        b"87" => "Tsukuda Original",
+        b"91" => "Chunsoft",
+        b"92" => "Video system",
+        b"93" => "Ocean/Acclaim",
+        b"95" => "Varie",
+        b"96" => "Yonezawa/s'pal",
+        b"97" => "Kaneko",
+        b"99" => "Pack in soft",
+        b"A4" => "Konami (Yu-Gi-Oh!)",
+        _ => "Unknown",
+    }
+}
+
+fn old_licensee_name(code: u8) -> &'static str {
+    match code {
+        0x00 => "None",
+        0x01 | 0x31 => "Nintendo",
+        0x08 | 0x38 => "Capcom",
+        0x09 => "Hot-B",
+        0x0A | 0xE0 => "Jaleco",
+        0x0B => "Coconuts Japan",
+        0x0C | 0x6E => "Elite Systems",
+        0x13 | 0x69 => "Electronic Arts",
+        0x18 => "Hudson Soft",
+        0x19 => "ITC Entertainment",
+        0x1A => "Yanoman",
+        0x1D => "Japan Clary",
+        0x1F | 0x4A | 0x61 => "Virgin Interactive",
+        0x24 => "PCM Complete",
+        0x25 => "San-X",
+        0x28 => "Kotobuki Systems",
+        0x29 => "Seta",
+        0x30 | 0x70 => "Infogrames",
+        0x32 | 0xA2 | 0xB2 => "Bandai",
+        0x34 | 0xA4 => "Konami",
+        0x35 => "HectorSoft",
+        0x39 | 0x9D | 0xD9 => "Banpresto",
+        0x3C => "Entertainment i",
+        0x3E => "Gremlin",
+        0x41 => "Ubi Soft",
+        0x42 | 0xEB => "Atlus",
+        0x44 | 0x4D => "Malibu",
+        0x46 | 0xCF => "Angel",
+        0x47 => "Spectrum Holoby",
+        0x49 => "Irem",
+        0x4F => "U.S. Gold",
+        0x50 => "Absolute",
+        0x51 | 0xB0 => "Acclaim",
+        0x52 => "Activision",
+        0x53 => "American Sammy",
+        0x54 => "GameTek",
+        0x55 => "Park Place",
+        0x56 | 0xDB | 0xFF => "LJN",
+        0x57 => "Matchbox",
+        0x59 => "Milton Bradley",
+        0x5A => "Mindscape",
+        0x5B => "Romstar",
+        0x5C | 0xD6 => "Naxat Soft",
+        0x5D => "Tradewest",
+        0x60 => "Titus",
+        0x67 => "Ocean",
+        0x6F => "Electro Brain",
+        0x71 => "Interplay",
+        0x72 | 0xAA => "Broderbund",
+        0x73 => "Sculptered Soft",
+        0x75 => "The Sales Curve",
+        0x78 => "THQ",
+        0x79 => "Accolade",
+        0x7A => "Triffix Entertainment",
+        0x7C => "Microprose",
+        0x7F | 0xC2 => "Kemco",
+        0x80 => "Misawa Entertainment",
+        0x83 => "Lozc",
+        0x86 | 0xC4 => "Tokuma Shoten Intermedia",
+        0x8B => "Bullet-Proof Software",
+        0x8C => "Vic Tokai",
+        0x8E => "Ape",
+        0x8F => "I'Max",
+        0x91 => "Chunsoft",
+        0x92 => "Video System",
+        0x93 => "Tsubaraya Productions",
+        0x95 | 0xE3 => "Varie",
+        0x96 => "Yonezawa/S'Pal",
+        0x97 => "Kaneko",
+        0x99 => "Arc",
+        0x9A => "Nihon Bussan",
+        0x9B => "Tecmo",
+        0x9C => "Imagineer",
+        0x9F => "Nova",
+        0xA1 => "Hori Electric",
+        0xA6 => "Kawada",
+        0xA7 => "Takara",
+        0xA9 => "Technos Japan",
+        0xAC => "Toei Animation",
+        0xAD => "Toho",
+        0xAF => "Namco",
+        0xB1 => "ASCII or Nexsoft",
+        0xB4 => "Square Enix",
+        0xB6 => "HAL Laboratory",
+        0xB7 => "SNK",
+        0xB9 | 0xCE => "Pony Canyon",
+        0xBA => "Culture Brain",
+        0xBB => "Sunsoft",
+        0xBD => "Sony Imagesoft",
+        0xBF => "Sammy",
+        0xC0 | 0xD0 => "Taito",
+        0xC3 => "Squaresoft",
+        0xC5 => "Data East",
+        0xC6 => "Tonkinhouse",
+        0xC8 => "Koei",
+        0xC9 => "UFL",
+        0xCA => "Ultra",
+        0xCB => "Vap",
+        0xCC => "Use Corporation",
+        0xCD => "Meldac",
+        0xD1 => "Sofel",
+        0xD2 => "Quest",
+        0xD3 => "Sigma Enterprises",
+        0xD4 => "ASK Kodansha",
+        0xD7 => "Copya System",
+        0xDA => "Tomy",
+        0xDD => "NCS",
+        0xDE => "Human",
+        0xDF => "Altron",
+        0xE1 => "Towa Chiki",
+        0xE2 => "Yutaka",
+        0xE5 => "Epoch",
+        0xE7 => "Athena",
+        0xE8 => "Asmik ACE Entertainment",
+        0xE9 => "Natsume",
+        0xEA => "King Records",
+        0xEC => "Epic/Sony Records",
+        0xEE => "IGS",
+        0xF0 => "A Wave",
+        0xF3 => "Extreme Entertainment",
+        _ => "Unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //32KB rom with a header the boot rom accepts and a correct global checksum
+    fn rom(title: &[u8], c_type: u8) -> Vec<u8> {
+        let mut rom = vec![0; 2 * ROM_BANK_SIZE];
+        rom[LOGO_START..LOGO_START + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        rom[TITLE_START..TITLE_START + title.len()].copy_from_slice(title);
+        rom[C_TYPE] = c_type;
+        fix_checksums(&mut rom);
+        rom
+    }
+
+    fn fix_checksums(rom: &mut [u8]) {
+        rom[HEADER_CHECKSUM] = header_checksum(rom);
+        let global = global_checksum(rom);
+        rom[GLOBAL_CHECKSUM..GLOBAL_CHECKSUM + 2].copy_from_slice(&global.to_be_bytes());
+    }
+
+    #[test]
+    fn parse_fields() {
+        let mut rom = rom(b"TETRIS", 0x13);
+        rom[ROM_SIZE] = 0x05;
+        rom[RAM_SIZE] = 0x03;
+        rom[DESTINATION] = 0x01;
+        rom[OLD_LICENSEE] = 0x01;
+        rom[MASK_ROM_VERSION] = 0x02;
+        fix_checksums(&mut rom);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.title, "TETRIS");
+        assert_eq!(header.cgb, CgbSupport::None);
+        assert!(!header.sgb);
+        assert_eq!(header.licensee, Licensee::Old(0x01));
+        assert_eq!(header.licensee.name(), "Nintendo");
+        assert_eq!(header.cartridge_type.mapper, MapperKind::Mbc3);
+        assert!(header.cartridge_type.ram && header.cartridge_type.battery);
+        assert!(!header.cartridge_type.timer);
+        assert_eq!(header.rom_size, RomSize::Mib1);
+        assert_eq!(header.rom_size.bytes(), Some(0x100000));
+        assert_eq!(header.ram_size, RamSize::Kib32);
+        assert_eq!(header.ram_size.bytes(), 0x8000);
+        assert_eq!(header.destination, Destination::Overseas);
+        assert_eq!(header.mask_rom_version, 0x02);
+        assert_eq!(header.verify(), Ok(()));
+    }
+
+    #[test]
+    fn cgb_titles_are_shorter() {
+        let mut rom = rom(b"POKEMON CRYSTALX", 0x10);
+        rom[CGB_FLAG] = 0xC0;
+        rom[OLD_LICENSEE] = USE_NEW_LICENSEE;
+        rom[NEW_LICENSEE..NEW_LICENSEE + 2].copy_from_slice(b"01");
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.title, "POKEMON CRY");
+        assert_eq!(header.cgb, CgbSupport::Only);
+        assert_eq!(header.licensee, Licensee::New(*b"01"));
+        assert_eq!(header.licensee.name(), "Nintendo R&D1");
+        assert!(header.cartridge_type.timer);
+    }
+
+    #[test]
+    fn sgb_flag() {
+        let mut rom = rom(b"SGB", 0x00);
+        rom[SGB_FLAG] = SGB_SUPPORTED;
+        rom[OLD_LICENSEE] = USE_NEW_LICENSEE;
+        assert!(CartridgeHeader::parse(&rom).unwrap().sgb);
+        rom[OLD_LICENSEE] = 0x01;
+        assert!(!CartridgeHeader::parse(&rom).unwrap().sgb);
+    }
+
+    #[test]
+    fn cgb_flag_bits() {
+        assert_eq!(CgbSupport::from_byte(0x00), CgbSupport::None);
+        assert_eq!(CgbSupport::from_byte(0x45), CgbSupport::None);
+        assert_eq!(CgbSupport::from_byte(0x80), CgbSupport::Enhanced);
+        assert_eq!(CgbSupport::from_byte(0x88), CgbSupport::Enhanced);
+        assert_eq!(CgbSupport::from_byte(0xC0), CgbSupport::Only);
+        assert_eq!(CgbSupport::from_byte(0xC4), CgbSupport::Only);
+    }
+
+    #[test]
+    fn too_short() {
+        assert_eq!(
+            CartridgeHeader::parse(&[0; 0x100]).err(),
+            Some(HeaderError::TooShort { len: 0x100 })
+        );
+    }
+
+    #[test]
+    fn verify_in_boot_rom_order() {
+        let mut rom = rom(b"GAME", 0x00);
+        rom[GLOBAL_CHECKSUM] ^= 0xFF;
+        assert!(matches!(
+            CartridgeHeader::parse(&rom).unwrap().verify(),
+            Err(HeaderError::GlobalChecksum { .. })
+        ));
+        rom[HEADER_CHECKSUM] ^= 0xFF;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(
+            header.verify(),
+            Err(HeaderError::HeaderChecksum {
+                expected: rom[HEADER_CHECKSUM],
+                computed: header_checksum(&rom),
+            })
+        );
+        assert!(!header.header_checksum_valid());
+        rom[LOGO_START] = 0;
+        assert_eq!(
+            CartridgeHeader::parse(&rom).unwrap().verify(),
+            Err(HeaderError::BadLogo)
+        );
+    }
+
+    #[test]
+    fn sizes_from_bytes() {
+        assert_eq!(RomSize::from_byte(0x00).banks(), Some(2));
+        assert_eq!(RomSize::from_byte(0x08).banks(), Some(512));
+        assert_eq!(RomSize::from_byte(0x52).banks(), Some(72));
+        assert_eq!(RomSize::from_byte(0x09), RomSize::Unknown(0x09));
+        assert_eq!(RomSize::Unknown(0x09).bytes(), None);
+        assert_eq!(RamSize::from_byte(0x00).bytes(), 0);
+        assert_eq!(RamSize::from_byte(0x05).bytes(), 0x10000);
+        assert_eq!(RamSize::from_byte(0x06).bytes(), 0);
+    }
+}
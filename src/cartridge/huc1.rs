@@ -0,0 +1,161 @@
+use super::{
+    banked_ram_offset, load_ram, read_banked_rom, read_ir, write_ir, InfraredPeer, Mapper,
+};
+
+const BANK_MASK: u8 = 0b00111111;
+const RAM_BANK_MASK: u8 = 0b00000011;
+const IR_MODE_VALUE: u8 = 0x0E;
+
+pub struct Huc1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rom_bank: u8,
+    ram_bank: u8,
+    //0xA000-0xBFFF is either ram or the infrared port
+    ir_mode: bool,
+    dirty: bool,
+    ir_peer: Option<Box<dyn InfraredPeer>>,
+}
+
+impl Huc1 {
+    pub fn new(rom: Vec<u8>, ram: Vec<u8>) -> Huc1 {
+        Huc1 {
+            rom,
+            ram,
+            rom_bank: 1,
+            ram_bank: 0,
+            ir_mode: false,
+            dirty: false,
+            ir_peer: None,
+        }
+    }
+}
+
+impl Mapper for Huc1 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn read_rom(&self, address: u16) -> u8 {
+        read_banked_rom(&self.rom, self.rom_bank as usize, address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ir_mode = value & 0x0F == IR_MODE_VALUE,
+            0x2000..=0x3FFF => self.rom_bank = (value & BANK_MASK).max(1),
+            0x4000..=0x5FFF => self.ram_bank = value & RAM_BANK_MASK,
+            _ => (),
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if self.ir_mode {
+            return read_ir(&self.ir_peer);
+        }
+        match banked_ram_offset(&self.ram, self.ram_bank as usize, address) {
+            Some(offset) => self.ram[offset],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if self.ir_mode {
+            write_ir(&mut self.ir_peer, value);
+            return;
+        }
+        if let Some(offset) = banked_ram_offset(&self.ram, self.ram_bank as usize, address) {
+            self.ram[offset] = value;
+            self.dirty = true;
+        }
+    }
+
+    fn save_data(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    fn set_infrared_peer(&mut self, peer: Box<dyn InfraredPeer>) {
+        self.ir_peer = Some(peer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{RAM_BANK_SIZE, ROM_BANK_SIZE};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    //every bank starts with its own number
+    fn rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * ROM_BANK_SIZE];
+        for bank in 0..banks {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        rom
+    }
+
+    struct Peer {
+        led: Rc<Cell<bool>>,
+        light: bool,
+    }
+
+    impl InfraredPeer for Peer {
+        fn set_led(&mut self, on: bool) {
+            self.led.set(on);
+        }
+
+        fn light_detected(&self) -> bool {
+            self.light
+        }
+    }
+
+    #[test]
+    fn rom_bank_switching() {
+        let mut huc1 = Huc1::new(rom(64), Vec::new());
+        assert_eq!(huc1.read_rom(0x4000), 1);
+        huc1.write_rom(0x2000, 0x3F);
+        assert_eq!(huc1.read_rom(0x4000), 0x3F);
+        //6 bits, and bank 0 reads bank 1
+        huc1.write_rom(0x2000, 0x40);
+        assert_eq!(huc1.read_rom(0x4000), 1);
+        assert_eq!(huc1.read_rom(0x0000), 0);
+    }
+
+    #[test]
+    fn ram_bank_switching() {
+        let mut huc1 = Huc1::new(rom(2), vec![0; 4 * RAM_BANK_SIZE]);
+        huc1.write_rom(0x4000, 3);
+        huc1.write_ram(0xA000, 0x12);
+        huc1.write_rom(0x4000, 0);
+        assert_eq!(huc1.read_ram(0xA000), 0);
+        assert_eq!(huc1.save_data()[3 * RAM_BANK_SIZE], 0x12);
+    }
+
+    #[test]
+    fn infrared_mode() {
+        let mut huc1 = Huc1::new(rom(2), vec![0; RAM_BANK_SIZE]);
+        let led = Rc::new(Cell::new(false));
+        huc1.set_infrared_peer(Box::new(Peer {
+            led: led.clone(),
+            light: true,
+        }));
+        huc1.write_rom(0x0000, IR_MODE_VALUE);
+        assert_eq!(huc1.read_ram(0xA000), 0xC1);
+        huc1.write_ram(0xA000, 1);
+        assert!(led.get());
+        //ram isn't touched while the port is mapped
+        assert_eq!(huc1.save_data()[0], 0);
+        huc1.write_rom(0x0000, 0x00);
+        huc1.write_ram(0xA000, 0x34);
+        assert_eq!(huc1.read_ram(0xA000), 0x34);
+    }
+}
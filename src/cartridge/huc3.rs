@@ -0,0 +1,361 @@
+use super::{
+    banked_ram_offset, load_ram, read_banked_rom, read_ir, unix_time, write_ir, InfraredPeer,
+    Mapper,
+};
+
+const BANK_MASK: u8 = 0b01111111;
+const RAM_BANK_MASK: u8 = 0b00000011;
+
+//what 0xA000-0xBFFF is connected to, selected through 0x0000-0x1FFF
+const MODE_RAM_READ: u8 = 0x0;
+const MODE_RAM: u8 = 0xA;
+const MODE_RTC_COMMAND: u8 = 0xB;
+const MODE_RTC_RESPONSE: u8 = 0xC;
+const MODE_RTC_SEMAPHORE: u8 = 0xD;
+const MODE_IR: u8 = 0xE;
+
+//rtc commands, the high nibble of the byte written in MODE_RTC_COMMAND
+const RTC_READ: u8 = 0x1;
+const RTC_WRITE: u8 = 0x3;
+const RTC_ADDRESS_LOW: u8 = 0x4;
+const RTC_ADDRESS_HIGH: u8 = 0x5;
+const RTC_EXTENDED: u8 = 0x6;
+
+//arguments of RTC_EXTENDED
+const RTC_EXT_LOAD_TIME: u8 = 0x0;
+const RTC_EXT_STORE_TIME: u8 = 0x1;
+const RTC_EXT_STATUS: u8 = 0x2;
+
+//nibbles in rtc memory the current time is copied to and from: 3 for minutes, 3 for days
+const RTC_TIME_NIBBLES: usize = 6;
+const RTC_MEMORY_SIZE: usize = 0x100;
+
+const CYCLES_PER_MINUTE: u64 = 4_194_304 * 60;
+const MINUTES_PER_DAY: u16 = 60 * 24;
+const DAY_MASK: u16 = 0x0FFF;
+
+//footer appended to the ram in .sav files by sameboy: the unix time it was written at as
+//64 bit, minutes and days as 16 bit, then an alarm we don't emulate, all little endian
+const RTC_FOOTER_SIZE: usize = 8 + 2 + 2 + 5;
+
+pub struct Huc3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rom_bank: u8,
+    ram_bank: u8,
+    mode: u8,
+    ir_peer: Option<Box<dyn InfraredPeer>>,
+    rtc: Rtc,
+    dirty: bool,
+}
+
+impl Huc3 {
+    pub fn new(rom: Vec<u8>, ram: Vec<u8>) -> Huc3 {
+        Huc3 {
+            rom,
+            ram,
+            rom_bank: 1,
+            ram_bank: 0,
+            mode: MODE_RAM_READ,
+            ir_peer: None,
+            rtc: Rtc::new(),
+            dirty: false,
+        }
+    }
+}
+
+impl Mapper for Huc3 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn read_rom(&self, address: u16) -> u8 {
+        read_banked_rom(&self.rom, self.rom_bank as usize, address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.mode = value & 0x0F,
+            0x2000..=0x3FFF => self.rom_bank = value & BANK_MASK,
+            0x4000..=0x5FFF => self.ram_bank = value & RAM_BANK_MASK,
+            _ => (),
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        match self.mode {
+            MODE_RAM_READ | MODE_RAM => {
+                match banked_ram_offset(&self.ram, self.ram_bank as usize, address) {
+                    Some(offset) => self.ram[offset],
+                    None => 0xFF,
+                }
+            }
+            MODE_RTC_COMMAND | MODE_RTC_RESPONSE => 0x80 | self.rtc.response,
+            //bit 0 set means the last command completed, which is always the case here
+            MODE_RTC_SEMAPHORE => 0xFF,
+            MODE_IR => read_ir(&self.ir_peer),
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        match self.mode {
+            MODE_RAM => {
+                if let Some(offset) = banked_ram_offset(&self.ram, self.ram_bank as usize, address)
+                {
+                    self.ram[offset] = value;
+                    self.dirty = true;
+                }
+            }
+            MODE_RTC_COMMAND => self.rtc.command = value & 0x7F,
+            //clearing bit 0 runs the pending command
+            MODE_RTC_SEMAPHORE if value & 1 == 0 => self.dirty |= self.rtc.execute(),
+            MODE_IR => write_ir(&mut self.ir_peer, value),
+            _ => (),
+        }
+    }
+
+    fn save_data(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    fn set_infrared_peer(&mut self, peer: Box<dyn InfraredPeer>) {
+        self.ir_peer = Some(peer);
+    }
+
+    //the time stamp is for other emulators, loading ignores it
+    fn rtc_footer(&self) -> Vec<u8> {
+        let mut footer = Vec::with_capacity(RTC_FOOTER_SIZE);
+        footer.extend_from_slice(&unix_time().to_le_bytes());
+        footer.extend_from_slice(&self.rtc.minutes.to_le_bytes());
+        footer.extend_from_slice(&self.rtc.days.to_le_bytes());
+        footer.resize(RTC_FOOTER_SIZE, 0);
+        footer
+    }
+
+    fn load_rtc_footer(&mut self, footer: &[u8]) {
+        if footer.len() != RTC_FOOTER_SIZE {
+            return;
+        }
+        //the clock picks up where it was saved, time spent off doesn't count so a run goes
+        //the same whenever it's started
+        self.rtc.minutes = u16::from_le_bytes([footer[8], footer[9]]) % MINUTES_PER_DAY;
+        self.rtc.days = u16::from_le_bytes([footer[10], footer[11]]) & DAY_MASK;
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.rtc.tick(cycles);
+    }
+}
+
+//the huc3 clock counts minutes and days from emulated cycles only, so runs are reproducible
+struct Rtc {
+    memory: [u8; RTC_MEMORY_SIZE],
+    address: u8,
+    command: u8,
+    response: u8,
+    minutes: u16,
+    days: u16,
+    cycles: u64,
+}
+
+impl Rtc {
+    fn new() -> Rtc {
+        Rtc {
+            memory: [0; RTC_MEMORY_SIZE],
+            address: 0,
+            command: 0,
+            response: 0,
+            minutes: 0,
+            days: 0,
+            cycles: 0,
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.cycles += cycles as u64;
+        if self.cycles >= CYCLES_PER_MINUTE {
+            self.advance(self.cycles / CYCLES_PER_MINUTE);
+            self.cycles %= CYCLES_PER_MINUTE;
+        }
+    }
+
+    fn advance(&mut self, minutes: u64) {
+        let minutes = self.minutes as u64 + minutes;
+        let days = self.days as u64 + minutes / MINUTES_PER_DAY as u64;
+        self.minutes = (minutes % MINUTES_PER_DAY as u64) as u16;
+        self.days = (days & DAY_MASK as u64) as u16;
+    }
+
+    //run the pending command, returns whether it changed the memory or the clock
+    fn execute(&mut self) -> bool {
+        let command = self.command >> 4;
+        let argument = self.command & 0x0F;
+        let mut result = argument;
+        let changed = matches!(
+            (command, argument),
+            (RTC_WRITE, _) | (RTC_EXTENDED, RTC_EXT_STORE_TIME)
+        );
+        match command {
+            RTC_READ => {
+                result = self.memory[self.address as usize] & 0x0F;
+                self.address = self.address.wrapping_add(1);
+            }
+            RTC_WRITE => {
+                self.memory[self.address as usize] = argument;
+                self.address = self.address.wrapping_add(1);
+            }
+            RTC_ADDRESS_LOW => self.address = (self.address & 0xF0) | argument,
+            RTC_ADDRESS_HIGH => self.address = (self.address & 0x0F) | (argument << 4),
+            RTC_EXTENDED => match argument {
+                RTC_EXT_LOAD_TIME => self.load_time(),
+                RTC_EXT_STORE_TIME => self.store_time(),
+                RTC_EXT_STATUS => result = 1,
+                _ => (),
+            },
+            _ => (),
+        }
+        self.response = (command << 4) | result;
+        changed
+    }
+
+    //copy the clock into memory 0x00-0x05, least significant nibble first
+    fn load_time(&mut self) {
+        let time = (self.days as u32) << 12 | self.minutes as u32;
+        for i in 0..RTC_TIME_NIBBLES {
+            self.memory[i] = ((time >> (i * 4)) & 0x0F) as u8;
+        }
+    }
+
+    //set the clock from memory 0x00-0x05
+    fn store_time(&mut self) {
+        let mut time = 0u32;
+        for i in 0..RTC_TIME_NIBBLES {
+            time |= ((self.memory[i] & 0x0F) as u32) << (i * 4);
+        }
+        self.minutes = (time & 0x0FFF) as u16 % MINUTES_PER_DAY;
+        self.days = (time >> 12) as u16 & DAY_MASK;
+        self.cycles = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+    //every bank starts with its own number
+    fn rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * ROM_BANK_SIZE];
+        for bank in 0..banks {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        rom
+    }
+
+    //send a command through the semaphore, returns the response nibble
+    fn rtc_command(huc3: &mut Huc3, command: u8, argument: u8) -> u8 {
+        huc3.write_rom(0x0000, MODE_RTC_COMMAND);
+        huc3.write_ram(0xA000, command << 4 | argument);
+        huc3.write_rom(0x0000, MODE_RTC_SEMAPHORE);
+        huc3.write_ram(0xA000, 0xFE);
+        huc3.write_rom(0x0000, MODE_RTC_RESPONSE);
+        huc3.read_ram(0xA000) & 0x0F
+    }
+
+    //minutes and days as the game sees them
+    fn read_time(huc3: &mut Huc3) -> (u16, u16) {
+        rtc_command(huc3, RTC_EXTENDED, RTC_EXT_LOAD_TIME);
+        rtc_command(huc3, RTC_ADDRESS_LOW, 0);
+        rtc_command(huc3, RTC_ADDRESS_HIGH, 0);
+        let time = (0..RTC_TIME_NIBBLES).fold(0u32, |time, i| {
+            time | (rtc_command(huc3, RTC_READ, 0) as u32) << (i * 4)
+        });
+        ((time & 0x0FFF) as u16, (time >> 12) as u16)
+    }
+
+    #[test]
+    fn rom_bank_switching() {
+        let mut huc3 = Huc3::new(rom(128), Vec::new());
+        assert_eq!(huc3.read_rom(0x4000), 1);
+        huc3.write_rom(0x2000, 0x7F);
+        assert_eq!(huc3.read_rom(0x4000), 0x7F);
+        //unlike huc1 bank 0 can be mapped
+        huc3.write_rom(0x2000, 0);
+        assert_eq!(huc3.read_rom(0x4000), 0);
+    }
+
+    #[test]
+    fn ram_is_read_only_until_enabled() {
+        let mut huc3 = Huc3::new(rom(2), vec![0; 4 * RAM_BANK_SIZE]);
+        huc3.write_rom(0x4000, 2);
+        huc3.write_ram(0xA000, 0x12);
+        assert_eq!(huc3.read_ram(0xA000), 0);
+        huc3.write_rom(0x0000, MODE_RAM);
+        huc3.write_ram(0xA000, 0x12);
+        huc3.write_rom(0x0000, MODE_RAM_READ);
+        assert_eq!(huc3.read_ram(0xA000), 0x12);
+        assert_eq!(huc3.save_data()[2 * RAM_BANK_SIZE], 0x12);
+    }
+
+    #[test]
+    fn rtc_memory_and_clock() {
+        let mut huc3 = Huc3::new(rom(2), Vec::new());
+        //set the clock to day 2, 23:59
+        let time = 2u32 << 12 | (MINUTES_PER_DAY as u32 - 1);
+        rtc_command(&mut huc3, RTC_ADDRESS_LOW, 0);
+        rtc_command(&mut huc3, RTC_ADDRESS_HIGH, 0);
+        for i in 0..RTC_TIME_NIBBLES {
+            rtc_command(&mut huc3, RTC_WRITE, (time >> (i * 4)) as u8 & 0x0F);
+        }
+        rtc_command(&mut huc3, RTC_EXTENDED, RTC_EXT_STORE_TIME);
+        assert_eq!(read_time(&mut huc3), (MINUTES_PER_DAY - 1, 2));
+        huc3.tick((CYCLES_PER_MINUTE - 1) as u32);
+        assert_eq!(read_time(&mut huc3), (MINUTES_PER_DAY - 1, 2));
+        huc3.tick(1);
+        assert_eq!(read_time(&mut huc3), (0, 3));
+        assert_eq!(rtc_command(&mut huc3, RTC_EXTENDED, RTC_EXT_STATUS), 1);
+    }
+
+    #[test]
+    fn rtc_footer_round_trip() {
+        let mut huc3 = Huc3::new(rom(2), Vec::new());
+        huc3.tick((CYCLES_PER_MINUTE * 3) as u32);
+        let footer = huc3.rtc_footer();
+        assert_eq!(footer.len(), RTC_FOOTER_SIZE);
+        let mut loaded = Huc3::new(rom(2), Vec::new());
+        loaded.load_rtc_footer(&footer);
+        assert_eq!(read_time(&mut loaded), (3, 0));
+    }
+
+    #[test]
+    fn time_spent_off_doesnt_count() {
+        let mut footer = vec![0; RTC_FOOTER_SIZE];
+        //saved at 1970-01-01 with the clock on day 2 at 00:05
+        footer[8..10].copy_from_slice(&5u16.to_le_bytes());
+        footer[10..12].copy_from_slice(&2u16.to_le_bytes());
+        let mut huc3 = Huc3::new(rom(2), Vec::new());
+        huc3.load_rtc_footer(&footer);
+        assert_eq!(read_time(&mut huc3), (5, 2));
+    }
+
+    #[test]
+    fn only_changes_make_the_save_dirty() {
+        let mut huc3 = Huc3::new(rom(2), Vec::new());
+        read_time(&mut huc3);
+        rtc_command(&mut huc3, RTC_EXTENDED, RTC_EXT_STATUS);
+        assert!(!huc3.take_dirty());
+        rtc_command(&mut huc3, RTC_WRITE, 1);
+        assert!(huc3.take_dirty());
+        rtc_command(&mut huc3, RTC_EXTENDED, RTC_EXT_STORE_TIME);
+        assert!(huc3.take_dirty());
+    }
+}
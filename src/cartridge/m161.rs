@@ -0,0 +1,59 @@
+use super::{read_rom_bank, Mapper};
+
+const BANK_SIZE: usize = 0x8000;
+const BANK_MASK: u8 = 0b00000111;
+
+//mani 4 in 1: the menu picks one 32KB game once, after that writes are ignored until reset
+pub struct M161 {
+    rom: Vec<u8>,
+    bank: u8,
+    locked: bool,
+}
+
+impl M161 {
+    pub fn new(rom: Vec<u8>) -> M161 {
+        M161 {
+            rom,
+            bank: 0,
+            locked: false,
+        }
+    }
+}
+
+impl Mapper for M161 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn read_rom(&self, address: u16) -> u8 {
+        read_rom_bank(&self.rom, self.bank as usize, BANK_SIZE, address)
+    }
+
+    fn write_rom(&mut self, _address: u16, value: u8) {
+        if !self.locked {
+            self.bank = value & BANK_MASK;
+            self.locked = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_a_game_once() {
+        let mut rom = vec![0; 8 * BANK_SIZE];
+        for bank in 0..8 {
+            rom[bank * BANK_SIZE] = bank as u8;
+            rom[bank * BANK_SIZE + 0x4000] = 0x80 | bank as u8;
+        }
+        let mut m161 = M161::new(rom);
+        assert_eq!(m161.read_rom(0x0000), 0);
+        m161.write_rom(0x4000, 3);
+        assert_eq!(m161.read_rom(0x0000), 3);
+        assert_eq!(m161.read_rom(0x4000), 0x83);
+        m161.write_rom(0x4000, 5);
+        assert_eq!(m161.read_rom(0x0000), 3);
+    }
+}
@@ -0,0 +1,179 @@
+use super::{banked_ram_offset, load_ram, read_rom_bank, Mapper, ROM_BANK_SIZE};
+
+const BANK_MASK: u8 = 0b00011111;
+const BANK_BITS: u8 = 5;
+//mbc1m multicarts wire the upper bits one line lower, leaving 16 banks per game
+const MULTICART_BANK_BITS: u8 = 4;
+const UPPER_BANK_MASK: u8 = 0b00000011;
+const RAM_ENABLE_VALUE: u8 = 0x0A;
+
+pub struct Mbc1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rom_bank: u8,
+    upper_bank: u8,
+    //how many bits of rom_bank reach the rom before upper_bank takes over
+    bank_bits: u8,
+    ram_enabled: bool,
+    //false: upper bits select the rom bank, true: they select the ram bank
+    ram_banking_mode: bool,
+    dirty: bool,
+}
+
+impl Mbc1 {
+    pub fn new(rom: Vec<u8>, ram: Vec<u8>) -> Mbc1 {
+        Mbc1 {
+            rom,
+            ram,
+            rom_bank: 1,
+            upper_bank: 0,
+            bank_bits: BANK_BITS,
+            ram_enabled: false,
+            ram_banking_mode: false,
+            dirty: false,
+        }
+    }
+
+    pub fn new_multicart(rom: Vec<u8>, ram: Vec<u8>) -> Mbc1 {
+        Mbc1 {
+            bank_bits: MULTICART_BANK_BITS,
+            ..Mbc1::new(rom, ram)
+        }
+    }
+
+    fn ram_bank(&self) -> usize {
+        if self.ram_banking_mode {
+            self.upper_bank as usize
+        } else {
+            0
+        }
+    }
+}
+
+impl Mapper for Mbc1 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn read_rom(&self, address: u16) -> u8 {
+        let upper_bank = (self.upper_bank as usize) << self.bank_bits;
+        let bank = match address {
+            //in ram banking mode the upper bits reach 0x0000-0x3FFF too, which is how
+            //multicarts put the first bank of the selected game there
+            0x0000..=0x3FFF if self.ram_banking_mode => upper_bank,
+            0x0000..=0x3FFF => 0,
+            _ => upper_bank | (self.rom_bank & ((1 << self.bank_bits) - 1)) as usize,
+        };
+        read_rom_bank(&self.rom, bank, ROM_BANK_SIZE, address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == RAM_ENABLE_VALUE,
+            0x2000..=0x3FFF => {
+                //bank 0 can't be selected in the switchable window
+                self.rom_bank = (value & BANK_MASK).max(1);
+            }
+            0x4000..=0x5FFF => self.upper_bank = value & UPPER_BANK_MASK,
+            _ => self.ram_banking_mode = value & 1 == 1,
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        match banked_ram_offset(&self.ram, self.ram_bank(), address) {
+            Some(offset) => self.ram[offset],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        if let Some(offset) = banked_ram_offset(&self.ram, self.ram_bank(), address) {
+            self.ram[offset] = value;
+            self.dirty = true;
+        }
+    }
+
+    fn save_data(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::RAM_BANK_SIZE;
+
+    //every bank starts with its own number
+    fn rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * ROM_BANK_SIZE];
+        for bank in 0..banks {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        rom
+    }
+
+    #[test]
+    fn rom_bank_switching() {
+        let mut mbc = Mbc1::new(rom(128), Vec::new());
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        mbc.write_rom(0x2000, 0x05);
+        mbc.write_rom(0x4000, 0x02);
+        assert_eq!(mbc.read_rom(0x4000), 0x45);
+        assert_eq!(mbc.read_rom(0x0000), 0);
+        //bank 0 reads bank 1
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x41);
+    }
+
+    #[test]
+    fn mode_1_maps_the_upper_bits_into_bank_0() {
+        let mut mbc = Mbc1::new(rom(128), Vec::new());
+        mbc.write_rom(0x4000, 0x02);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x40);
+        mbc.write_rom(0x6000, 0x00);
+        assert_eq!(mbc.read_rom(0x0000), 0);
+    }
+
+    #[test]
+    fn mode_1_banks_ram() {
+        let mut mbc = Mbc1::new(rom(4), vec![0; 4 * RAM_BANK_SIZE]);
+        mbc.write_rom(0x0000, RAM_ENABLE_VALUE);
+        mbc.write_rom(0x4000, 0x03);
+        mbc.write_ram(0xA000, 0x12);
+        assert_eq!(mbc.save_data()[0], 0x12);
+        mbc.write_rom(0x6000, 0x01);
+        mbc.write_ram(0xA000, 0x34);
+        assert_eq!(mbc.save_data()[3 * RAM_BANK_SIZE], 0x34);
+        mbc.write_rom(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn multicart_games_have_16_banks() {
+        let mut mbc = Mbc1::new_multicart(rom(64), Vec::new());
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_rom(0x2000, 0x03);
+        assert_eq!(mbc.read_rom(0x4000), 0x13);
+        //the menu starts a game by switching to mode 1
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x10);
+        //bit 4 of the bank doesn't reach the rom
+        mbc.write_rom(0x2000, 0x12);
+        assert_eq!(mbc.read_rom(0x4000), 0x12);
+    }
+}
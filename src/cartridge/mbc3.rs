@@ -0,0 +1,450 @@
+use super::{banked_ram_offset, load_ram, read_banked_rom, unix_time, Mapper};
+
+const BANK_MASK: u8 = 0b01111111;
+const RAM_ENABLE_VALUE: u8 = 0x0A;
+//values of the 0x4000-0x5FFF register that map a clock register instead of ram
+const RTC_SECONDS: u8 = 0x08;
+const RTC_DAY_HIGH: u8 = 0x0C;
+
+//bits of the day high register
+const DAY_HIGH_BIT: u8 = 0b00000001;
+const HALT_FLAG: u8 = 0b01000000;
+const DAY_CARRY_FLAG: u8 = 0b10000000;
+const DAY_HIGH_MASK: u8 = DAY_HIGH_BIT | HALT_FLAG | DAY_CARRY_FLAG;
+
+const CYCLES_PER_SECOND: u64 = 4_194_304;
+const SECONDS_PER_DAY: u64 = 60 * 60 * 24;
+const DAYS: u64 = 512;
+
+//footer appended to the ram in .sav files by bgb and vba: the live registers and the
+//latched registers as 32 bit little endian values, then the unix time it was written at
+//as 64 bit, or 32 bit in older files
+const RTC_REGISTERS: usize = 5;
+const RTC_FOOTER_SIZE: usize = RTC_REGISTERS * 2 * 4 + 8;
+const RTC_FOOTER_SIZE_32: usize = RTC_REGISTERS * 2 * 4 + 4;
+
+pub struct Mbc3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rom_bank: u8,
+    //ram bank 0-3 or clock register 0x08-0x0C
+    ram_bank: u8,
+    ram_enabled: bool,
+    has_rtc: bool,
+    rtc: Rtc,
+    latched: Rtc,
+    //latching happens on a 0 then 1 write
+    latch_armed: bool,
+    dirty: bool,
+}
+
+impl Mbc3 {
+    pub fn new(rom: Vec<u8>, ram: Vec<u8>, has_rtc: bool) -> Mbc3 {
+        Mbc3 {
+            rom,
+            ram,
+            rom_bank: 1,
+            ram_bank: 0,
+            ram_enabled: false,
+            has_rtc,
+            rtc: Rtc::new(),
+            latched: Rtc::new(),
+            latch_armed: false,
+            dirty: false,
+        }
+    }
+
+    fn rtc_selected(&self) -> bool {
+        self.has_rtc && (RTC_SECONDS..=RTC_DAY_HIGH).contains(&self.ram_bank)
+    }
+
+    fn footer_at(&self, now: u64) -> Vec<u8> {
+        let mut footer = Vec::with_capacity(RTC_FOOTER_SIZE);
+        for rtc in [&self.rtc, &self.latched] {
+            for register in RTC_SECONDS..=RTC_DAY_HIGH {
+                footer.extend_from_slice(&(rtc.read(register) as u32).to_le_bytes());
+            }
+        }
+        footer.extend_from_slice(&now.to_le_bytes());
+        footer
+    }
+
+    fn load_footer_at(&mut self, footer: &[u8], now: u64) {
+        if footer.len() != RTC_FOOTER_SIZE && footer.len() != RTC_FOOTER_SIZE_32 {
+            return;
+        }
+        let word = |i: usize| u32::from_le_bytes(footer[i * 4..i * 4 + 4].try_into().unwrap());
+        for (i, register) in (RTC_SECONDS..=RTC_DAY_HIGH).enumerate() {
+            self.rtc.write(register, word(i) as u8);
+            self.latched.write(register, word(RTC_REGISTERS + i) as u8);
+        }
+        //the clock kept running while we were off
+        let saved_at = match footer.len() {
+            RTC_FOOTER_SIZE => u64::from_le_bytes(footer[40..48].try_into().unwrap()),
+            _ => word(RTC_REGISTERS * 2) as u64,
+        };
+        self.rtc.advance(now.saturating_sub(saved_at));
+    }
+}
+
+impl Mapper for Mbc3 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn read_rom(&self, address: u16) -> u8 {
+        read_banked_rom(&self.rom, self.rom_bank as usize, address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == RAM_ENABLE_VALUE,
+            0x2000..=0x3FFF => self.rom_bank = (value & BANK_MASK).max(1),
+            0x4000..=0x5FFF => self.ram_bank = value,
+            _ => {
+                if self.latch_armed && value == 1 {
+                    self.latched = self.rtc;
+                }
+                self.latch_armed = value == 0;
+            }
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        if self.rtc_selected() {
+            return self.latched.read(self.ram_bank);
+        }
+        match banked_ram_offset(&self.ram, self.ram_bank as usize & 0b11, address) {
+            Some(offset) => self.ram[offset],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        if self.rtc_selected() {
+            self.rtc.write(self.ram_bank, value);
+            self.latched.write(self.ram_bank, value);
+            self.dirty = true;
+            return;
+        }
+        if let Some(offset) = banked_ram_offset(&self.ram, self.ram_bank as usize & 0b11, address) {
+            self.ram[offset] = value;
+            self.dirty = true;
+        }
+    }
+
+    fn save_data(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    fn rtc_footer(&self) -> Vec<u8> {
+        match self.has_rtc {
+            true => self.footer_at(unix_time()),
+            false => Vec::new(),
+        }
+    }
+
+    fn load_rtc_footer(&mut self, footer: &[u8]) {
+        if self.has_rtc {
+            self.load_footer_at(footer, unix_time());
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        if self.has_rtc {
+            self.rtc.tick(cycles);
+        }
+    }
+}
+
+//the clock runs on emulated cycles, only time spent powered off comes from the host
+#[derive(Clone, Copy)]
+struct Rtc {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16,
+    halted: bool,
+    day_carry: bool,
+    cycles: u64,
+}
+
+impl Rtc {
+    fn new() -> Rtc {
+        Rtc {
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halted: false,
+            day_carry: false,
+            cycles: 0,
+        }
+    }
+
+    fn read(&self, register: u8) -> u8 {
+        match register {
+            0x08 => self.seconds,
+            0x09 => self.minutes,
+            0x0A => self.hours,
+            0x0B => self.days as u8,
+            _ => {
+                let mut value = (self.days >> 8) as u8 & DAY_HIGH_BIT;
+                if self.halted {
+                    value |= HALT_FLAG;
+                }
+                if self.day_carry {
+                    value |= DAY_CARRY_FLAG;
+                }
+                value
+            }
+        }
+    }
+
+    fn write(&mut self, register: u8, value: u8) {
+        match register {
+            0x08 => {
+                self.seconds = value & 0x3F;
+                self.cycles = 0;
+            }
+            0x09 => self.minutes = value & 0x3F,
+            0x0A => self.hours = value & 0x1F,
+            0x0B => self.days = (self.days & 0x100) | value as u16,
+            _ => {
+                let value = value & DAY_HIGH_MASK;
+                self.days = (self.days & 0xFF) | ((value & DAY_HIGH_BIT) as u16) << 8;
+                self.halted = value & HALT_FLAG != 0;
+                self.day_carry = value & DAY_CARRY_FLAG != 0;
+            }
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        if self.halted {
+            return;
+        }
+        self.cycles += cycles as u64;
+        if self.cycles >= CYCLES_PER_SECOND {
+            self.advance(self.cycles / CYCLES_PER_SECOND);
+            self.cycles %= CYCLES_PER_SECOND;
+        }
+    }
+
+    fn advance(&mut self, seconds: u64) {
+        if self.halted || seconds == 0 {
+            return;
+        }
+        //out of range values set by the game keep counting until they overflow their bits,
+        //step one second at a time for those and jump in whole days otherwise
+        let mut seconds = seconds;
+        while seconds > 0 {
+            if self.seconds < 60
+                && self.minutes < 60
+                && self.hours < 24
+                && seconds >= SECONDS_PER_DAY
+            {
+                let days = self.days as u64 + seconds / SECONDS_PER_DAY;
+                seconds %= SECONDS_PER_DAY;
+                if days >= DAYS {
+                    self.day_carry = true;
+                }
+                self.days = (days % DAYS) as u16;
+                continue;
+            }
+            self.step_second();
+            seconds -= 1;
+        }
+    }
+
+    fn step_second(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        self.days += 1;
+        if self.days as u64 == DAYS {
+            self.days = 0;
+            self.day_carry = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+    const SAVED_AT: u64 = 1_700_000_000;
+
+    //every bank starts with its own number
+    fn rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * ROM_BANK_SIZE];
+        for bank in 0..banks {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        rom
+    }
+
+    fn mbc3() -> Mbc3 {
+        let mut mbc = Mbc3::new(rom(128), vec![0; 4 * RAM_BANK_SIZE], true);
+        mbc.write_rom(0x0000, RAM_ENABLE_VALUE);
+        mbc
+    }
+
+    fn read_rtc(mbc: &mut Mbc3, register: u8) -> u8 {
+        mbc.write_rom(0x4000, register);
+        mbc.read_ram(0xA000)
+    }
+
+    fn write_rtc(mbc: &mut Mbc3, register: u8, value: u8) {
+        mbc.write_rom(0x4000, register);
+        mbc.write_ram(0xA000, value);
+    }
+
+    fn latch(mbc: &mut Mbc3) {
+        mbc.write_rom(0x6000, 0);
+        mbc.write_rom(0x6000, 1);
+    }
+
+    fn time(mbc: &mut Mbc3) -> [u8; 5] {
+        latch(mbc);
+        [0x08, 0x09, 0x0A, 0x0B, 0x0C].map(|register| read_rtc(mbc, register))
+    }
+
+    #[test]
+    fn rom_banks() {
+        let mut mbc = mbc3();
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        mbc.write_rom(0x2000, 0);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        mbc.write_rom(0x2000, 0x7F);
+        assert_eq!(mbc.read_rom(0x4000), 0x7F);
+        mbc.write_rom(0x2000, 0x85);
+        assert_eq!(mbc.read_rom(0x4000), 0x05);
+        assert_eq!(mbc.read_rom(0x0000), 0);
+    }
+
+    #[test]
+    fn ram_banks() {
+        let mut mbc = mbc3();
+        for bank in 0..4 {
+            mbc.write_rom(0x4000, bank);
+            mbc.write_ram(0xA000, 0x10 + bank);
+        }
+        for bank in 0..4 {
+            mbc.write_rom(0x4000, bank);
+            assert_eq!(mbc.read_ram(0xA000), 0x10 + bank);
+        }
+        mbc.write_rom(0x0000, 0);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+        assert!(mbc.take_dirty());
+        assert!(!mbc.take_dirty());
+    }
+
+    #[test]
+    fn latch_on_0_then_1() {
+        let mut mbc = mbc3();
+        write_rtc(&mut mbc, 0x08, 10);
+        mbc.tick(2 * CYCLES_PER_SECOND as u32);
+        //reads show the latched copy until the next latch
+        assert_eq!(read_rtc(&mut mbc, 0x08), 10);
+        mbc.write_rom(0x6000, 1);
+        assert_eq!(read_rtc(&mut mbc, 0x08), 10);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, 0x08), 12);
+    }
+
+    #[test]
+    fn halt_stops_the_clock() {
+        let mut mbc = mbc3();
+        write_rtc(&mut mbc, 0x0C, HALT_FLAG);
+        mbc.tick(5 * CYCLES_PER_SECOND as u32);
+        assert_eq!(time(&mut mbc), [0, 0, 0, 0, HALT_FLAG]);
+        write_rtc(&mut mbc, 0x0C, 0);
+        mbc.tick(5 * CYCLES_PER_SECOND as u32);
+        assert_eq!(time(&mut mbc), [5, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn day_carry() {
+        let mut mbc = mbc3();
+        write_rtc(&mut mbc, 0x08, 59);
+        write_rtc(&mut mbc, 0x09, 59);
+        write_rtc(&mut mbc, 0x0A, 23);
+        write_rtc(&mut mbc, 0x0B, 0xFF);
+        write_rtc(&mut mbc, 0x0C, DAY_HIGH_BIT);
+        mbc.tick(CYCLES_PER_SECOND as u32);
+        assert_eq!(time(&mut mbc), [0, 0, 0, 0, DAY_CARRY_FLAG]);
+        //the carry stays until the game clears it
+        mbc.tick(CYCLES_PER_SECOND as u32);
+        assert_eq!(time(&mut mbc), [1, 0, 0, 0, DAY_CARRY_FLAG]);
+    }
+
+    #[test]
+    fn footer_round_trip() {
+        let mut mbc = mbc3();
+        write_rtc(&mut mbc, 0x09, 42);
+        write_rtc(&mut mbc, 0x0B, 3);
+        let footer = mbc.footer_at(SAVED_AT);
+        assert_eq!(footer.len(), RTC_FOOTER_SIZE);
+
+        let mut loaded = mbc3();
+        loaded.load_footer_at(&footer, SAVED_AT);
+        assert_eq!(time(&mut loaded), [0, 42, 0, 3, 0]);
+    }
+
+    #[test]
+    fn footer_with_32_bit_time() {
+        let mut mbc = mbc3();
+        write_rtc(&mut mbc, 0x0A, 5);
+        let mut footer = mbc.footer_at(SAVED_AT);
+        footer.truncate(RTC_FOOTER_SIZE_32);
+        let mut loaded = mbc3();
+        loaded.load_footer_at(&footer, SAVED_AT);
+        assert_eq!(time(&mut loaded), [0, 0, 5, 0, 0]);
+    }
+
+    #[test]
+    fn time_passes_while_off() {
+        let mut mbc = mbc3();
+        write_rtc(&mut mbc, 0x0A, 23);
+        let footer = mbc.footer_at(SAVED_AT);
+        let mut loaded = mbc3();
+        //a day, an hour and a half and 5 seconds later
+        loaded.load_footer_at(&footer, SAVED_AT + 86_400 + 5_405);
+        assert_eq!(time(&mut loaded), [5, 30, 0, 2, 0]);
+    }
+
+    #[test]
+    fn footer_of_the_wrong_size_is_ignored() {
+        let mut mbc = mbc3();
+        mbc.load_footer_at(&[1; 20], SAVED_AT);
+        assert_eq!(time(&mut mbc), [0; 5]);
+        assert!(Mbc3::new(rom(2), Vec::new(), false).rtc_footer().is_empty());
+    }
+}
@@ -0,0 +1,193 @@
+use super::{banked_ram_offset, load_ram, read_banked_rom, Mapper, RumbleCallback};
+
+const RAM_ENABLE_VALUE: u8 = 0x0A;
+const RAM_BANK_MASK: u8 = 0b00001111;
+//on rumble cartridges bit 3 drives the motor and only 3 bits are left for the ram bank
+const RUMBLE_FLAG: u8 = 0b00001000;
+const RUMBLE_RAM_BANK_MASK: u8 = 0b00000111;
+
+pub struct Mbc5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    //9 bits, 0x2000-0x2FFF holds the low 8 and 0x3000-0x3FFF bit 8
+    rom_bank: u16,
+    ram_bank: u8,
+    ram_enabled: bool,
+    dirty: bool,
+    has_rumble: bool,
+    rumble: bool,
+    rumble_callback: Option<RumbleCallback>,
+}
+
+impl Mbc5 {
+    pub fn new(rom: Vec<u8>, ram: Vec<u8>, has_rumble: bool) -> Mbc5 {
+        Mbc5 {
+            rom,
+            ram,
+            rom_bank: 1,
+            ram_bank: 0,
+            ram_enabled: false,
+            dirty: false,
+            has_rumble,
+            rumble: false,
+            rumble_callback: None,
+        }
+    }
+
+    fn write_ram_bank(&mut self, value: u8) {
+        if !self.has_rumble {
+            self.ram_bank = value & RAM_BANK_MASK;
+            return;
+        }
+        self.ram_bank = value & RUMBLE_RAM_BANK_MASK;
+        let rumble = value & RUMBLE_FLAG != 0;
+        if rumble != self.rumble {
+            self.rumble = rumble;
+            if let Some(callback) = self.rumble_callback.as_mut() {
+                callback(rumble);
+            }
+        }
+    }
+}
+
+impl Mapper for Mbc5 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn read_rom(&self, address: u16) -> u8 {
+        //unlike mbc1 bank 0 is a valid choice for the switchable window
+        read_banked_rom(&self.rom, self.rom_bank as usize, address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value == RAM_ENABLE_VALUE,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | ((value as u16 & 1) << 8),
+            0x4000..=0x5FFF => self.write_ram_bank(value),
+            _ => (),
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        match banked_ram_offset(&self.ram, self.ram_bank as usize, address) {
+            Some(offset) => self.ram[offset],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        if let Some(offset) = banked_ram_offset(&self.ram, self.ram_bank as usize, address) {
+            self.ram[offset] = value;
+            self.dirty = true;
+        }
+    }
+
+    fn save_data(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    fn set_rumble_callback(&mut self, callback: RumbleCallback) {
+        self.rumble_callback = Some(callback);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{RAM_BANK_SIZE, ROM_BANK_SIZE};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    //every bank starts with its own number, low byte then high byte
+    fn rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * ROM_BANK_SIZE];
+        for bank in 0..banks {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+            rom[bank * ROM_BANK_SIZE + 1] = (bank >> 8) as u8;
+        }
+        rom
+    }
+
+    fn bank(mbc: &Mbc5) -> usize {
+        mbc.read_rom(0x4000) as usize | (mbc.read_rom(0x4001) as usize) << 8
+    }
+
+    #[test]
+    fn nine_bit_rom_bank() {
+        let mut mbc = Mbc5::new(rom(512), Vec::new(), false);
+        assert_eq!(bank(&mbc), 1);
+        mbc.write_rom(0x2000, 0x23);
+        assert_eq!(bank(&mbc), 0x23);
+        mbc.write_rom(0x3000, 1);
+        assert_eq!(bank(&mbc), 0x123);
+        //writing the low byte keeps bit 8
+        mbc.write_rom(0x2FFF, 0xFF);
+        assert_eq!(bank(&mbc), 0x1FF);
+        mbc.write_rom(0x3FFF, 0);
+        assert_eq!(bank(&mbc), 0xFF);
+        //bank 0 isn't turned into bank 1
+        mbc.write_rom(0x2000, 0);
+        assert_eq!(bank(&mbc), 0);
+        assert_eq!(mbc.read_rom(0x0000), 0);
+    }
+
+    #[test]
+    fn ram_banks_need_enabling() {
+        let mut mbc = Mbc5::new(rom(2), vec![0; 16 * RAM_BANK_SIZE], false);
+        mbc.write_ram(0xA000, 0x12);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+        mbc.write_rom(0x0000, RAM_ENABLE_VALUE);
+        mbc.write_rom(0x4000, 0x0F);
+        mbc.write_ram(0xA000, 0x12);
+        mbc.write_rom(0x4000, 0);
+        assert_eq!(mbc.read_ram(0xA000), 0);
+        mbc.write_rom(0x4000, 0x0F);
+        assert_eq!(mbc.read_ram(0xA000), 0x12);
+        assert_eq!(mbc.save_data()[15 * RAM_BANK_SIZE], 0x12);
+    }
+
+    #[test]
+    fn rumble_takes_bit_3_of_the_ram_bank() {
+        let mut mbc = Mbc5::new(rom(2), vec![0; 8 * RAM_BANK_SIZE], true);
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let log = calls.clone();
+        mbc.set_rumble_callback(Box::new(move |on| log.borrow_mut().push(on)));
+        mbc.write_rom(0x0000, RAM_ENABLE_VALUE);
+        mbc.write_rom(0x4000, RUMBLE_FLAG | 2);
+        mbc.write_ram(0xA000, 0x34);
+        //the motor only reports changes
+        mbc.write_rom(0x4000, RUMBLE_FLAG | 2);
+        mbc.write_rom(0x4000, 2);
+        assert_eq!(*calls.borrow(), [true, false]);
+        assert_eq!(mbc.save_data()[2 * RAM_BANK_SIZE], 0x34);
+    }
+
+    #[test]
+    fn bit_3_is_a_ram_bank_without_rumble() {
+        let mut mbc = Mbc5::new(rom(2), vec![0; 16 * RAM_BANK_SIZE], false);
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let log = calls.clone();
+        mbc.set_rumble_callback(Box::new(move |on| log.borrow_mut().push(on)));
+        mbc.write_rom(0x0000, RAM_ENABLE_VALUE);
+        mbc.write_rom(0x4000, RUMBLE_FLAG);
+        mbc.write_ram(0xA000, 0x56);
+        assert!(calls.borrow().is_empty());
+        assert_eq!(mbc.save_data()[8 * RAM_BANK_SIZE], 0x56);
+    }
+}
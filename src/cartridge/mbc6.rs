@@ -0,0 +1,299 @@
+use super::{load_ram, read_rom_bank, Mapper};
+
+const RAM_ENABLE_VALUE: u8 = 0x0A;
+const RAM_BANK_MASK: u8 = 0b00000111;
+const ROM_BANK_MASK: u8 = 0b01111111;
+//written to the source select registers to put flash in a window instead of rom
+const SOURCE_FLASH: u8 = 0x08;
+
+//each of the two rom windows and the two ram windows is half the usual size
+const ROM_BANK_SIZE: usize = 0x2000;
+const RAM_BANK_SIZE: usize = 0x1000;
+const RAM_SIZE: usize = RAM_BANK_SIZE * 8;
+//MX29F008, 1MB
+const FLASH_SIZE: usize = 0x100000;
+const FLASH_SECTOR_SIZE: usize = 0x10000;
+
+const FLASH_UNLOCK_1: (usize, u8) = (0x5555, 0xAA);
+const FLASH_UNLOCK_2: (usize, u8) = (0x2AAA, 0x55);
+const FLASH_PROGRAM: u8 = 0xA0;
+const FLASH_ERASE: u8 = 0x80;
+const FLASH_ERASE_SECTOR: u8 = 0x30;
+const FLASH_ERASE_CHIP: u8 = 0x10;
+const FLASH_ID: u8 = 0x90;
+const FLASH_RESET: u8 = 0xF0;
+const FLASH_MANUFACTURER: u8 = 0xC2;
+const FLASH_DEVICE: u8 = 0x81;
+
+#[derive(Clone, Copy, PartialEq)]
+enum FlashState {
+    Read,
+    Unlock1,
+    Unlock2,
+    Program,
+    Erase,
+    EraseUnlock1,
+    EraseUnlock2,
+    Id,
+}
+
+pub struct Mbc6 {
+    rom: Vec<u8>,
+    //battery ram followed by the flash, the layout other emulators save
+    save: Vec<u8>,
+    ram_enabled: bool,
+    ram_bank_a: u8,
+    ram_bank_b: u8,
+    rom_bank_a: u8,
+    rom_bank_b: u8,
+    flash_a: bool,
+    flash_b: bool,
+    flash_enabled: bool,
+    flash_write_enabled: bool,
+    flash_state: FlashState,
+    dirty: bool,
+}
+
+impl Mbc6 {
+    pub fn new(rom: Vec<u8>) -> Mbc6 {
+        let mut save = vec![0; RAM_SIZE];
+        save.resize(RAM_SIZE + FLASH_SIZE, 0xFF);
+        Mbc6 {
+            rom,
+            save,
+            ram_enabled: false,
+            ram_bank_a: 0,
+            ram_bank_b: 0,
+            rom_bank_a: 0,
+            rom_bank_b: 0,
+            flash_a: false,
+            flash_b: false,
+            flash_enabled: false,
+            flash_write_enabled: false,
+            flash_state: FlashState::Read,
+            dirty: false,
+        }
+    }
+
+    //bank and source of the 0x4000-0x5FFF or 0x6000-0x7FFF window
+    fn window(&self, address: u16) -> (usize, bool) {
+        if address < 0x6000 {
+            (self.rom_bank_a as usize, self.flash_a)
+        } else {
+            (self.rom_bank_b as usize, self.flash_b)
+        }
+    }
+
+    fn flash_offset(bank: usize, address: u16) -> usize {
+        (bank * ROM_BANK_SIZE + (address as usize & (ROM_BANK_SIZE - 1))) % FLASH_SIZE
+    }
+
+    fn ram_offset(&self, address: u16) -> usize {
+        let bank = if address < 0xB000 {
+            self.ram_bank_a
+        } else {
+            self.ram_bank_b
+        };
+        bank as usize * RAM_BANK_SIZE + (address as usize & (RAM_BANK_SIZE - 1))
+    }
+
+    fn flash(&mut self) -> &mut [u8] {
+        &mut self.save[RAM_SIZE..]
+    }
+
+    fn write_flash(&mut self, offset: usize, value: u8) {
+        let command_address = offset & 0x7FFF;
+        self.flash_state = match self.flash_state {
+            FlashState::Program => {
+                //programming can only clear bits
+                self.flash()[offset] &= value;
+                self.dirty = true;
+                FlashState::Read
+            }
+            _ if value == FLASH_RESET => FlashState::Read,
+            FlashState::Read | FlashState::Id if (command_address, value) == FLASH_UNLOCK_1 => {
+                FlashState::Unlock1
+            }
+            FlashState::Unlock1 if (command_address, value) == FLASH_UNLOCK_2 => {
+                FlashState::Unlock2
+            }
+            FlashState::Unlock2 if command_address == FLASH_UNLOCK_1.0 => match value {
+                FLASH_PROGRAM => FlashState::Program,
+                FLASH_ERASE => FlashState::Erase,
+                FLASH_ID => FlashState::Id,
+                _ => FlashState::Read,
+            },
+            FlashState::Erase if (command_address, value) == FLASH_UNLOCK_1 => {
+                FlashState::EraseUnlock1
+            }
+            FlashState::EraseUnlock1 if (command_address, value) == FLASH_UNLOCK_2 => {
+                FlashState::EraseUnlock2
+            }
+            FlashState::EraseUnlock2 => {
+                self.dirty = true;
+                match value {
+                    FLASH_ERASE_SECTOR => {
+                        let start = offset & !(FLASH_SECTOR_SIZE - 1);
+                        self.flash()[start..start + FLASH_SECTOR_SIZE].fill(0xFF);
+                    }
+                    FLASH_ERASE_CHIP => self.flash().fill(0xFF),
+                    _ => (),
+                }
+                FlashState::Read
+            }
+            _ => FlashState::Read,
+        };
+    }
+}
+
+impl Mapper for Mbc6 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn read_rom(&self, address: u16) -> u8 {
+        if address < 0x4000 {
+            return read_rom_bank(&self.rom, 0, ROM_BANK_SIZE * 2, address);
+        }
+        let (bank, flash) = self.window(address);
+        if !flash {
+            return read_rom_bank(&self.rom, bank, ROM_BANK_SIZE, address);
+        }
+        if !self.flash_enabled {
+            return 0xFF;
+        }
+        let offset = Mbc6::flash_offset(bank, address);
+        if self.flash_state == FlashState::Id {
+            return if offset & 1 == 0 {
+                FLASH_MANUFACTURER
+            } else {
+                FLASH_DEVICE
+            };
+        }
+        self.save[RAM_SIZE + offset]
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x03FF => self.ram_enabled = value & 0x0F == RAM_ENABLE_VALUE,
+            0x0400..=0x07FF => self.ram_bank_a = value & RAM_BANK_MASK,
+            0x0800..=0x0BFF => self.ram_bank_b = value & RAM_BANK_MASK,
+            0x0C00..=0x0FFF => self.flash_enabled = value & 1 == 1,
+            0x1000 => self.flash_write_enabled = value & 1 == 1,
+            0x2000..=0x27FF => self.rom_bank_a = value & ROM_BANK_MASK,
+            0x2800..=0x2FFF => self.flash_a = value == SOURCE_FLASH,
+            0x3000..=0x37FF => self.rom_bank_b = value & ROM_BANK_MASK,
+            0x3800..=0x3FFF => self.flash_b = value == SOURCE_FLASH,
+            0x4000..=0x7FFF => {
+                let (bank, flash) = self.window(address);
+                if flash && self.flash_enabled && self.flash_write_enabled {
+                    self.write_flash(Mbc6::flash_offset(bank, address), value);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        self.save[self.ram_offset(address)]
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if self.ram_enabled {
+            let offset = self.ram_offset(address);
+            self.save[offset] = value;
+            self.dirty = true;
+        }
+    }
+
+    fn save_data(&self) -> &[u8] {
+        &self.save
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.save, data);
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //every 8KB bank starts with its own number
+    fn mbc6() -> Mbc6 {
+        let mut rom = vec![0; 16 * ROM_BANK_SIZE];
+        for bank in 0..16 {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        let mut mbc6 = Mbc6::new(rom);
+        mbc6.write_rom(0x0C00, 1);
+        mbc6.write_rom(0x1000, 1);
+        mbc6
+    }
+
+    //write to flash through window a, which has to be switched to the right bank
+    fn write_flash(mbc6: &mut Mbc6, offset: usize, value: u8) {
+        mbc6.write_rom(0x2800, SOURCE_FLASH);
+        mbc6.write_rom(0x2000, (offset / ROM_BANK_SIZE) as u8);
+        mbc6.write_rom(0x4000 + (offset % ROM_BANK_SIZE) as u16, value);
+    }
+
+    fn flash_command(mbc6: &mut Mbc6, command: u8) {
+        write_flash(mbc6, FLASH_UNLOCK_1.0, FLASH_UNLOCK_1.1);
+        write_flash(mbc6, FLASH_UNLOCK_2.0, FLASH_UNLOCK_2.1);
+        write_flash(mbc6, FLASH_UNLOCK_1.0, command);
+    }
+
+    #[test]
+    fn two_rom_windows() {
+        let mut mbc6 = mbc6();
+        mbc6.write_rom(0x2000, 3);
+        mbc6.write_rom(0x3000, 5);
+        assert_eq!(mbc6.read_rom(0x4000), 3);
+        assert_eq!(mbc6.read_rom(0x6000), 5);
+        assert_eq!(mbc6.read_rom(0x2000), 1);
+    }
+
+    #[test]
+    fn two_ram_windows() {
+        let mut mbc6 = mbc6();
+        mbc6.write_rom(0x0000, RAM_ENABLE_VALUE);
+        mbc6.write_rom(0x0400, 1);
+        mbc6.write_ram(0xA000, 0x12);
+        mbc6.write_rom(0x0800, 1);
+        assert_eq!(mbc6.read_ram(0xB000), 0x12);
+        assert_eq!(mbc6.save_data()[RAM_BANK_SIZE], 0x12);
+    }
+
+    #[test]
+    fn flash_program_and_erase() {
+        let mut mbc6 = mbc6();
+        flash_command(&mut mbc6, FLASH_PROGRAM);
+        write_flash(&mut mbc6, 0x10000, 0x12);
+        mbc6.write_rom(0x2000, (0x10000 / ROM_BANK_SIZE) as u8);
+        assert_eq!(mbc6.read_rom(0x4000), 0x12);
+        assert_eq!(mbc6.save_data()[RAM_SIZE + 0x10000], 0x12);
+
+        flash_command(&mut mbc6, FLASH_ERASE);
+        write_flash(&mut mbc6, FLASH_UNLOCK_1.0, FLASH_UNLOCK_1.1);
+        write_flash(&mut mbc6, FLASH_UNLOCK_2.0, FLASH_UNLOCK_2.1);
+        write_flash(&mut mbc6, 0x10000, FLASH_ERASE_SECTOR);
+        assert_eq!(mbc6.save_data()[RAM_SIZE + 0x10000], 0xFF);
+    }
+
+    #[test]
+    fn flash_id() {
+        let mut mbc6 = mbc6();
+        flash_command(&mut mbc6, FLASH_ID);
+        mbc6.write_rom(0x2000, 0);
+        assert_eq!(mbc6.read_rom(0x4000), FLASH_MANUFACTURER);
+        assert_eq!(mbc6.read_rom(0x4001), FLASH_DEVICE);
+    }
+}
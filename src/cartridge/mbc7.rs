@@ -0,0 +1,415 @@
+use super::{load_ram, read_banked_rom, Mapper};
+
+const RAM_ENABLE_1_VALUE: u8 = 0x0A;
+const RAM_ENABLE_2_VALUE: u8 = 0x40;
+const LATCH_ERASE_VALUE: u8 = 0x55;
+const LATCH_VALUE: u8 = 0xAA;
+
+//accelerometer reading when flat and change per g of tilt
+const ACCEL_CENTER: f32 = 0x81D0 as f32;
+const ACCEL_PER_G: f32 = 0x70 as f32;
+const ACCEL_ERASED: u16 = 0x8000;
+
+//eeprom pins in the Ax8x register
+const EEPROM_CS: u8 = 0b10000000;
+const EEPROM_CLK: u8 = 0b01000000;
+const EEPROM_DI: u8 = 0b00000010;
+const EEPROM_DO: u8 = 0b00000001;
+
+//93LC56 in x16 organisation: 128 words, 256 bytes of save data
+const EEPROM_WORDS: usize = 128;
+const COMMAND_BITS: u8 = 10;
+const WORD_BITS: u8 = 16;
+
+pub struct Mbc7 {
+    rom: Vec<u8>,
+    rom_bank: u8,
+    ram_enabled_1: bool,
+    ram_enabled_2: bool,
+    //tilt in g supplied by the embedder, only seen by the game once latched
+    tilt_x: f32,
+    tilt_y: f32,
+    latch_erased: bool,
+    x_latch: u16,
+    y_latch: u16,
+    eeprom: Eeprom,
+}
+
+impl Mbc7 {
+    pub fn new(rom: Vec<u8>) -> Mbc7 {
+        Mbc7 {
+            rom,
+            rom_bank: 1,
+            ram_enabled_1: false,
+            ram_enabled_2: false,
+            tilt_x: 0.0,
+            tilt_y: 0.0,
+            latch_erased: false,
+            x_latch: ACCEL_ERASED,
+            y_latch: ACCEL_ERASED,
+            eeprom: Eeprom::new(),
+        }
+    }
+
+    fn registers_enabled(&self) -> bool {
+        self.ram_enabled_1 && self.ram_enabled_2
+    }
+
+    fn latch(&mut self) {
+        self.x_latch = (ACCEL_CENTER + self.tilt_x * ACCEL_PER_G) as u16;
+        self.y_latch = (ACCEL_CENTER + self.tilt_y * ACCEL_PER_G) as u16;
+    }
+}
+
+impl Mapper for Mbc7 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn read_rom(&self, address: u16) -> u8 {
+        read_banked_rom(&self.rom, self.rom_bank as usize, address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled_1 = value == RAM_ENABLE_1_VALUE,
+            0x2000..=0x3FFF => self.rom_bank = value,
+            0x4000..=0x5FFF => self.ram_enabled_2 = value == RAM_ENABLE_2_VALUE,
+            _ => (),
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        //registers are only mapped in 0xA000-0xAFFF and repeat every 0x100 bytes
+        if !self.registers_enabled() || address >= 0xB000 {
+            return 0xFF;
+        }
+        match (address >> 4) & 0xF {
+            0x2 => self.x_latch as u8,
+            0x3 => (self.x_latch >> 8) as u8,
+            0x4 => self.y_latch as u8,
+            0x5 => (self.y_latch >> 8) as u8,
+            0x6 => 0x00,
+            0x8 => self.eeprom.read(),
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if !self.registers_enabled() || address >= 0xB000 {
+            return;
+        }
+        match (address >> 4) & 0xF {
+            0x0 if value == LATCH_ERASE_VALUE => {
+                self.latch_erased = true;
+                self.x_latch = ACCEL_ERASED;
+                self.y_latch = ACCEL_ERASED;
+            }
+            //a new value is only latched after the old one was erased
+            0x1 if value == LATCH_VALUE && self.latch_erased => {
+                self.latch_erased = false;
+                self.latch();
+            }
+            0x8 => self.eeprom.write(value),
+            _ => (),
+        }
+    }
+
+    fn save_data(&self) -> &[u8] {
+        &self.eeprom.data
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.eeprom.data, data);
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.eeprom.dirty)
+    }
+
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt_x = x;
+        self.tilt_y = y;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum EepromState {
+    //waiting for the start bit
+    Idle,
+    //shifting in the 2 bit opcode and 8 bit address
+    Command,
+    //shifting out a word, reads continue on the following address
+    Read,
+    //shifting in a word for WRITE, or WRAL when all is set
+    Write { all: bool },
+}
+
+//93LC56 serial eeprom, bit-banged by the game through cs/clk/di and read back through do
+struct Eeprom {
+    //words are stored little endian like the .sav files of other emulators
+    data: Vec<u8>,
+    state: EepromState,
+    cs: bool,
+    clk: bool,
+    data_out: bool,
+    write_enabled: bool,
+    shift: u16,
+    bits: u8,
+    address: u8,
+    dirty: bool,
+}
+
+impl Eeprom {
+    fn new() -> Eeprom {
+        Eeprom {
+            data: vec![0xFF; EEPROM_WORDS * 2],
+            state: EepromState::Idle,
+            cs: false,
+            clk: false,
+            data_out: true,
+            write_enabled: false,
+            shift: 0,
+            bits: 0,
+            address: 0,
+            dirty: false,
+        }
+    }
+
+    fn read(&self) -> u8 {
+        let mut value = 0b00111100;
+        if self.cs {
+            value |= EEPROM_CS;
+        }
+        if self.clk {
+            value |= EEPROM_CLK;
+        }
+        if self.data_out {
+            value |= EEPROM_DO;
+        }
+        value
+    }
+
+    fn write(&mut self, value: u8) {
+        let cs = value & EEPROM_CS != 0;
+        let clk = value & EEPROM_CLK != 0;
+        let di = value & EEPROM_DI != 0;
+
+        if !cs {
+            //deselecting aborts any command, do reports ready
+            self.state = EepromState::Idle;
+            self.data_out = true;
+        } else if clk && !self.clk {
+            self.rising_edge(di);
+        }
+        self.cs = cs;
+        self.clk = clk;
+    }
+
+    fn word(&self, address: u8) -> u16 {
+        let i = (address as usize % EEPROM_WORDS) * 2;
+        u16::from_le_bytes([self.data[i], self.data[i + 1]])
+    }
+
+    fn set_word(&mut self, address: u8, value: u16) {
+        let i = (address as usize % EEPROM_WORDS) * 2;
+        self.data[i..i + 2].copy_from_slice(&value.to_le_bytes());
+        self.dirty = true;
+    }
+
+    fn shift_in(&mut self, di: bool) {
+        self.shift = (self.shift << 1) | di as u16;
+        self.bits += 1;
+    }
+
+    fn rising_edge(&mut self, di: bool) {
+        match self.state {
+            EepromState::Idle => {
+                if di {
+                    self.state = EepromState::Command;
+                    self.shift = 0;
+                    self.bits = 0;
+                }
+            }
+            EepromState::Command => {
+                self.shift_in(di);
+                if self.bits == COMMAND_BITS {
+                    self.execute_command();
+                }
+            }
+            EepromState::Read => {
+                if self.bits == 0 {
+                    self.address = self.address.wrapping_add(1);
+                    self.shift = self.word(self.address);
+                    self.bits = WORD_BITS;
+                }
+                self.data_out = self.shift & 0x8000 != 0;
+                self.shift <<= 1;
+                self.bits -= 1;
+            }
+            EepromState::Write { all } => {
+                self.shift_in(di);
+                if self.bits == WORD_BITS {
+                    if self.write_enabled {
+                        if all {
+                            for address in 0..EEPROM_WORDS as u8 {
+                                self.set_word(address, self.shift);
+                            }
+                        } else {
+                            self.set_word(self.address, self.shift);
+                        }
+                    }
+                    self.state = EepromState::Idle;
+                    self.data_out = true;
+                }
+            }
+        }
+    }
+
+    fn execute_command(&mut self) {
+        let opcode = (self.shift >> 8) & 0b11;
+        self.address = self.shift as u8;
+        self.shift = 0;
+        self.bits = 0;
+        self.state = EepromState::Idle;
+        match opcode {
+            0b10 => {
+                //READ, a dummy 0 comes out before the data
+                self.state = EepromState::Read;
+                self.shift = self.word(self.address);
+                self.bits = WORD_BITS;
+                self.data_out = false;
+            }
+            0b01 => self.state = EepromState::Write { all: false },
+            0b11 => {
+                if self.write_enabled {
+                    self.set_word(self.address, 0xFFFF);
+                }
+            }
+            _ => match self.address >> 6 {
+                0b11 => self.write_enabled = true,
+                0b00 => self.write_enabled = false,
+                0b10 => {
+                    if self.write_enabled {
+                        self.data.fill(0xFF);
+                        self.dirty = true;
+                    }
+                }
+                _ => self.state = EepromState::Write { all: true },
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EEPROM_REGISTER: u16 = 0xA080;
+    const READ: u16 = 0b10;
+    const WRITE: u16 = 0b01;
+    const EWEN: u16 = 0b00_1100_0000;
+
+    fn mbc7() -> Mbc7 {
+        let mut mbc = Mbc7::new(vec![0; 0x8000]);
+        mbc.write_rom(0x0000, RAM_ENABLE_1_VALUE);
+        mbc.write_rom(0x4000, RAM_ENABLE_2_VALUE);
+        mbc
+    }
+
+    //one clock with di held, returns do after the rising edge
+    fn clock(mbc: &mut Mbc7, di: bool) -> bool {
+        let di = if di { EEPROM_DI } else { 0 };
+        mbc.write_ram(EEPROM_REGISTER, EEPROM_CS | di);
+        mbc.write_ram(EEPROM_REGISTER, EEPROM_CS | EEPROM_CLK | di);
+        mbc.read_ram(EEPROM_REGISTER) & EEPROM_DO != 0
+    }
+
+    fn send(mbc: &mut Mbc7, value: u16, bits: u8) {
+        for bit in (0..bits).rev() {
+            clock(mbc, value >> bit & 1 != 0);
+        }
+    }
+
+    //start bit, then opcode and address
+    fn command(mbc: &mut Mbc7, opcode: u16, address: u16) {
+        send(mbc, 1, 1);
+        send(mbc, opcode << 8 | address, COMMAND_BITS);
+    }
+
+    fn deselect(mbc: &mut Mbc7) {
+        mbc.write_ram(EEPROM_REGISTER, 0);
+    }
+
+    fn receive(mbc: &mut Mbc7) -> u16 {
+        (0..WORD_BITS).fold(0, |word, _| word << 1 | clock(mbc, false) as u16)
+    }
+
+    fn write_word(mbc: &mut Mbc7, address: u16, word: u16) {
+        command(mbc, WRITE, address);
+        send(mbc, word, WORD_BITS);
+        deselect(mbc);
+    }
+
+    #[test]
+    fn eeprom_write_and_read() {
+        let mut mbc = mbc7();
+        command(&mut mbc, 0b00, EWEN);
+        deselect(&mut mbc);
+        write_word(&mut mbc, 5, 0x1234);
+        write_word(&mut mbc, 6, 0xABCD);
+        assert_eq!(mbc.save_data()[10..14], [0x34, 0x12, 0xCD, 0xAB]);
+
+        command(&mut mbc, READ, 5);
+        //dummy 0 before the data
+        assert_eq!(mbc.read_ram(EEPROM_REGISTER) & EEPROM_DO, 0);
+        assert_eq!(receive(&mut mbc), 0x1234);
+        //reads carry on with the next word
+        assert_eq!(receive(&mut mbc), 0xABCD);
+    }
+
+    #[test]
+    fn eeprom_writes_need_enabling() {
+        let mut mbc = mbc7();
+        write_word(&mut mbc, 5, 0x1234);
+        assert!(mbc.save_data().iter().all(|&byte| byte == 0xFF));
+    }
+
+    #[test]
+    fn eeprom_save_data_round_trip() {
+        let mut mbc = mbc7();
+        let mut save = vec![0; EEPROM_WORDS * 2];
+        save[0] = 0x78;
+        save[1] = 0x56;
+        mbc.load_save_data(&save);
+        command(&mut mbc, READ, 0);
+        assert_eq!(receive(&mut mbc), 0x5678);
+    }
+
+    #[test]
+    fn accelerometer_latches_after_erase() {
+        let mut mbc = mbc7();
+        mbc.set_tilt(1.0, -1.0);
+        //latching without erasing first does nothing
+        mbc.write_ram(0xA010, LATCH_VALUE);
+        assert_eq!(mbc.read_ram(0xA020), 0x00);
+        assert_eq!(mbc.read_ram(0xA030), 0x80);
+        mbc.write_ram(0xA000, LATCH_ERASE_VALUE);
+        mbc.write_ram(0xA010, LATCH_VALUE);
+        let x = mbc.read_ram(0xA020) as u16 | (mbc.read_ram(0xA030) as u16) << 8;
+        let y = mbc.read_ram(0xA040) as u16 | (mbc.read_ram(0xA050) as u16) << 8;
+        assert_eq!(x, 0x81D0 + 0x70);
+        assert_eq!(y, 0x81D0 - 0x70);
+    }
+
+    #[test]
+    fn registers_need_both_enables() {
+        let mut mbc = Mbc7::new(vec![0; 0x8000]);
+        mbc.write_rom(0x0000, RAM_ENABLE_1_VALUE);
+        assert_eq!(mbc.read_ram(0xA020), 0xFF);
+        mbc.write_rom(0x4000, RAM_ENABLE_2_VALUE);
+        assert_eq!(mbc.read_ram(0xA020), 0x00);
+        //nothing above 0xAFFF
+        assert_eq!(mbc.read_ram(0xB020), 0xFF);
+    }
+}
@@ -0,0 +1,228 @@
+use super::{banked_ram_offset, load_ram, read_rom_bank, Mapper, ROM_BANK_SIZE};
+
+const RAM_ENABLE_VALUE: u8 = 0x0A;
+//0x0000-0x1FFF
+const MAP_FLAG: u8 = 0b01000000;
+const RAM_MASK: u8 = 0b00110000;
+//0x2000-0x3FFF
+const ROM_BANK_LOW_MASK: u8 = 0b00011111;
+const ROM_BANK_MID_MASK: u8 = 0b01100000;
+//0x4000-0x5FFF
+const RAM_BANK_LOW_MASK: u8 = 0b00000011;
+const RAM_BANK_HIGH_MASK: u8 = 0b00001100;
+const ROM_BANK_HIGH_MASK: u8 = 0b00110000;
+const MODE_LOCK_FLAG: u8 = 0b01000000;
+//0x6000-0x7FFF
+const ROM_MASK: u8 = 0b00111100;
+
+//multicart mapper: boots into the menu in the last 32KB, the menu then picks a game
+//by writing the outer bank bits and masks and setting the map flag, which locks them
+pub struct Mmm01 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    mapped: bool,
+    ram_enabled: bool,
+    rom_bank_low: u8,
+    rom_bank_mid: u8,
+    rom_bank_high: u8,
+    //bits of rom_bank_low the game can't change, mask bit n covers bank bit n + 1
+    rom_mask: u8,
+    ram_bank_low: u8,
+    ram_bank_high: u8,
+    //bits of ram_bank_low the game can't change
+    ram_mask: u8,
+    mode: bool,
+    mode_locked: bool,
+    dirty: bool,
+}
+
+impl Mmm01 {
+    pub fn new(rom: Vec<u8>, ram: Vec<u8>) -> Mmm01 {
+        Mmm01 {
+            rom,
+            ram,
+            mapped: false,
+            ram_enabled: false,
+            rom_bank_low: 0,
+            rom_bank_mid: 0,
+            rom_bank_high: 0,
+            rom_mask: 0,
+            ram_bank_low: 0,
+            ram_bank_high: 0,
+            ram_mask: 0,
+            mode: false,
+            mode_locked: false,
+            dirty: false,
+        }
+    }
+
+    fn outer_rom_bank(&self) -> usize {
+        (self.rom_bank_high as usize) << 7 | (self.rom_bank_mid as usize) << 5
+    }
+
+    fn rom_bank_mask(&self) -> u8 {
+        (self.rom_mask << 1) & ROM_BANK_LOW_MASK
+    }
+
+    fn ram_bank(&self) -> usize {
+        let low = if self.mode { self.ram_bank_low } else { 0 };
+        (self.ram_bank_high as usize) << 2 | low as usize
+    }
+}
+
+impl Mapper for Mmm01 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn read_rom(&self, address: u16) -> u8 {
+        if !self.mapped {
+            //the menu lives in the last two banks
+            let banks = self.rom.len().div_ceil(ROM_BANK_SIZE).max(2);
+            let bank = banks - 2 + (address as usize >> 14);
+            return read_rom_bank(&self.rom, bank, ROM_BANK_SIZE, address);
+        }
+        let bank = if address < 0x4000 {
+            self.outer_rom_bank() | (self.rom_bank_low & self.rom_bank_mask()) as usize
+        } else {
+            let low = if self.rom_bank_low & !self.rom_bank_mask() == 0 {
+                self.rom_bank_low | 1
+            } else {
+                self.rom_bank_low
+            };
+            self.outer_rom_bank() | low as usize
+        };
+        read_rom_bank(&self.rom, bank, ROM_BANK_SIZE, address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => {
+                self.ram_enabled = value & 0x0F == RAM_ENABLE_VALUE;
+                if !self.mapped {
+                    self.ram_mask = (value & RAM_MASK) >> 4;
+                    self.mapped = value & MAP_FLAG != 0;
+                }
+            }
+            0x2000..=0x3FFF => {
+                let mask = self.rom_bank_mask();
+                self.rom_bank_low =
+                    (self.rom_bank_low & mask) | (value & ROM_BANK_LOW_MASK & !mask);
+                if !self.mapped {
+                    self.rom_bank_mid = (value & ROM_BANK_MID_MASK) >> 5;
+                }
+            }
+            0x4000..=0x5FFF => {
+                let mask = self.ram_mask;
+                self.ram_bank_low =
+                    (self.ram_bank_low & mask) | (value & RAM_BANK_LOW_MASK & !mask);
+                if !self.mapped {
+                    self.ram_bank_high = (value & RAM_BANK_HIGH_MASK) >> 2;
+                    self.rom_bank_high = (value & ROM_BANK_HIGH_MASK) >> 4;
+                    self.mode_locked = value & MODE_LOCK_FLAG != 0;
+                }
+            }
+            _ => {
+                if !self.mode_locked {
+                    self.mode = value & 1 == 1;
+                }
+                if !self.mapped {
+                    self.rom_mask = (value & ROM_MASK) >> 2;
+                }
+            }
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        match banked_ram_offset(&self.ram, self.ram_bank(), address) {
+            Some(offset) => self.ram[offset],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        if let Some(offset) = banked_ram_offset(&self.ram, self.ram_bank(), address) {
+            self.ram[offset] = value;
+            self.dirty = true;
+        }
+    }
+
+    fn save_data(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::RAM_BANK_SIZE;
+
+    //every bank starts with its own number
+    fn rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * ROM_BANK_SIZE];
+        for bank in 0..banks {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        rom
+    }
+
+    #[test]
+    fn boots_into_the_menu() {
+        let mmm01 = Mmm01::new(rom(64), Vec::new());
+        assert_eq!(mmm01.read_rom(0x0000), 62);
+        assert_eq!(mmm01.read_rom(0x4000), 63);
+    }
+
+    #[test]
+    fn menu_maps_a_game() {
+        let mut mmm01 = Mmm01::new(rom(64), Vec::new());
+        //game in the second 512KB
+        mmm01.write_rom(0x2000, 0x20);
+        mmm01.write_rom(0x0000, MAP_FLAG);
+        assert_eq!(mmm01.read_rom(0x0000), 32);
+        assert_eq!(mmm01.read_rom(0x4000), 33);
+        //the outer bits are locked now
+        mmm01.write_rom(0x2000, 0x02);
+        assert_eq!(mmm01.read_rom(0x0000), 32);
+        assert_eq!(mmm01.read_rom(0x4000), 34);
+    }
+
+    #[test]
+    fn rom_mask_locks_low_bank_bits() {
+        let mut mmm01 = Mmm01::new(rom(64), Vec::new());
+        //bank bits 1-2 fixed to 0b10 by the menu
+        mmm01.write_rom(0x2000, 0x24);
+        mmm01.write_rom(0x6000, 0b00001100);
+        mmm01.write_rom(0x0000, MAP_FLAG);
+        mmm01.write_rom(0x2000, 0x01);
+        assert_eq!(mmm01.read_rom(0x4000), 32 | 0b101);
+        assert_eq!(mmm01.read_rom(0x0000), 32 | 0b100);
+    }
+
+    #[test]
+    fn ram_banks() {
+        let mut mmm01 = Mmm01::new(rom(4), vec![0; 4 * RAM_BANK_SIZE]);
+        mmm01.write_rom(0x0000, MAP_FLAG | RAM_ENABLE_VALUE);
+        //the low ram bank bits only count in mode 1
+        mmm01.write_rom(0x4000, 2);
+        mmm01.write_ram(0xA000, 0x12);
+        assert_eq!(mmm01.save_data()[0], 0x12);
+        mmm01.write_rom(0x6000, 1);
+        mmm01.write_ram(0xA000, 0x34);
+        assert_eq!(mmm01.save_data()[2 * RAM_BANK_SIZE], 0x34);
+    }
+}
@@ -0,0 +1,158 @@
+use super::{load_ram, read_banked_rom, Mapper};
+
+//the game talks to the tama5 one nibble at a time: it selects a register by writing
+//its index to 0xA001, then reads or writes the value through 0xA000
+const REG_BANK_LOW: u8 = 0x0;
+const REG_BANK_HIGH: u8 = 0x1;
+const REG_WRITE_LOW: u8 = 0x4;
+const REG_WRITE_HIGH: u8 = 0x5;
+//bit 0 is bit 4 of the ram address, bits 1-3 the command
+const REG_COMMAND: u8 = 0x6;
+//low nibble of the ram address, writing it runs the command
+const REG_ADDRESS_LOW: u8 = 0x7;
+const REG_ACTIVE: u8 = 0xA;
+const REG_READ_LOW: u8 = 0xC;
+const REG_READ_HIGH: u8 = 0xD;
+const REGISTERS: usize = 0x10;
+
+const COMMAND_WRITE: u8 = 0x0;
+const COMMAND_READ: u8 = 0x1;
+
+const RAM_SIZE: usize = 0x20;
+//unused bits of every read come back set
+const READ_BASE: u8 = 0xF0;
+
+pub struct Tama5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    registers: [u8; REGISTERS],
+    register: u8,
+    read_value: u8,
+    dirty: bool,
+}
+
+impl Tama5 {
+    pub fn new(rom: Vec<u8>) -> Tama5 {
+        Tama5 {
+            rom,
+            ram: vec![0; RAM_SIZE],
+            registers: [0; REGISTERS],
+            register: 0,
+            read_value: 0,
+            dirty: false,
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        ((self.registers[REG_BANK_HIGH as usize] & 1) << 4 | self.registers[REG_BANK_LOW as usize])
+            as usize
+    }
+
+    fn execute(&mut self) {
+        let command = self.registers[REG_COMMAND as usize];
+        let address = ((command & 1) << 4 | self.registers[REG_ADDRESS_LOW as usize]) as usize;
+        match command >> 1 {
+            COMMAND_WRITE => {
+                self.ram[address] = self.registers[REG_WRITE_HIGH as usize] << 4
+                    | self.registers[REG_WRITE_LOW as usize];
+                self.dirty = true;
+            }
+            COMMAND_READ => self.read_value = self.ram[address],
+            _ => (),
+        }
+    }
+}
+
+impl Mapper for Tama5 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn read_rom(&self, address: u16) -> u8 {
+        read_banked_rom(&self.rom, self.rom_bank(), address)
+    }
+
+    //there are no registers in 0x0000-0x7FFF
+    fn write_rom(&mut self, _address: u16, _value: u8) {}
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if address & 1 == 1 {
+            return 0xFF;
+        }
+        match self.register {
+            REG_ACTIVE => READ_BASE | 1,
+            REG_READ_LOW => READ_BASE | (self.read_value & 0x0F),
+            REG_READ_HIGH => READ_BASE | (self.read_value >> 4),
+            _ => READ_BASE,
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if address & 1 == 1 {
+            self.register = value & 0x0F;
+            return;
+        }
+        self.registers[self.register as usize] = value & 0x0F;
+        if self.register == REG_ADDRESS_LOW {
+            self.execute();
+        }
+    }
+
+    fn save_data(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::ROM_BANK_SIZE;
+
+    fn write_register(tama5: &mut Tama5, register: u8, value: u8) {
+        tama5.write_ram(0xA001, register);
+        tama5.write_ram(0xA000, value);
+    }
+
+    fn read_register(tama5: &mut Tama5, register: u8) -> u8 {
+        tama5.write_ram(0xA001, register);
+        tama5.read_ram(0xA000)
+    }
+
+    #[test]
+    fn rom_bank_switching() {
+        let mut rom = vec![0; 32 * ROM_BANK_SIZE];
+        for bank in 0..32 {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        let mut tama5 = Tama5::new(rom);
+        write_register(&mut tama5, REG_BANK_LOW, 0x5);
+        write_register(&mut tama5, REG_BANK_HIGH, 0x1);
+        assert_eq!(tama5.read_rom(0x4000), 0x15);
+        assert_eq!(tama5.read_rom(0x0000), 0);
+    }
+
+    #[test]
+    fn ram_through_commands() {
+        let mut tama5 = Tama5::new(Vec::new());
+        assert_eq!(read_register(&mut tama5, REG_ACTIVE), READ_BASE | 1);
+        //write 0xA7 to 0x13
+        write_register(&mut tama5, REG_WRITE_LOW, 0x7);
+        write_register(&mut tama5, REG_WRITE_HIGH, 0xA);
+        write_register(&mut tama5, REG_COMMAND, COMMAND_WRITE << 1 | 1);
+        write_register(&mut tama5, REG_ADDRESS_LOW, 0x3);
+        assert_eq!(tama5.save_data()[0x13], 0xA7);
+
+        write_register(&mut tama5, REG_COMMAND, COMMAND_READ << 1 | 1);
+        write_register(&mut tama5, REG_ADDRESS_LOW, 0x3);
+        assert_eq!(read_register(&mut tama5, REG_READ_LOW), READ_BASE | 0x7);
+        assert_eq!(read_register(&mut tama5, REG_READ_HIGH), READ_BASE | 0xA);
+    }
+}
@@ -0,0 +1,563 @@
+use std::cell::Cell;
+
+use super::mbc5::Mbc5;
+use super::{banked_ram_offset, load_ram, read_rom_bank, Mapper, ROM_BANK_SIZE};
+
+const WISDOM_TREE_BANK_SIZE: usize = 0x8000;
+
+//sachen registers 0x0000 and 0x4000 are only writable while both bits are set in the rom bank
+const SACHEN_UNLOCK_BITS: u8 = 0b00110000;
+//reads of 0x0100-0x01FF the mapper sees before it drops the logo redirection
+const SACHEN_LOCKED_READS: u8 = 0x31;
+const SACHEN_LOGO_REDIRECT: u16 = 0x0080;
+
+//li cheng mbc5 clones only decode the rom bank register up to 0x2100
+const LI_CHENG_BANK_END: u16 = 0x2100;
+
+//bit swapping mbc5 clones pick one of 8 permutations for the rom bank written to 0x2000
+//and one for the data read from the switchable window, entry n is the source of bit n
+type BitOrders = [[u8; 8]; 8];
+const BIT_SWAP_MODE_MASK: u8 = 0b00000111;
+const BBD_DATA_ORDERS: BitOrders = [
+    [0, 1, 2, 3, 4, 5, 6, 7],
+    [0, 1, 2, 3, 4, 5, 6, 7],
+    [0, 1, 5, 3, 4, 2, 6, 7],
+    [0, 1, 2, 3, 4, 5, 6, 7],
+    [7, 1, 5, 3, 4, 2, 6, 0],
+    [0, 1, 5, 3, 4, 7, 6, 2],
+    [0, 1, 2, 3, 4, 5, 6, 7],
+    [0, 1, 2, 3, 4, 5, 6, 7],
+];
+const BBD_BANK_ORDERS: BitOrders = [
+    [0, 1, 2, 3, 4, 5, 6, 7],
+    [0, 1, 2, 3, 4, 5, 6, 7],
+    [0, 1, 2, 3, 4, 5, 6, 7],
+    [3, 4, 2, 0, 1, 5, 6, 7],
+    [0, 1, 2, 3, 4, 5, 6, 7],
+    [1, 2, 3, 4, 0, 5, 6, 7],
+    [0, 1, 2, 3, 4, 5, 6, 7],
+    [0, 1, 2, 3, 4, 5, 6, 7],
+];
+const HITEK_DATA_ORDERS: BitOrders = [
+    [0, 1, 2, 3, 4, 5, 6, 7],
+    [0, 6, 5, 3, 4, 1, 2, 7],
+    [0, 5, 6, 3, 4, 2, 1, 7],
+    [0, 6, 2, 3, 4, 5, 1, 7],
+    [0, 6, 1, 3, 4, 5, 2, 7],
+    [0, 1, 6, 3, 4, 5, 2, 7],
+    [0, 2, 6, 3, 4, 1, 5, 7],
+    [0, 6, 2, 3, 4, 1, 5, 7],
+];
+const HITEK_BANK_ORDERS: BitOrders = [
+    [0, 1, 2, 3, 4, 5, 6, 7],
+    [3, 2, 1, 0, 4, 5, 6, 7],
+    [2, 1, 0, 3, 4, 5, 6, 7],
+    [1, 0, 3, 2, 4, 5, 6, 7],
+    [0, 3, 2, 1, 4, 5, 6, 7],
+    [2, 3, 0, 1, 4, 5, 6, 7],
+    [3, 0, 1, 2, 4, 5, 6, 7],
+    [2, 0, 3, 1, 4, 5, 6, 7],
+];
+
+//nt older multicarts take the outer bank, the bank count and the bit swap at 0x5000-0x5FFF
+const NT_OLD_BANK_MASK: u8 = 0b00011111;
+const NT_OLD_BASE_MASK: u8 = 0b00111111;
+const NT_OLD_SWAP_FLAG: u8 = 0b00010000;
+const NT_OLD_BANK_ORDER: [u8; 8] = [0, 2, 1, 4, 3, 5, 6, 7];
+const NT_OLD_RAM_ENABLE_VALUE: u8 = 0x0A;
+
+//32KB banks picked by the low address byte of any write to 0x0000-0x3FFF, the data is ignored
+pub struct WisdomTree {
+    rom: Vec<u8>,
+    bank: u8,
+}
+
+impl WisdomTree {
+    pub fn new(rom: Vec<u8>) -> WisdomTree {
+        WisdomTree { rom, bank: 0 }
+    }
+}
+
+impl Mapper for WisdomTree {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn read_rom(&self, address: u16) -> u8 {
+        read_rom_bank(
+            &self.rom,
+            self.bank as usize,
+            WISDOM_TREE_BANK_SIZE,
+            address,
+        )
+    }
+
+    fn write_rom(&mut self, address: u16, _value: u8) {
+        if address < 0x4000 {
+            self.bank = address as u8;
+        }
+    }
+}
+
+//sachen mappers hide the nintendo logo from the game: while locked, reads in
+//0x0100-0x01FF are redirected to the upper half of that page where the real logo
+//is stored, which is what the boot rom ends up checking. The page is also stored
+//with address lines swapped, both versions need unscrambling.
+pub struct Sachen {
+    rom: Vec<u8>,
+    //mmc2 carts never redirect the logo on dmg, they only count the reads
+    mmc2: bool,
+    base_bank: u8,
+    rom_bank: u8,
+    mask: u8,
+    locked: Cell<bool>,
+    locked_reads: Cell<u8>,
+}
+
+impl Sachen {
+    pub fn new(rom: Vec<u8>, mmc2: bool) -> Sachen {
+        Sachen {
+            rom,
+            mmc2,
+            base_bank: 0,
+            rom_bank: 1,
+            mask: 0,
+            locked: Cell::new(true),
+            locked_reads: Cell::new(0),
+        }
+    }
+
+    fn registers_unlocked(&self) -> bool {
+        self.rom_bank & SACHEN_UNLOCK_BITS == SACHEN_UNLOCK_BITS
+    }
+
+    fn switchable_bank(&self) -> u8 {
+        (self.rom_bank & !self.mask) | (self.base_bank & self.mask)
+    }
+}
+
+//swap address lines a0 with a6 and a1 with a4
+pub fn unscramble_sachen(address: u16) -> u16 {
+    let mut unscrambled = address & 0xFFAC;
+    unscrambled |= (address & 0x40) >> 6;
+    unscrambled |= (address & 0x10) >> 3;
+    unscrambled |= (address & 0x02) << 3;
+    unscrambled |= (address & 0x01) << 6;
+    unscrambled
+}
+
+impl Mapper for Sachen {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn read_rom(&self, address: u16) -> u8 {
+        if address & 0xFF00 == 0x0100 && self.locked.get() {
+            let reads = self.locked_reads.get() + 1;
+            self.locked_reads.set(reads);
+            //the read that unlocks it already sees the game's own page
+            if reads == SACHEN_LOCKED_READS {
+                self.locked.set(false);
+            }
+        }
+        self.peek_rom(address)
+    }
+
+    fn peek_rom(&self, address: u16) -> u8 {
+        let mut address = address;
+        if address & 0xFF00 == 0x0100 {
+            if self.locked.get() && !self.mmc2 {
+                address |= SACHEN_LOGO_REDIRECT;
+            }
+            address = unscramble_sachen(address);
+        }
+        let bank = if address < 0x4000 {
+            self.base_bank & self.mask
+        } else {
+            self.switchable_bank()
+        };
+        read_rom_bank(&self.rom, bank as usize, ROM_BANK_SIZE, address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF if self.registers_unlocked() => self.base_bank = value,
+            0x2000..=0x3FFF => self.rom_bank = value.max(1),
+            0x4000..=0x5FFF if self.registers_unlocked() => self.mask = value,
+            _ => (),
+        }
+    }
+}
+
+//mbc5 clone that ignores rom bank writes above 0x2100, games rely on it
+//by writing garbage to the rest of the register range
+pub struct LiCheng {
+    mbc5: Mbc5,
+}
+
+impl LiCheng {
+    pub fn new(rom: Vec<u8>, ram: Vec<u8>) -> LiCheng {
+        LiCheng {
+            mbc5: Mbc5::new(rom, ram, false),
+        }
+    }
+}
+
+impl Mapper for LiCheng {
+    fn rom(&self) -> &[u8] {
+        self.mbc5.rom()
+    }
+
+    fn read_rom(&self, address: u16) -> u8 {
+        self.mbc5.read_rom(address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        if address > LI_CHENG_BANK_END && address < 0x3000 {
+            return;
+        }
+        self.mbc5.write_rom(address, value);
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        self.mbc5.read_ram(address)
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        self.mbc5.write_ram(address, value);
+    }
+
+    fn save_data(&self) -> &[u8] {
+        self.mbc5.save_data()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        self.mbc5.load_save_data(data);
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        self.mbc5.take_dirty()
+    }
+}
+
+//value with bit n taken from bit order[n]
+fn reorder_bits(value: u8, order: &[u8; 8]) -> u8 {
+    (0..8).fold(0, |reordered, bit| {
+        reordered | ((value >> order[bit]) & 1) << bit
+    })
+}
+
+//mbc5 clones that scramble the rom bank written to 0x2000 and the data read from the
+//switchable window, the game picks the permutations through 0x2080 and 0x2001
+pub struct BitSwappedMbc5 {
+    mbc5: Mbc5,
+    bank_orders: &'static BitOrders,
+    data_orders: &'static BitOrders,
+    bank_mode: u8,
+    data_mode: u8,
+}
+
+impl BitSwappedMbc5 {
+    pub fn new_bbd(rom: Vec<u8>, ram: Vec<u8>) -> BitSwappedMbc5 {
+        BitSwappedMbc5::new(rom, ram, &BBD_BANK_ORDERS, &BBD_DATA_ORDERS)
+    }
+
+    pub fn new_hitek(rom: Vec<u8>, ram: Vec<u8>) -> BitSwappedMbc5 {
+        BitSwappedMbc5::new(rom, ram, &HITEK_BANK_ORDERS, &HITEK_DATA_ORDERS)
+    }
+
+    fn new(
+        rom: Vec<u8>,
+        ram: Vec<u8>,
+        bank_orders: &'static BitOrders,
+        data_orders: &'static BitOrders,
+    ) -> BitSwappedMbc5 {
+        BitSwappedMbc5 {
+            mbc5: Mbc5::new(rom, ram, false),
+            bank_orders,
+            data_orders,
+            bank_mode: 0,
+            data_mode: 0,
+        }
+    }
+}
+
+impl Mapper for BitSwappedMbc5 {
+    fn rom(&self) -> &[u8] {
+        self.mbc5.rom()
+    }
+
+    fn read_rom(&self, address: u16) -> u8 {
+        let value = self.mbc5.read_rom(address);
+        match address {
+            0x0000..=0x3FFF => value,
+            _ => reorder_bits(value, &self.data_orders[self.data_mode as usize]),
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        //the mode registers sit inside the rom bank range without switching banks
+        match address & 0xF0FF {
+            0x2000 => {
+                let bank = reorder_bits(value, &self.bank_orders[self.bank_mode as usize]);
+                self.mbc5.write_rom(address, bank);
+            }
+            0x2001 => self.data_mode = value & BIT_SWAP_MODE_MASK,
+            0x2080 => self.bank_mode = value & BIT_SWAP_MODE_MASK,
+            _ => self.mbc5.write_rom(address, value),
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        self.mbc5.read_ram(address)
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        self.mbc5.write_ram(address, value);
+    }
+
+    fn save_data(&self) -> &[u8] {
+        self.mbc5.save_data()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        self.mbc5.load_save_data(data);
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        self.mbc5.take_dirty()
+    }
+}
+
+//mbc1 style multicarts from nt: the menu picks the game through 0x5001 for the first bank,
+//0x5002 for the number of banks and 0x5003 for swapping bank bits, the game itself only
+//sees 5 bit banks
+pub struct NtOld {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rom_bank: u8,
+    base_bank: usize,
+    //0 while the menu hasn't limited it
+    bank_count: u8,
+    swapped: bool,
+    ram_enabled: bool,
+    dirty: bool,
+}
+
+impl NtOld {
+    pub fn new(rom: Vec<u8>, ram: Vec<u8>) -> NtOld {
+        NtOld {
+            rom,
+            ram,
+            rom_bank: 1,
+            base_bank: 0,
+            bank_count: 0,
+            swapped: false,
+            ram_enabled: false,
+            dirty: false,
+        }
+    }
+
+    fn switchable_bank(&self) -> usize {
+        let mut bank = (self.rom_bank & NT_OLD_BANK_MASK).max(1);
+        if self.swapped {
+            bank = reorder_bits(bank, &NT_OLD_BANK_ORDER);
+        }
+        if self.bank_count != 0 {
+            bank &= self.bank_count - 1;
+        }
+        self.base_bank + bank as usize
+    }
+}
+
+impl Mapper for NtOld {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn read_rom(&self, address: u16) -> u8 {
+        let bank = match address {
+            0x0000..=0x3FFF => self.base_bank,
+            _ => self.switchable_bank(),
+        };
+        read_rom_bank(&self.rom, bank, ROM_BANK_SIZE, address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == NT_OLD_RAM_ENABLE_VALUE,
+            0x2000..=0x3FFF => self.rom_bank = value,
+            0x5000..=0x5FFF => match address & 3 {
+                1 => self.base_bank = (value & NT_OLD_BASE_MASK) as usize * 2,
+                2 => {
+                    self.bank_count = match value & 0x0F {
+                        0x0 => 32,
+                        0x8 => 16,
+                        0xC => 8,
+                        0xE => 4,
+                        0xF => 2,
+                        _ => self.bank_count,
+                    }
+                }
+                3 => self.swapped = value & NT_OLD_SWAP_FLAG != 0,
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        match banked_ram_offset(&self.ram, 0, address) {
+            Some(offset) => self.ram[offset],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        if let Some(offset) = banked_ram_offset(&self.ram, 0, address) {
+            self.ram[offset] = value;
+            self.dirty = true;
+        }
+    }
+
+    fn save_data(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{NINTENDO_LOGO, RAM_BANK_SIZE};
+
+    //every 16KB bank starts with its own number
+    fn rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * ROM_BANK_SIZE];
+        for bank in 0..banks {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        rom
+    }
+
+    #[test]
+    fn wisdom_tree_banks_from_the_address() {
+        let mut wisdom_tree = WisdomTree::new(rom(8));
+        assert_eq!(wisdom_tree.read_rom(0x4000), 1);
+        wisdom_tree.write_rom(0x0002, 0xFF);
+        assert_eq!(wisdom_tree.read_rom(0x0000), 4);
+        assert_eq!(wisdom_tree.read_rom(0x4000), 5);
+        //not a register
+        wisdom_tree.write_rom(0x4001, 0x00);
+        assert_eq!(wisdom_tree.read_rom(0x0000), 4);
+    }
+
+    #[test]
+    fn sachen_shows_the_logo_until_unlocked() {
+        let mut rom = rom(4);
+        for (i, &byte) in NINTENDO_LOGO.iter().enumerate() {
+            let address = unscramble_sachen((0x104 + i) as u16 | SACHEN_LOGO_REDIRECT);
+            rom[address as usize] = byte;
+        }
+        rom[unscramble_sachen(0x104) as usize] = 0x12;
+        let sachen = Sachen::new(rom.clone(), false);
+        let logo = (0..NINTENDO_LOGO.len())
+            .map(|i| sachen.read_rom(0x104 + i as u16))
+            .collect::<Vec<_>>();
+        assert_eq!(logo, NINTENDO_LOGO);
+        //peeks don't count
+        for _ in 0..SACHEN_LOCKED_READS {
+            assert_eq!(sachen.peek_rom(0x104), NINTENDO_LOGO[0]);
+        }
+        for _ in logo.len()..SACHEN_LOCKED_READS as usize {
+            sachen.read_rom(0x100);
+        }
+        assert_eq!(sachen.read_rom(0x104), 0x12);
+        //mmc2 only counts the reads
+        let sachen = Sachen::new(rom, true);
+        assert_eq!(sachen.read_rom(0x104), 0x12);
+    }
+
+    #[test]
+    fn sachen_registers_need_unlocking() {
+        let mut sachen = Sachen::new(rom(16), false);
+        sachen.write_rom(0x0000, 0x08);
+        sachen.write_rom(0x4000, 0x0C);
+        assert_eq!(sachen.read_rom(0x0000), 0);
+        sachen.write_rom(0x2000, SACHEN_UNLOCK_BITS);
+        sachen.write_rom(0x0000, 0x08);
+        sachen.write_rom(0x4000, 0x0C);
+        //the mask keeps bits 2-3 from the base bank
+        assert_eq!(sachen.read_rom(0x0000), 0x08);
+        sachen.write_rom(0x2000, 0x03);
+        assert_eq!(sachen.read_rom(0x4000), 0x0B);
+    }
+
+    #[test]
+    fn li_cheng_ignores_bank_writes_above_0x2100() {
+        let mut li_cheng = LiCheng::new(rom(8), Vec::new());
+        li_cheng.write_rom(0x2100, 3);
+        assert_eq!(li_cheng.read_rom(0x4000), 3);
+        li_cheng.write_rom(0x2101, 5);
+        assert_eq!(li_cheng.read_rom(0x4000), 3);
+    }
+
+    #[test]
+    fn bit_swapped_bank_and_data() {
+        let mut rom = rom(32);
+        rom[3 * ROM_BANK_SIZE + 1] = 0b00000100;
+        let mut bbd = BitSwappedMbc5::new_bbd(rom, Vec::new());
+        //bits pass through until a mode is picked
+        bbd.write_rom(0x2000, 3);
+        assert_eq!(bbd.read_rom(0x4000), 3);
+        //mode 3 takes bank bits 0 and 1 from bits 3 and 4
+        bbd.write_rom(0x2080, 3);
+        bbd.write_rom(0x2000, 0b00011000);
+        assert_eq!(bbd.read_rom(0x4000), 3);
+        //mode 2 swaps data bits 2 and 5 in the switchable window only
+        bbd.write_rom(0x2001, 2);
+        assert_eq!(bbd.read_rom(0x4001), 0b00100000);
+        assert_eq!(bbd.read_rom(0x0000), 0);
+    }
+
+    #[test]
+    fn hitek_bank_orders() {
+        let mut hitek = BitSwappedMbc5::new_hitek(rom(16), Vec::new());
+        //mode 1 reverses the low 4 bank bits
+        hitek.write_rom(0x2080, 1);
+        hitek.write_rom(0x2000, 0b00000001);
+        assert_eq!(hitek.read_rom(0x4000), 0b1000);
+    }
+
+    #[test]
+    fn nt_old_multicart() {
+        let mut nt_old = NtOld::new(rom(64), vec![0; RAM_BANK_SIZE]);
+        //game at bank 16 with 8 banks
+        nt_old.write_rom(0x5001, 8);
+        nt_old.write_rom(0x5002, 0x0C);
+        assert_eq!(nt_old.read_rom(0x0000), 16);
+        assert_eq!(nt_old.read_rom(0x4000), 17);
+        nt_old.write_rom(0x2000, 0x0B);
+        assert_eq!(nt_old.read_rom(0x4000), 16 + 3);
+        //swapping exchanges bank bits 1 and 2
+        nt_old.write_rom(0x5003, NT_OLD_SWAP_FLAG);
+        nt_old.write_rom(0x2000, 0x02);
+        assert_eq!(nt_old.read_rom(0x4000), 16 + 4);
+        nt_old.write_rom(0x0000, NT_OLD_RAM_ENABLE_VALUE);
+        nt_old.write_ram(0xA000, 0x12);
+        assert_eq!(nt_old.read_ram(0xA000), 0x12);
+    }
+}
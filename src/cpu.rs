@@ -2,6 +2,8 @@ use crate::memory::Memory;
 
 pub struct Cpu {
     pub registers: Registers,
+    //stopped by halt until an enabled interrupt is requested
+    halted: bool,
 }
 
 #[derive(Clone, Copy)]
@@ -123,11 +125,14 @@ const OPCODE_LENGTHS_CB: [u8; 256] = [
     2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
 ];
 
-const OPCODE_REGISTERS: [char; 8] = ['b', 'c', 'd', 'e', 'h', 'l', 'n', 'a'];
 const ZERO_FLAG: u8 = 0b10000000;
 const SUBTRACT_FLAG: u8 = 0b01000000;
 const HALF_CARRY_FLAG: u8 = 0b00100000;
 const CARRY_FLAG: u8 = 0b00010000;
+const INTERRUPT_FLAG: u16 = 0xFF0F;
+const INTERRUPT_ENABLE: u16 = 0xFFFF;
+//vblank, lcd, timer, serial and joypad
+const INTERRUPT_MASK: u8 = 0b00011111;
 
 impl Cpu {
     pub(crate) fn new() -> Cpu {
@@ -141,6 +146,7 @@ impl Cpu {
                 pc: 0,
                 ime: 0,
             },
+            halted: false,
         }
     }
     //load instructions
@@ -165,8 +171,8 @@ impl Cpu {
         let mut srcmem = false;
         let mut dest = dest;
         let mut source = source;
-        let mut temp = String::new();
-        let mut temp2 = String::new();
+        let temp;
+        let temp2;
         if dest.contains("(") {
             dstmem = true;
 
@@ -243,7 +249,7 @@ impl Cpu {
         );
     }
 
-    fn ldh_a_n(&mut self, mem: &mut Memory) {}
+    fn ldh_a_n(&mut self, _mem: &mut Memory) {}
 
     fn ldh_c_a(&mut self, mem: &mut Memory) {
         let value = self.registers.read_8('a');
@@ -268,7 +274,7 @@ impl Cpu {
         let mut value = self.registers.read_16(reg);
         //if regs is af then the last 4 bits are 0
         if reg == "af" {
-            value = value ^ 0b0000000000001111;
+            value ^= 0b0000000000001111;
         }
         self.registers
             .write_16("sp", self.registers.clone().read_16("sp") - 2);
@@ -276,7 +282,7 @@ impl Cpu {
     }
 
     // incr and decr
-    fn inc_r(&mut self, mem: &mut Memory, reg: &str) {
+    fn inc_r(&mut self, _mem: &mut Memory, reg: &str) {
         //if reg have 1 char, it's a 8 bit register
         if reg.len() == 1 {
             let value = self.registers.read_8(reg.chars().next().unwrap());
@@ -288,7 +294,7 @@ impl Cpu {
         }
     }
 
-    fn dec_r(&mut self, mem: &mut Memory, reg: &str) {
+    fn dec_r(&mut self, _mem: &mut Memory, reg: &str) {
         //if reg have 1 char, it's a 8 bit register
         if reg.len() == 1 {
             let value = self.registers.read_8(reg.chars().next().unwrap());
@@ -312,7 +318,7 @@ impl Cpu {
 
     //rotate and shift
     fn rlca(&mut self) {
-        let value = self.registers.read_8('a').clone();
+        let value = self.registers.read_8('a');
         let msb = value & 0x80;
         let new_value = (value << 1) | msb;
         self.registers.write_8('a', new_value);
@@ -358,7 +364,7 @@ impl Cpu {
         self.registers.write_8('f', flags);
     }
 
-    fn rra(&mut self, mem: &mut Memory) {
+    fn rra(&mut self, _mem: &mut Memory) {
         let value = self.registers.read_8('a');
         let lsb = value & 0x01;
         let new_value = (value >> 1) | ((self.registers.read_8('f') & CARRY_FLAG) << 3);
@@ -375,7 +381,7 @@ impl Cpu {
     }
 
     //arithmetic and logic
-    fn add_hl(&mut self, mem: &mut Memory, reg: &str) {
+    fn add_hl(&mut self, _mem: &mut Memory, reg: &str) {
         let value = self.registers.read_16(reg);
         let hl = self.registers.read_16("hl");
         let result: u32 = value as u32 + hl as u32;
@@ -420,7 +426,7 @@ impl Cpu {
         }
         //set Z flag if result is 0
         flags &= !ZERO_FLAG;
-        if result as u8 == 0 {
+        if result == 0 {
             flags |= ZERO_FLAG;
         }
         self.registers.write_8('f', flags);
@@ -444,7 +450,7 @@ impl Cpu {
         }
         //set Z flag if result is 0
         flags &= !ZERO_FLAG;
-        if result as u8 == 0 {
+        if result == 0 {
             flags |= ZERO_FLAG;
         }
         self.registers.write_8('f', flags);
@@ -499,7 +505,7 @@ impl Cpu {
         }
         //set Z flag if result is 0
         flags &= !ZERO_FLAG;
-        if result as u8 == 0 {
+        if result == 0 {
             flags |= ZERO_FLAG;
         }
         self.registers.write_8('f', flags);
@@ -557,7 +563,7 @@ impl Cpu {
         }
         //set Z flag if result is 0
         flags &= !ZERO_FLAG;
-        if result as u8 == 0 {
+        if result == 0 {
             flags |= ZERO_FLAG;
         }
         self.registers.write_8('f', flags);
@@ -581,7 +587,7 @@ impl Cpu {
         }
         //set Z flag if result is 0
         flags &= !ZERO_FLAG;
-        if result as u8 == 0 {
+        if result == 0 {
             flags |= ZERO_FLAG;
         }
         self.registers.write_8('f', flags);
@@ -608,7 +614,7 @@ impl Cpu {
         }
         //set Z flag if result is 0
         flags &= !ZERO_FLAG;
-        if result as u8 == 0 {
+        if result == 0 {
             flags |= ZERO_FLAG;
         }
         self.registers.write_8('f', flags);
@@ -634,13 +640,13 @@ impl Cpu {
         }
         //set Z flag if result is 0
         flags &= !ZERO_FLAG;
-        if result as u8 == 0 {
+        if result == 0 {
             flags |= ZERO_FLAG;
         }
         self.registers.write_8('f', flags);
     }
 
-    fn daa(&mut self, mem: &mut Memory) {
+    fn daa(&mut self, _mem: &mut Memory) {
         let mut value = self.registers.read_8('a');
         let mut flags = self.registers.read_8('f');
         let mut carry = flags & CARRY_FLAG;
@@ -701,7 +707,7 @@ impl Cpu {
         flags &= !CARRY_FLAG;
         //set Z flag if result is 0
         flags &= !ZERO_FLAG;
-        if result as u8 == 0 {
+        if result == 0 {
             flags |= ZERO_FLAG;
         }
         self.registers.write_8('f', flags);
@@ -742,7 +748,7 @@ impl Cpu {
         flags &= !CARRY_FLAG;
         //set Z flag if result is 0
         flags &= !ZERO_FLAG;
-        if result as u8 == 0 {
+        if result == 0 {
             flags |= ZERO_FLAG;
         }
         self.registers.write_8('f', flags);
@@ -783,7 +789,7 @@ impl Cpu {
         flags &= !CARRY_FLAG;
         //set Z flag if result is 0
         flags &= !ZERO_FLAG;
-        if result as u8 == 0 {
+        if result == 0 {
             flags |= ZERO_FLAG;
         }
         self.registers.write_8('f', flags);
@@ -827,7 +833,7 @@ impl Cpu {
         }
         //set Z flag if result is 0
         flags &= !ZERO_FLAG;
-        if result as u8 == 0 {
+        if result == 0 {
             flags |= ZERO_FLAG;
         }
         self.registers.write_8('f', flags);
@@ -850,7 +856,7 @@ impl Cpu {
         }
         //set Z flag if result is 0
         flags &= !ZERO_FLAG;
-        if result as u8 == 0 {
+        if result == 0 {
             flags |= ZERO_FLAG;
         }
         self.registers.write_8('f', flags);
@@ -871,6 +877,10 @@ impl Cpu {
         //do nothing
     }
 
+    fn halt(&mut self) {
+        self.halted = true;
+    }
+
     fn stop(&mut self) {
         //stop Cpu until button pressed
     }
@@ -1049,7 +1059,7 @@ impl Cpu {
         flags &= !SUBTRACT_FLAG;
         //set Z flag if result is 0
         flags &= !ZERO_FLAG;
-        let result = (value >> 4) | (value << 4);
+        let result = value.rotate_left(4);
         if result == 0 {
             flags |= ZERO_FLAG;
         }
@@ -1106,36 +1116,34 @@ impl Cpu {
         self.write_mem_or_regs(mem, &reg, result);
     }
 
-    fn call_CB(&mut self, mem: &mut Memory) {
-        //TODO change name
+    fn call_cb(&mut self, mem: &mut Memory) {
         const REG_NAMES: [&str; 8] = ["b", "c", "d", "e", "h", "l", "(hl)", "a"];
         //TODO implement CB instructions
         let cb_opcode = mem.read_8(self.registers.read_16("pc") + 1);
         match cb_opcode {
-            0x00..0x07 => self.rlc_r(mem, REG_NAMES[(cb_opcode & 0x0f) as usize]),
-            0x08..0x0F => self.rrc_r(mem, REG_NAMES[(cb_opcode & 0x0f) as usize]),
-            0x10..0x17 => self.rl_r(mem, REG_NAMES[(cb_opcode & 0x0f) as usize]),
-            0x18..0x1F => self.rr_r(mem, REG_NAMES[(cb_opcode & 0x0f) as usize]),
-            0x20..0x27 => self.sla_r(mem, REG_NAMES[(cb_opcode & 0x0f) as usize]),
-            0x28..0x2F => self.sra_r(mem, REG_NAMES[(cb_opcode & 0x0f) as usize]),
-            0x30..0x37 => self.swap_r(mem, REG_NAMES[(cb_opcode & 0x0f) as usize]),
-            0x38..0x3F => self.srl_r(mem, REG_NAMES[(cb_opcode & 0x0f) as usize]),
-            0x40..0x7F => self.bit_n_r(
+            0x00..=0x07 => self.rlc_r(mem, REG_NAMES[(cb_opcode & 0x07) as usize]),
+            0x08..=0x0F => self.rrc_r(mem, REG_NAMES[(cb_opcode & 0x07) as usize]),
+            0x10..=0x17 => self.rl_r(mem, REG_NAMES[(cb_opcode & 0x07) as usize]),
+            0x18..=0x1F => self.rr_r(mem, REG_NAMES[(cb_opcode & 0x07) as usize]),
+            0x20..=0x27 => self.sla_r(mem, REG_NAMES[(cb_opcode & 0x07) as usize]),
+            0x28..=0x2F => self.sra_r(mem, REG_NAMES[(cb_opcode & 0x07) as usize]),
+            0x30..=0x37 => self.swap_r(mem, REG_NAMES[(cb_opcode & 0x07) as usize]),
+            0x38..=0x3F => self.srl_r(mem, REG_NAMES[(cb_opcode & 0x07) as usize]),
+            0x40..=0x7F => self.bit_n_r(
                 mem,
-                REG_NAMES[(cb_opcode & 0x0f) as usize],
+                REG_NAMES[(cb_opcode & 0x07) as usize],
                 (cb_opcode >> 3) & 0x07,
             ),
-            0x80..0xBF => self.res_n_r(
+            0x80..=0xBF => self.res_n_r(
                 mem,
-                REG_NAMES[(cb_opcode & 0x0f) as usize],
-                (cb_opcode >> 4) & 0x07,
+                REG_NAMES[(cb_opcode & 0x07) as usize],
+                (cb_opcode >> 3) & 0x07,
             ),
-            0xC0..0xFF => self.set_n_r(
+            0xC0..=0xFF => self.set_n_r(
                 mem,
-                REG_NAMES[(cb_opcode & 0x0f) as usize],
-                (cb_opcode >> 5) & 0x07,
+                REG_NAMES[(cb_opcode & 0x07) as usize],
+                (cb_opcode >> 3) & 0x07,
             ),
-            _ => panic!("Unknown CB opcode: {:x}", cb_opcode),
         }
     }
 
@@ -1150,9 +1158,9 @@ impl Cpu {
     //flow
     fn jr_e(&mut self, mem: &mut Memory) {
         //temp register
-        let tmp = self.registers.read_16("pc").clone() as i16;
+        let tmp = self.registers.read_16("pc") as i16;
         //read next byte
-        let value = mem.read_8(tmp.clone() as u16 + 1) as i8;
+        let value = mem.read_8(tmp as u16 + 1) as i8;
         self.registers.write_16("pc", (tmp + value as i16) as u16);
     }
 
@@ -1304,6 +1312,31 @@ impl Cpu {
         self.registers.write_8('i', 1);
     }
 
+    pub(crate) fn halted(&self) -> bool {
+        self.halted
+    }
+
+    //run the instruction at pc, returns the cycles it took
+    pub(crate) fn step(&mut self, mem: &mut Memory) -> u32 {
+        //halt ends once an enabled interrupt is requested, whether or not ime lets it run
+        if self.halted {
+            if mem.read_8(INTERRUPT_ENABLE) & mem.read_8(INTERRUPT_FLAG) & INTERRUPT_MASK == 0 {
+                return 4;
+            }
+            self.halted = false;
+        }
+        let pc = self.registers.read_16("pc");
+        let opcode = mem.read_8(pc);
+        let cycles = match opcode {
+            //a lookup, call_cb fetches the operand
+            0xCB => OPCODE_DURATION_CB[mem.peek_8(pc.wrapping_add(1)) as usize],
+            _ => OPCODE_DURATION[opcode as usize],
+        };
+        self.execute(opcode, mem);
+        self.handle_post_instruction(mem, opcode);
+        cycles as u32
+    }
+
     //end of Cpu
     fn execute(&mut self, opcode: u8, mem: &mut Memory) {
         const REG_NAMES: [&str; 8] = ["b", "c", "d", "e", "h", "l", "(hl)", "a"];
@@ -1384,19 +1417,20 @@ impl Cpu {
             0x3D => self.dec_r(mem, "a"),
             0x3E => self.ld_n(mem, "a"),
             0x3F => self.ccf(),
-            0x40..0x7F => self.ld_r1_r2(
+            0x76 => self.halt(),
+            0x40..=0x7F => self.ld_r1_r2(
                 mem,
                 REG_NAMES[((opcode >> 3) & 0b111) as usize],
-                REG_NAMES[(opcode & 0x0f) as usize],
+                REG_NAMES[(opcode & 0x07) as usize],
             ),
-            0x80..0x87 => self.add_a_r(mem, REG_NAMES[(opcode & 0x0f) as usize]),
-            0x88..0x8F => self.adc_a_r(mem, REG_NAMES[(opcode & 0x0f) as usize]),
-            0x90..0x97 => self.sub_a_r(mem, REG_NAMES[(opcode & 0x0f) as usize]),
-            0x98..0x9F => self.sbc_a_r(mem, REG_NAMES[(opcode & 0x0f) as usize]),
-            0xA0..0xA7 => self.and_a_r(mem, REG_NAMES[(opcode & 0x0f) as usize]),
-            0xA8..0xAF => self.xor_a_r(mem, REG_NAMES[(opcode & 0x0f) as usize]),
-            0xB0..0xB7 => self.or_a_r(mem, REG_NAMES[(opcode & 0x0f) as usize]),
-            0xB8..0xBF => self.cp_a_r(mem, REG_NAMES[(opcode & 0x0f) as usize]),
+            0x80..=0x87 => self.add_a_r(mem, REG_NAMES[(opcode & 0x07) as usize]),
+            0x88..=0x8F => self.adc_a_r(mem, REG_NAMES[(opcode & 0x07) as usize]),
+            0x90..=0x97 => self.sub_a_r(mem, REG_NAMES[(opcode & 0x07) as usize]),
+            0x98..=0x9F => self.sbc_a_r(mem, REG_NAMES[(opcode & 0x07) as usize]),
+            0xA0..=0xA7 => self.and_a_r(mem, REG_NAMES[(opcode & 0x07) as usize]),
+            0xA8..=0xAF => self.xor_a_r(mem, REG_NAMES[(opcode & 0x07) as usize]),
+            0xB0..=0xB7 => self.or_a_r(mem, REG_NAMES[(opcode & 0x07) as usize]),
+            0xB8..=0xBF => self.cp_a_r(mem, REG_NAMES[(opcode & 0x07) as usize]),
             0xC0 => self.ret_f(mem, 'z', false), //return if z flag is false
             0xC1 => self.pop(mem, "bc"),
             0xC2 => self.jp_f_nn(mem, 'z', false),
@@ -1408,7 +1442,7 @@ impl Cpu {
             0xC8 => self.ret_f(mem, 'z', true), //return if z flag is true
             0xC9 => self.ret(mem),
             0xCA => self.jp_f_nn(mem, 'z', true),
-            0xCB => self.call_CB(mem),
+            0xCB => self.call_cb(mem),
             0xCC => self.call_f_nn(mem, 'z', true),
             0xCD => self.call_nn(mem),
             0xCE => self.adc_a_n(mem),
@@ -1432,14 +1466,14 @@ impl Cpu {
             0xE0 => self.ldh_n_a(mem),
             0xE1 => self.pop(mem, "hl"),
             0xE2 => self.ldh_c_a(mem),
-            0xE3..0xE4 => unimplemented!(),
+            0xE3..=0xE4 => unimplemented!(),
             0xE5 => self.push(mem, "hl"),
             0xE6 => self.and_a_n(mem),
             0xE7 => self.rst(mem, 0x20),
             0xE8 => self.add_sp_e(mem),
             0xE9 => self.jp_hl(),
             0xEA => self.ld_nn_a(mem),
-            0xEB..0xED => unimplemented!(),
+            0xEB..=0xED => unimplemented!(),
             0xEE => self.xor_a_n(mem),
             0xEF => self.rst(mem, 0x28),
             0xF0 => self.ldh_a_n(mem),
@@ -1454,32 +1488,80 @@ impl Cpu {
             0xF9 => self.ld_sp_hl(mem),
             0xFA => self.ld_a_nn(mem),
             0xFB => self.ei(),
-            0xFC..0xFD => unimplemented!(),
+            0xFC..=0xFD => unimplemented!(),
             0xFE => self.cp_a_n(mem),
             0xFF => self.rst(mem, 0x38),
-            _ => println!("Opcode not implemented: {:2X}", opcode),
         }
     }
 
-    fn handle_post_instruction(&mut self, mem: &mut Memory, opcode: u8, mut lenght: u64) {
-        //increment pc
-        let mut delta = 0;
-        let pc = self.registers.read_16("pc").clone();
-        self.registers
-            .write_16("pc", pc + OPCODE_LENGTHS[opcode.clone() as usize] as u16);
-        lenght += OPCODE_LENGTHS[opcode.clone() as usize] as u64;
-        delta += OPCODE_LENGTHS[opcode as usize] as u64;
-        if opcode == 0xCB {
-            let pc = self.registers.read_16("pc").clone();
-            let opcode = mem.read_8(pc);
-            self.registers
-                .write_16("pc", pc + OPCODE_LENGTHS_CB[opcode.clone() as usize] as u16);
-            lenght += OPCODE_LENGTHS_CB[opcode.clone() as usize] as u64;
-            delta += OPCODE_LENGTHS_CB[opcode as usize] as u64;
-        }
+    fn handle_post_instruction(&mut self, mem: &mut Memory, opcode: u8) {
+        //increment pc, the cb lengths already count the prefix
+        let pc = self.registers.read_16("pc");
+        let length = match opcode {
+            0xCB => OPCODE_LENGTHS_CB[mem.peek_8(pc.wrapping_add(1)) as usize],
+            _ => OPCODE_LENGTHS[opcode as usize],
+        };
+        self.registers.write_16("pc", pc + length as u16);
 
         //handle interrupts
-        //draw delta pixels if vblank
         //handle stuff
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODE: u16 = 0xC000;
+    const VBLANK: u8 = 0b00000001;
+    const TIMER: u8 = 0b00000100;
+
+    //cpu about to run code from work ram
+    fn cpu(mem: &mut Memory, code: &[u8]) -> Cpu {
+        for (i, &byte) in code.iter().enumerate() {
+            mem.write_8(CODE + i as u16, byte);
+        }
+        let mut cpu = Cpu::new();
+        cpu.registers.write_16("pc", CODE);
+        cpu
+    }
+
+    #[test]
+    fn halt_waits_for_an_enabled_interrupt() {
+        let mut mem = Memory::new();
+        //halt, nop
+        let mut cpu = cpu(&mut mem, &[0x76, 0x00]);
+        mem.write_8(INTERRUPT_ENABLE, VBLANK);
+        cpu.step(&mut mem);
+        assert!(cpu.halted());
+        assert_eq!(cpu.registers.read_16("pc"), CODE + 1);
+
+        //a disabled interrupt doesn't wake it
+        mem.write_8(INTERRUPT_FLAG, TIMER);
+        assert_eq!(cpu.step(&mut mem), 4);
+        assert!(cpu.halted());
+        assert_eq!(cpu.registers.read_16("pc"), CODE + 1);
+
+        //with ime off the cpu just carries on after the halt
+        mem.write_8(INTERRUPT_FLAG, VBLANK);
+        cpu.step(&mut mem);
+        assert!(!cpu.halted());
+        assert_eq!(cpu.registers.read_16("pc"), CODE + 2);
+    }
+
+    #[test]
+    fn register_from_the_low_three_bits() {
+        let mut mem = Memory::new();
+        //set 7,a; set 0,a; res 7,a; ld c,a
+        let mut cpu = cpu(&mut mem, &[0xCB, 0xFF, 0xCB, 0xC7, 0xCB, 0xBF, 0x4F]);
+        cpu.step(&mut mem);
+        assert_eq!(cpu.registers.read_8('a'), 0x80);
+        cpu.step(&mut mem);
+        assert_eq!(cpu.registers.read_8('a'), 0x81);
+        cpu.step(&mut mem);
+        assert_eq!(cpu.registers.read_8('a'), 0x01);
+        assert_eq!(cpu.registers.read_16("pc"), CODE + 6);
+        cpu.step(&mut mem);
+        assert_eq!(cpu.registers.read_8('c'), 0x01);
+    }
+}
@@ -0,0 +1,89 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::ppu::{Ppu, GREYSCALE, PIXEL_HEIGHT, PIXEL_WIDTH};
+
+//write the last finished frame to path, the extension picks the format: .ppm for a binary
+//portable pixmap any image viewer opens, .rgb565 for raw little endian 16 bit pixels the way
+//small lcds take them, dmg shades come out grey
+pub fn write_frame(ppu: &Ppu, path: &Path) -> io::Result<()> {
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    let data = match extension.map(str::to_ascii_lowercase).as_deref() {
+        Some("ppm") => ppm(ppu),
+        Some("rgb565") => rgb565(ppu),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unknown frame format, expected .ppm or .rgb565",
+            ))
+        }
+    };
+    fs::write(path, data)
+}
+
+fn ppm(ppu: &Ppu) -> Vec<u8> {
+    let mut data = format!("P6\n{} {}\n255\n", PIXEL_WIDTH, PIXEL_HEIGHT).into_bytes();
+    //ppm has no alpha channel
+    data.extend(
+        ppu.frame_rgba8888(&GREYSCALE)
+            .chunks(4)
+            .flat_map(|pixel| &pixel[..3]),
+    );
+    data
+}
+
+fn rgb565(ppu: &Ppu) -> Vec<u8> {
+    ppu.frame_rgb565(&GREYSCALE)
+        .into_iter()
+        .flat_map(u16::to_le_bytes)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::PIXEL_SIZE;
+    use std::env;
+    use std::path::PathBuf;
+
+    fn test_dir(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("dump-test-{}-{}", test, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn ppm_of_a_blank_frame() {
+        let dir = test_dir("ppm");
+        let path = dir.join("frame.PPM");
+        write_frame(&Ppu::new(), &path).unwrap();
+        let data = fs::read(&path).unwrap();
+        let header = b"P6\n160 144\n255\n";
+        assert_eq!(&data[..header.len()], header);
+        assert_eq!(data.len(), header.len() + PIXEL_SIZE as usize * 3);
+        assert!(data[header.len()..].iter().all(|&byte| byte == 0xFF));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rgb565_of_a_blank_frame() {
+        let dir = test_dir("rgb565");
+        let path = dir.join("frame.rgb565");
+        write_frame(&Ppu::new(), &path).unwrap();
+        let data = fs::read(&path).unwrap();
+        assert_eq!(data.len(), PIXEL_SIZE as usize * 2);
+        assert!(data.iter().all(|&byte| byte == 0xFF));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn unknown_format() {
+        let dir = test_dir("unknown");
+        let path = dir.join("frame.png");
+        let error = write_frame(&Ppu::new(), &path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(!path.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
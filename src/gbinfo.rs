@@ -0,0 +1,343 @@
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::cartridge::header::{CartridgeHeader, CgbSupport, Destination};
+use crate::cartridge::{self, MapperKind};
+use crate::rom;
+
+const USAGE: &str = "usage: gbinfo [--json] <rom>...";
+
+//what gets reported for one file
+struct RomInfo {
+    path: String,
+    file_size: usize,
+    header: CartridgeHeader,
+    mapper: MapperKind,
+}
+
+//gbinfo [--json] <rom>...: print the header of every rom without booting it,
+//returns the process exit code, 1 when any file couldn't be read or parsed
+pub fn run(args: &[String]) -> i32 {
+    let json = args.iter().any(|arg| arg == "--json");
+    let paths = args
+        .iter()
+        .filter(|arg| !arg.starts_with("--"))
+        .collect::<Vec<_>>();
+    if paths.is_empty() {
+        eprintln!("{}", USAGE);
+        return 2;
+    }
+
+    let reports = paths
+        .iter()
+        .map(|path| (path.as_str(), inspect(path)))
+        .collect::<Vec<_>>();
+    if json {
+        print!("{}", to_json(&reports));
+    } else {
+        for (i, (path, report)) in reports.iter().enumerate() {
+            if i > 0 {
+                println!();
+            }
+            match report {
+                Ok(info) => print!("{}", to_text(info)),
+                Err(error) => eprintln!("{}: {}", path, error),
+            }
+        }
+    }
+
+    match reports.iter().any(|(_, report)| report.is_err()) {
+        true => 1,
+        false => 0,
+    }
+}
+
+fn inspect(path: &str) -> Result<RomInfo, String> {
+    let rom = rom::load(Path::new(path), None).map_err(|error| error.to_string())?;
+    let header = CartridgeHeader::parse(&rom).map_err(|error| error.to_string())?;
+    Ok(RomInfo {
+        path: path.to_string(),
+        file_size: rom.len(),
+        mapper: cartridge::detect(&rom, header.cartridge_type.code),
+        header,
+    })
+}
+
+fn cgb_name(cgb: CgbSupport) -> &'static str {
+    match cgb {
+        CgbSupport::None => "none",
+        CgbSupport::Enhanced => "enhanced",
+        CgbSupport::Only => "only",
+    }
+}
+
+fn destination_name(destination: Destination) -> &'static str {
+    match destination {
+        Destination::Japan => "japan",
+        Destination::Overseas => "overseas",
+        Destination::Unknown(_) => "unknown",
+    }
+}
+
+fn validity(valid: bool) -> &'static str {
+    match valid {
+        true => "ok",
+        false => "bad",
+    }
+}
+
+fn to_text(info: &RomInfo) -> String {
+    let header = &info.header;
+    let c_type = &header.cartridge_type;
+    let mut features = Vec::new();
+    for (present, name) in [
+        (c_type.ram, "ram"),
+        (c_type.battery, "battery"),
+        (c_type.timer, "timer"),
+        (c_type.rumble, "rumble"),
+    ] {
+        if present {
+            features.push(name);
+        }
+    }
+    let rom_size = match header.rom_size.bytes() {
+        Some(bytes) => bytes.to_string(),
+        None => "unknown".to_string(),
+    };
+
+    let mut out = String::new();
+    let _ = writeln!(out, "{}", info.path);
+    let _ = writeln!(out, "  title           : {}", header.title);
+    let _ = writeln!(
+        out,
+        "  mapper          : {} (type {:02X}{}{})",
+        info.mapper.name(),
+        c_type.code,
+        if features.is_empty() { "" } else { ", " },
+        features.join(", ")
+    );
+    let _ = writeln!(
+        out,
+        "  rom size        : {} bytes, file is {} bytes",
+        rom_size, info.file_size
+    );
+    let _ = writeln!(out, "  ram size        : {} bytes", header.ram_size.bytes());
+    let _ = writeln!(out, "  cgb             : {}", cgb_name(header.cgb));
+    let _ = writeln!(
+        out,
+        "  sgb             : {}",
+        if header.sgb { "yes" } else { "no" }
+    );
+    let _ = writeln!(out, "  licensee        : {}", header.licensee.name());
+    let _ = writeln!(
+        out,
+        "  destination     : {}",
+        destination_name(header.destination)
+    );
+    let _ = writeln!(out, "  version         : {}", header.mask_rom_version);
+    let _ = writeln!(out, "  logo            : {}", validity(header.logo_valid()));
+    let _ = writeln!(
+        out,
+        "  header checksum : {:02X} {}",
+        header.header_checksum,
+        validity(header.header_checksum_valid())
+    );
+    let _ = writeln!(
+        out,
+        "  global checksum : {:04X} {}",
+        header.global_checksum,
+        validity(header.global_checksum_valid())
+    );
+    out
+}
+
+//one object per file in an array, files that failed only carry their path and the error
+fn to_json(reports: &[(&str, Result<RomInfo, String>)]) -> String {
+    let mut out = String::from("[\n");
+    for (i, (path, report)) in reports.iter().enumerate() {
+        let object = match report {
+            Ok(info) => json_info(info),
+            Err(error) => format!(
+                "{{\"path\": {}, \"error\": {}}}",
+                json_string(path),
+                json_string(error)
+            ),
+        };
+        let separator = if i + 1 < reports.len() { "," } else { "" };
+        let _ = writeln!(out, "  {}{}", object, separator);
+    }
+    out.push_str("]\n");
+    out
+}
+
+fn json_info(info: &RomInfo) -> String {
+    let header = &info.header;
+    let c_type = &header.cartridge_type;
+    let rom_size = match header.rom_size.bytes() {
+        Some(bytes) => bytes.to_string(),
+        None => "null".to_string(),
+    };
+    let fields = [
+        ("path", json_string(&info.path)),
+        ("title", json_string(&header.title)),
+        ("mapper", json_string(info.mapper.name())),
+        ("cartridge_type", c_type.code.to_string()),
+        ("ram", c_type.ram.to_string()),
+        ("battery", c_type.battery.to_string()),
+        ("timer", c_type.timer.to_string()),
+        ("rumble", c_type.rumble.to_string()),
+        ("rom_size", rom_size),
+        ("file_size", info.file_size.to_string()),
+        ("ram_size", header.ram_size.bytes().to_string()),
+        ("cgb", json_string(cgb_name(header.cgb))),
+        ("sgb", header.sgb.to_string()),
+        ("licensee", json_string(header.licensee.name())),
+        (
+            "destination",
+            json_string(destination_name(header.destination)),
+        ),
+        ("version", header.mask_rom_version.to_string()),
+        ("logo_valid", header.logo_valid().to_string()),
+        ("header_checksum", header.header_checksum.to_string()),
+        (
+            "header_checksum_valid",
+            header.header_checksum_valid().to_string(),
+        ),
+        ("global_checksum", header.global_checksum.to_string()),
+        (
+            "global_checksum_valid",
+            header.global_checksum_valid().to_string(),
+        ),
+    ];
+    let body = fields
+        .iter()
+        .map(|(key, value)| format!("\"{}\": {}", key, value))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("{{{}}}", body)
+}
+
+fn json_string(value: &str) -> String {
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::header::{self, C_TYPE, HEADER_CHECKSUM, LOGO_START, TITLE_START};
+    use crate::cartridge::NINTENDO_LOGO;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn rom(title: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[LOGO_START..LOGO_START + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        rom[TITLE_START..TITLE_START + title.len()].copy_from_slice(title);
+        rom[C_TYPE] = 0x03;
+        rom[HEADER_CHECKSUM] = header::header_checksum(&rom);
+        rom
+    }
+
+    fn info(title: &[u8]) -> RomInfo {
+        let rom = rom(title);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        RomInfo {
+            path: "game.gb".to_string(),
+            file_size: rom.len(),
+            mapper: cartridge::detect(&rom, header.cartridge_type.code),
+            header,
+        }
+    }
+
+    fn test_dir(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("gbinfo-test-{}-{}", test, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn json_escaping() {
+        assert_eq!(json_string("plain"), "\"plain\"");
+        assert_eq!(json_string("a\"b\\c"), "\"a\\\"b\\\\c\"");
+        assert_eq!(json_string("\n\r\t"), "\"\\n\\r\\t\"");
+        assert_eq!(json_string("\u{1}\u{1F}"), "\"\\u0001\\u001f\"");
+        assert_eq!(json_string("caf\u{E9}"), "\"caf\u{E9}\"");
+    }
+
+    #[test]
+    fn json_of_a_title_with_control_bytes() {
+        let json = json_info(&info(b"A\"\\\x01"));
+        assert!(json.contains("\"title\": \"A\\\"\\\\\\u0001\""));
+    }
+
+    #[test]
+    fn json_shape() {
+        let reports = [
+            ("game.gb", Ok(info(b"GAME"))),
+            ("bad.gb", Err("too short".to_string())),
+        ];
+        let json = to_json(&reports);
+        let lines = json.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "[");
+        assert!(lines[1].starts_with("  {\"path\": \"game.gb\", \"title\": \"GAME\""));
+        assert!(lines[1].contains("\"mapper\": \"mbc1\", \"cartridge_type\": 3"));
+        assert!(lines[1].contains("\"rom_size\": 32768"));
+        assert!(lines[1].contains("\"logo_valid\": true"));
+        assert!(lines[1].ends_with("},"));
+        assert_eq!(
+            lines[2],
+            "  {\"path\": \"bad.gb\", \"error\": \"too short\"}"
+        );
+        assert_eq!(lines[3], "]");
+    }
+
+    #[test]
+    fn text_shape() {
+        let text = to_text(&info(b"GAME"));
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "game.gb");
+        assert_eq!(lines[1], "  title           : GAME");
+        assert_eq!(lines[2], "  mapper          : mbc1 (type 03, ram, battery)");
+        assert_eq!(
+            lines[3],
+            "  rom size        : 32768 bytes, file is 32768 bytes"
+        );
+        assert_eq!(lines[10], "  logo            : ok");
+        assert!(lines[12].ends_with(" bad"));
+    }
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(run(&[]), 2);
+        assert_eq!(run(&["--json".to_string()]), 2);
+
+        let dir = test_dir("exit");
+        let good = dir.join("good.gb");
+        fs::write(&good, rom(b"GAME")).unwrap();
+        let short = dir.join("short.gb");
+        fs::write(&short, [0; 0x100]).unwrap();
+        let path = |path: &PathBuf| path.display().to_string();
+        assert_eq!(run(&[path(&good)]), 0);
+        assert_eq!(run(&["--json".to_string(), path(&good)]), 0);
+        assert_eq!(run(&[path(&good), path(&short)]), 1);
+        assert_eq!(run(&[path(&dir.join("missing.gb"))]), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,130 +1,344 @@
-#![feature(exclusive_range_pattern)]
 #![allow(clippy::unused_io_amount)]
 extern crate bitintr;
-use bitintr::*;
 
+mod cartridge;
 mod cpu;
+mod dump;
+mod gbinfo;
 mod memory;
+mod mkpatch;
+mod model;
+mod patch;
 mod ppu;
+mod rom;
+mod save;
 
-use crate::memory::Memory;
+use crate::cartridge::header::CartridgeHeader;
+use crate::cartridge::MapperKind;
+use crate::memory::{Memory, BOOT_ROM_SIZE, CGB_BOOT_ROM_SIZE};
+use crate::model::{Model, DEFAULT_PREFERENCE};
+use crate::ppu::{Ppu, Renderer};
+use crate::save::SaveFile;
 use cpu::Cpu;
-use std::fs::File;
-use std::io::Read;
-use std::mem;
-use std::ptr::null_mut;
-
-struct CartridgeHeader {
-    title: [char; 16],
-    logo: [char; 48],
-    c_type: u8,
-    rom_size: u8,
-    ram_size: u8,
-    destination: u8,
-    old_licensee: u8,
-    mask_rom_version: u8,
-    header_checksum: u8,
-    global_checksum: [u8; 2],
-}
-
-const LCDC_REGISTER: u16 = 0xFF40;
-//define bitmask for each flag to access them through and operation
-const LCD_ENABLE: u8 = 0b10000000;
-const WINDOW_TILE_MAP: u8 = 0b01000000;
-const WINDOW_ENABLE: u8 = 0b00100000;
-const BG_AND_WINDOW_TILE_DATA: u8 = 0b00010000;
-const BG_TILE_MAP: u8 = 0b00001000;
-const OBJ_ENABLE: u8 = 0b00000100;
-const OBJ_SIZE: u8 = 0b00000010;
-const BG_AND_WINDOW_TILE_MAP: u8 = 0b00000001;
-
-const STAT: u16 = 0xFF41;
-const MODE_FLAG: u8 = 0b00000011;
-const LYC_FLAG: u8 = 0b00000100;
-const HBLANK_FLAG: u8 = 0b00001000;
-const VBLANK_FLAG: u8 = 0b00010000;
-const OAM_FLAG: u8 = 0b00100000;
-const LYC_INTERRUPT: u8 = 0b01000000;
-const PIXEL_WIDTH: u16 = 160;
-const PIXEL_HEIGHT: u16 = 144;
-const PIXEL_SIZE: u16 = PIXEL_WIDTH * PIXEL_HEIGHT;
-
-const INTERRUPT_ENABLE: u16 = 0xFFFF;
-const INTERRUPT_FLAG: u16 = 0xFF0F;
-const VBLANK_INTERRUPT: u8 = 0b00000001;
-const LCD_INTERRUPT: u8 = 0b00000010;
-const TIMER_INTERRUPT: u8 = 0b00000100;
-const SERIAL_INTERRUPT: u8 = 0b00001000;
-const JOYPAD_INTERRUPT: u8 = 0b00010000;
-
-type Sprite = [u8; 4];
-
-const ZERO_FLAG: u8 = 0b10000000;
-const SUBTRACT_FLAG: u8 = 0b01000000;
-const HALF_CARRY_FLAG: u8 = 0b00100000;
-const CARRY_FLAG: u8 = 0b00010000;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 fn main() {
-    println!("Hello, world!");
-    let mut header = CartridgeHeader {
-        title: [' '; 16],
-        logo: [' '; 48],
-        c_type: 0,
-        rom_size: 0,
-        ram_size: 0,
-        destination: 0,
-        old_licensee: 0,
-        mask_rom_version: 0,
-        header_checksum: 0,
-        global_checksum: [0; 2],
-    };
+    //gbinfo [--json] <rom>... prints the headers and exits without booting anything
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.get(1).map(String::as_str) == Some("gbinfo") {
+        std::process::exit(gbinfo::run(&args[2..]));
+    }
+    //mkpatch <original> <modified> <patch> writes an ips or bps between two roms
+    if args.get(1).map(String::as_str) == Some("mkpatch") {
+        std::process::exit(mkpatch::run(&args[2..]));
+    }
     let mut cpu = Cpu::new();
-
-    cpu.registers.write_16("af", 0x01B0);
-    cpu.registers.write_16("bc", 0x0112);
-    println!("af : {:X}", cpu.registers.read_16("bc"));
-    //load Rom.gb to Rom buffer
-
     let mut mem: Memory = Memory::new();
-    //;load Rom to Rom buffer
-    let mut file = File::open("rom.gb").unwrap();
-    #[allow(clippy::unused_io_amount)]
-    file.read(&mut mem.rom.buffer).unwrap();
+
+    //;load Rom to Rom buffer, first argument that isn't an option, zipped or gzipped roms work too
+    let rom_path = args
+        .iter()
+        .skip(1)
+        .find(|arg| !arg.starts_with("--"))
+        .map(Path::new)
+        .unwrap_or(Path::new("rom.gb"));
+    let entry = args.iter().find_map(|arg| arg.strip_prefix("--entry="));
+    let mut rom = match rom::load(rom_path, entry) {
+        Ok(rom) => rom,
+        Err(error) => {
+            eprintln!("{}: {}", rom_path.display(), error);
+            std::process::exit(1);
+        }
+    };
+    //--save-patch=<file> writes the rom the cartridge ends up with as one .ips or .bps against
+    //the rom file on exit, folding in the patches applied below
+    let save_patch = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--save-patch="))
+        .map(|path| match mkpatch::Format::from_path(Path::new(path)) {
+            Some(format) => (Path::new(path), format, rom.clone()),
+            None => {
+                eprintln!("--save-patch: {} is not a .ips or .bps file", path);
+                std::process::exit(1);
+            }
+        });
+    //patches given with --patch=<file> are applied in order, otherwise a .bps, .ups or .ips
+    //with the rom's name is picked up, the header below is parsed from the patched rom
+    let named = args
+        .iter()
+        .filter_map(|arg| arg.strip_prefix("--patch="))
+        .map(PathBuf::from)
+        .collect();
+    for patch_path in patch::patches_for(rom_path, named) {
+        let patched = fs::read(&patch_path)
+            .map_err(|error| error.to_string())
+            .and_then(|data| patch::apply(&rom, &data).map_err(|error| error.to_string()));
+        match patched {
+            Ok(patched) => rom = patched,
+            Err(error) => {
+                eprintln!("{}: {}", patch_path.display(), error);
+                std::process::exit(1);
+            }
+        }
+        println!("patch : {}", patch_path.display());
+    }
     //load Rom header
-    for i in 0..16 {
-        header.title[i] = mem.rom.buffer[i + 0x134] as char;
-    }
-    for i in 0..48 {
-        header.logo[i] = mem.rom.buffer[0x100 + i] as char;
-    }
-    header.c_type = mem.rom.buffer[0x147];
-    header.rom_size = mem.rom.buffer[0x148];
-    header.ram_size = mem.rom.buffer[0x149];
-    header.destination = mem.rom.buffer[0x14A];
-    header.old_licensee = mem.rom.buffer[0x14B];
-    header.mask_rom_version = mem.rom.buffer[0x14C];
-    header.header_checksum = mem.rom.buffer[0x14D];
-    header.global_checksum[0] = mem.rom.buffer[0x14E];
-    header.global_checksum[1] = mem.rom.buffer[0x14F];
-    println!("title : {}", header.title.iter().collect::<String>());
-    println!("logo : {}", header.logo.iter().collect::<String>());
-    println!("c_type : {:X}", header.c_type);
-    println!("rom_size : {:X}", header.rom_size);
-    println!("ram_size : {:X}", header.ram_size);
-    println!("destination : {:X}", header.destination);
-    println!("old_licensee : {:X}", header.old_licensee);
+    let header = match CartridgeHeader::parse(&rom) {
+        Ok(header) => header,
+        Err(error) => {
+            eprintln!("{}: {}", rom_path.display(), error);
+            std::process::exit(1);
+        }
+    };
+    println!("title : {}", header.title);
+    println!("cgb : {:?}", header.cgb);
+    println!("sgb : {}", header.sgb);
+    println!("licensee : {}", header.licensee.name());
+    println!("c_type : {:X}", header.cartridge_type.code);
+    println!("rom_size : {:?}", header.rom_size);
+    println!("ram_size : {:?}", header.ram_size);
+    println!("destination : {:?}", header.destination);
     println!("mask_rom_version : {:X}", header.mask_rom_version);
     println!("header_checksum : {:X}", header.header_checksum);
-    println!("global_checksum : {:X}", header.global_checksum[0]);
-    println!("global_checksum : {:X}", header.global_checksum[1]);
+    println!("global_checksum : {:X}", header.global_checksum);
+    //real hardware refuses bad logos and header checksums, unlicensed carts still get a go
+    if let Err(error) = header.verify() {
+        println!("warning : {}", error);
+    }
+
+    //the detected mapper can be overridden with --mapper=<name>
+    let mapper = match args.iter().find_map(|arg| arg.strip_prefix("--mapper=")) {
+        Some(name) => MapperKind::from_name(name).unwrap_or_else(|| {
+            let names = MapperKind::ALL.map(|kind| kind.name());
+            eprintln!(
+                "--mapper: unknown mapper {}, expected one of {}",
+                name,
+                names.join(", ")
+            );
+            std::process::exit(1);
+        }),
+        None => cartridge::detect(&rom, header.cartridge_type.code),
+    };
+    println!("mapper : {:?}", mapper);
+    if mapper == MapperKind::Unsupported {
+        eprintln!(
+            "{}: unsupported cartridge type {:02X}, running it without a mapper, pick one with --mapper",
+            rom_path.display(),
+            header.cartridge_type.code
+        );
+    }
+    mem.set_cartridge(cartridge::new(rom, mapper, header.ram_size));
+    mem.cartridge
+        .set_rumble_callback(Box::new(|on| println!("rumble : {}", on)));
+    //--tilt=<x>,<y> holds an accelerometer cart tilted by that many g for the whole run
+    if let Some(tilt) = args.iter().find_map(|arg| arg.strip_prefix("--tilt=")) {
+        let parsed = tilt
+            .split_once(',')
+            .and_then(|(x, y)| Some((x.trim().parse().ok()?, y.trim().parse().ok()?)));
+        match parsed {
+            Some((x, y)) => mem.cartridge.set_tilt(x, y),
+            None => {
+                eprintln!("--tilt: expected <x>,<y> in g, got {}", tilt);
+                std::process::exit(1);
+            }
+        }
+    }
+    //--ir-loopback lets an infrared cart see its own led, enough for games that test the port
+    if args.iter().any(|arg| arg == "--ir-loopback") {
+        mem.cartridge
+            .set_infrared_peer(Box::new(cartridge::InfraredLoopback::default()));
+    }
+    //battery backed carts keep their save in a .sav next to the rom
+    let mut save = header
+        .cartridge_type
+        .battery
+        .then(|| SaveFile::for_rom(rom_path));
+    if let Some(file) = save.as_mut() {
+        match file.load(mem.cartridge.as_mut()) {
+            Ok(()) => println!("save : {}", file.path().display()),
+            //an unreadable save is left alone rather than overwritten with a blank one
+            Err(error) => {
+                eprintln!(
+                    "{}: {}, running without saving",
+                    file.path().display(),
+                    error
+                );
+                save = None;
+            }
+        }
+    }
+
+    //the hardware the game runs on: forced with --model=<name>, or picked from the header
+    //flags going through --models=<name,name,...> in order
+    let unknown_model = |option: &str, name: &str| -> ! {
+        let names = Model::ALL.map(|model| model.name());
+        eprintln!(
+            "{}: unknown model {}, expected one of {}",
+            option,
+            name,
+            names.join(", ")
+        );
+        std::process::exit(1);
+    };
+    let preference = match args.iter().find_map(|arg| arg.strip_prefix("--models=")) {
+        Some(names) => {
+            Model::list_from_names(names).unwrap_or_else(|name| unknown_model("--models", name))
+        }
+        None => DEFAULT_PREFERENCE.to_vec(),
+    };
+    let model = match args.iter().find_map(|arg| arg.strip_prefix("--model=")) {
+        Some(name) => Model::from_name(name).unwrap_or_else(|| unknown_model("--model", name)),
+        None => Model::select(&header, &preference).unwrap_or(preference[0]),
+    };
+    println!("model : {:?}", model);
+    //like on real hardware the game still boots without color, and is left to show its own
+    //warning screen when it finds a is not 0x11
+    if !model.can_run(&header) {
+        eprintln!(
+            "{}: this game needs a game boy color, booting it on {:?} anyway",
+            rom_path.display(),
+            model
+        );
+    }
+    mem.set_cgb_mode(model.cgb_mode(&header));
+    //--no-access-blocking lets the cpu at vram and oam in every ppu mode
+    mem.set_access_blocking(!args.iter().any(|arg| arg == "--no-access-blocking"));
+    //--renderer=fifo draws dot by dot for games that change registers in the middle of a line
+    let renderer = match args.iter().find_map(|arg| arg.strip_prefix("--renderer=")) {
+        Some(name) => Renderer::from_name(name).unwrap_or_else(|| {
+            let names = Renderer::ALL.map(|renderer| renderer.name());
+            eprintln!(
+                "--renderer: unknown renderer {}, expected one of {}",
+                name,
+                names.join(", ")
+            );
+            std::process::exit(1);
+        }),
+        None => Renderer::Scanline,
+    };
+    println!("renderer : {:?}", renderer);
+    let mut ppu = Ppu::new();
+    ppu.set_renderer(renderer);
+    //with --boot-rom=<file> execution starts at 0 in the boot rom, which shows the logo and
+    //locks up on a bad one, without it everything is set up the way the model's boot rom
+    //leaves it
+    match args.iter().find_map(|arg| arg.strip_prefix("--boot-rom=")) {
+        Some(boot_rom_path) => {
+            let boot_rom = fs::read(boot_rom_path).unwrap_or_else(|error| {
+                eprintln!("{}: {}", boot_rom_path, error);
+                std::process::exit(1);
+            });
+            if boot_rom.len() != BOOT_ROM_SIZE && boot_rom.len() != CGB_BOOT_ROM_SIZE {
+                eprintln!(
+                    "{}: boot rom should be {} or {} bytes, not {}",
+                    boot_rom_path,
+                    BOOT_ROM_SIZE,
+                    CGB_BOOT_ROM_SIZE,
+                    boot_rom.len()
+                );
+                std::process::exit(1);
+            }
+            mem.set_boot_rom(boot_rom);
+            println!("boot rom : {}", boot_rom_path);
+        }
+        None => model.post_boot(&header, &mut cpu.registers, &mut mem),
+    }
+    println!("af : {:X}", cpu.registers.read_16("af"));
+
+    //--frames=<n> stops after n frames, without it the game runs until killed
+    let frames = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--frames="))
+        .map(|frames| {
+            frames.parse::<u64>().unwrap_or_else(|_| {
+                eprintln!("--frames: {} is not a number", frames);
+                std::process::exit(1);
+            })
+        });
+    //ctrl-c and SIGTERM end the run the way --frames does so the save still gets written,
+    //a second ctrl-c kills it outright, SIGUSR1 writes the save right away
+    let quit = Arc::new(AtomicBool::new(false));
+    let flush_requested = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        let registered = signal_hook::flag::register_conditional_shutdown(signal, 1, quit.clone())
+            .and_then(|_| signal_hook::flag::register(signal, quit.clone()));
+        if let Err(error) = registered {
+            eprintln!("can't catch signal {}: {}", signal, error);
+        }
+    }
+    #[cfg(unix)]
+    if let Err(error) =
+        signal_hook::flag::register(signal_hook::consts::SIGUSR1, flush_requested.clone())
+    {
+        eprintln!("can't catch SIGUSR1: {}", error);
+    }
+
+    let mut frame = 0;
+    while frames != Some(frame) && !quit.load(Ordering::Relaxed) {
+        //vram dma started by the last instruction or the last hblank holds the cpu up
+        let cycles = cpu.step(&mut mem) + mem.take_stall_cycles();
+        //the ppu keeps its speed when the cpu switches to double speed
+        let dots = match mem.double_speed() {
+            true => cycles / 2,
+            false => cycles,
+        };
+        mem.tick(cycles);
+        //cartridge clocks count real time, which the ppu dots follow
+        mem.cartridge.tick(dots);
+        //hblank dma is paused while the cpu is halted
+        ppu.step(&mut mem, dots, cpu.halted());
+        if ppu.take_frame_ready() {
+            frame += 1;
+            //a failed write keeps the save in memory for the next try
+            if let Some(save) = save.as_mut() {
+                if let Err(error) = save.flush_if_due(mem.cartridge.as_mut()) {
+                    eprintln!("{}: {}", save.path().display(), error);
+                }
+            }
+        }
+        if flush_requested.swap(false, Ordering::Relaxed) {
+            if let Some(save) = save.as_mut() {
+                match save.flush(mem.cartridge.as_mut()) {
+                    Ok(()) => println!("save : written to {}", save.path().display()),
+                    Err(error) => eprintln!("{}: {}", save.path().display(), error),
+                }
+            }
+        }
+    }
+
+    //--dump-frame=<file> keeps the last finished frame as a .ppm or .rgb565
+    if let Some(path) = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--dump-frame="))
+    {
+        match dump::write_frame(&ppu, Path::new(path)) {
+            Ok(()) => println!("frame : written to {}", path),
+            Err(error) => eprintln!("{}: {}", path, error),
+        }
+    }
 
-    unsafe {
-        mem.rom.bank = mem.rom.buffer.as_mut_ptr().offset(0x4000);
+    if let Some((path, format, original)) = save_patch {
+        let written = mkpatch::create(&original, mem.cartridge.rom(), format)
+            .map_err(|error| error.to_string())
+            .and_then(|contents| fs::write(path, contents).map_err(|error| error.to_string()));
+        match written {
+            Ok(()) => println!("patch : written to {}", path.display()),
+            Err(error) => eprintln!("{}: {}", path.display(), error),
+        }
     }
 
-    for i in 0..0xFFFF {
-        mem.main_memory[i] = 0;
+    if let Some(save) = save.as_mut() {
+        if let Err(error) = save.flush(mem.cartridge.as_mut()) {
+            eprintln!("{}: {}", save.path().display(), error);
+            match save.rescue(mem.cartridge.as_ref()) {
+                Ok(path) => eprintln!("save written to {} instead", path.display()),
+                Err(error) => eprintln!("save lost: {}", error),
+            }
+            std::process::exit(1);
+        }
     }
-    mem.write_16(0x9000, 0x1234);
-    println!("mem[0x9000] : {:X}", mem.read_16(0x9000));
 }
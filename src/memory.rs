@@ -1,47 +1,165 @@
-use std::ptr::{null, null_mut};
+mod dma;
+pub(crate) mod palette;
 
-type MainMemory = [u8; 0xFFFF];
+use crate::cartridge::header::RamSize;
+use crate::cartridge::{self, Mapper, MapperKind};
+use crate::ppu::{
+    DRAWING_MODE, LCDC_REGISTER, LCD_ENABLE, LY_POSITION, MODE_FLAG, OAM_SCAN_MODE, STAT,
+    STAT_READ_ONLY,
+};
+use dma::{Hdma, OamDma, HDMA_BLOCK_SIZE, OAM_SIZE};
+use palette::PaletteRam;
 
-type RawBankNumber = u8;
+type MainMemory = [u8; 0x10000];
 
-const BANK_MASK: u8 = 0b00011111;
+//dmg, mgb and sgb boot roms cover 0x0000-0x00FF, the cgb one also 0x0200-0x08FF and
+//leaves the cartridge header visible in between
+pub const BOOT_ROM_SIZE: usize = 0x100;
+pub const CGB_BOOT_ROM_SIZE: usize = 0x900;
+const BOOT_ROM_HOLE: std::ops::Range<u16> = 0x0100..0x0200;
+//any nonzero write unmaps the boot rom for good
+const BOOT_ROM_DISABLE: u16 = 0xFF50;
 
-type Bank = [u8; 0x4000];
+const OAM_START: u16 = 0xFE00;
+const OAM_END: u16 = OAM_START + OAM_SIZE;
+pub(crate) const DMA_REGISTER: u16 = 0xFF46;
+//io, hram and ie sit on their own bus that dma doesn't use
+const HIGH_PAGE: u16 = 0xFF00;
+const CYCLES_PER_MACHINE_CYCLE: u32 = 4;
+//sources past wram read the echo of it
+const ECHO_START: u16 = 0xE000;
+const ECHO_OFFSET: u16 = 0x2000;
+
+pub const VRAM_START: u16 = 0x8000;
+pub const VRAM_SIZE: usize = 0x2000;
+const VRAM_END: u16 = 0xA000;
+const WRAM_START: u16 = 0xC000;
+const WRAM_BANK_SIZE: usize = 0x1000;
+const WRAM_BANKED_START: u16 = 0xD000;
+const WRAM_END: u16 = 0xE000;
+const ECHO_END: u16 = 0xFE00;
+//the cgb has 2 banks of vram and 8 of wram, 0xD000-0xDFFF shows bank 1-7
+const VRAM_BANKS: usize = 2;
+const WRAM_BANKS: usize = 8;
+pub(crate) const VBK: u16 = 0xFF4F;
+const SVBK: u16 = 0xFF70;
+//unused bits of the bank registers read as 1
+const VBK_UNUSED: u8 = 0b11111110;
+const SVBK_UNUSED: u8 = 0b11111000;
+const KEY1: u16 = 0xFF4D;
+const DOUBLE_SPEED: u8 = 0b10000000;
+const HDMA1: u16 = 0xFF51;
+const HDMA2: u16 = 0xFF52;
+const HDMA3: u16 = 0xFF53;
+const HDMA4: u16 = 0xFF54;
+const HDMA5: u16 = 0xFF55;
+pub(crate) const BCPS: u16 = 0xFF68;
+pub(crate) const BCPD: u16 = 0xFF69;
+pub(crate) const OCPS: u16 = 0xFF6A;
+pub(crate) const OCPD: u16 = 0xFF6B;
 
 pub struct Memory {
+    //io, hram and everything else that isn't banked
     pub main_memory: MainMemory,
-    pub rom: Rom,
+    vram: [[u8; VRAM_SIZE]; VRAM_BANKS],
+    wram: [[u8; WRAM_BANK_SIZE]; WRAM_BANKS],
+    //banking only exists with a cgb running a cgb game
+    cgb_mode: bool,
+    vram_bank: u8,
+    wram_bank: u8,
+    pub cartridge: Box<dyn Mapper>,
+    //overlaid on the cartridge until the game writes to 0xFF50
+    boot_rom: Option<Vec<u8>>,
+    dma: OamDma,
+    hdma: Hdma,
+    bg_palettes: PaletteRam,
+    obj_palettes: PaletteRam,
+    //cpu cycles lost to vram dma, for the cpu to burn before its next instruction
+    stall_cycles: u32,
+    //whether the cpu is kept out of vram and oam while the ppu uses them
+    access_blocking: bool,
+    //cycles not yet making up a whole machine cycle
+    cycles: u32,
 }
 
 impl Memory {
     pub(crate) fn new() -> Memory {
         Memory {
-            main_memory: [0; 0xFFFF],
-            rom: Rom {
-                buffer: [0; 0x2FFFF],
-                bank: null_mut(),
-            },
+            main_memory: [0; 0x10000],
+            vram: [[0; VRAM_SIZE]; VRAM_BANKS],
+            wram: [[0; WRAM_BANK_SIZE]; WRAM_BANKS],
+            cgb_mode: false,
+            vram_bank: 0,
+            wram_bank: 0,
+            cartridge: cartridge::new(Vec::new(), MapperKind::RomOnly, RamSize::None),
+            boot_rom: None,
+            dma: OamDma::new(),
+            hdma: Hdma::new(),
+            bg_palettes: PaletteRam::new(),
+            obj_palettes: PaletteRam::new(),
+            stall_cycles: 0,
+            access_blocking: true,
+            cycles: 0,
         }
     }
 }
 
-#[derive(Clone, Copy)]
-pub struct Rom {
-    pub buffer: [u8; 0x2FFFF],
-    pub bank: *mut u8,
-}
-
-const RBN: u16 = 0x2000;
-
 impl Memory {
+    //read from the cpu, which only sees the byte being copied while oam dma runs and 0xFF
+    //from vram and oam while the ppu is using them
     pub fn read_8(&self, address: u16) -> u8 {
-        if (0x4000..0x8000).contains(&address) {
-            unsafe {
-                let x = *(self.rom.bank.offset((address - 0x4000) as isize));
-                return x;
+        if self.dma.active() && address < HIGH_PAGE {
+            return match address {
+                OAM_START..OAM_END => 0xFF,
+                _ => self.dma.last_byte,
+            };
+        }
+        if self.ppu_blocks(address) {
+            return 0xFF;
+        }
+        self.bus_read(address)
+    }
+
+    //read_8 for looking at a byte the cpu doesn't fetch, mappers counting reads don't see it
+    pub fn peek_8(&self, address: u16) -> u8 {
+        match address {
+            0x0000..0x8000 if !self.dma.active() => self.dma_read(address),
+            _ => self.read_8(address),
+        }
+    }
+
+    //dma copies aren't cpu fetches either
+    fn dma_read(&self, address: u16) -> u8 {
+        match address {
+            0x0000..0x8000 => self
+                .read_boot_rom(address)
+                .unwrap_or_else(|| self.cartridge.peek_rom(address)),
+            _ => self.bus_read(address),
+        }
+    }
+
+    fn bus_read(&self, address: u16) -> u8 {
+        if let Some(value) = self.read_boot_rom(address) {
+            return value;
+        }
+        match address {
+            0x0000..0x8000 => self.cartridge.read_rom(address),
+            VRAM_START..VRAM_END => self.read_vram(self.vram_bank, address),
+            0xA000..0xC000 => self.cartridge.read_ram(address),
+            WRAM_START..ECHO_END => {
+                let (bank, offset) = self.wram_offset(address);
+                self.wram[bank][offset]
             }
+            VBK if self.cgb_mode => VBK_UNUSED | self.vram_bank,
+            SVBK if self.cgb_mode => SVBK_UNUSED | self.wram_bank,
+            HDMA1..=HDMA4 if self.cgb_mode => 0xFF,
+            HDMA5 if self.cgb_mode => self.hdma.status(),
+            BCPS if self.cgb_mode => self.bg_palettes.selection(),
+            BCPD if self.cgb_mode => self.bg_palettes.read(),
+            OCPS if self.cgb_mode => self.obj_palettes.selection(),
+            OCPD if self.cgb_mode => self.obj_palettes.read(),
+            _ => self.main_memory[address as usize],
         }
-        self.main_memory[address as usize]
     }
 
     pub fn read_16(&self, address: u16) -> u16 {
@@ -50,28 +168,50 @@ impl Memory {
         (y as u16) << 8 | x as u16
     }
 
-    fn write_to_rom_register(&mut self, address: u16, value: u8) {
-        if address > RBN && address < 2 * RBN {
-            let bank_number: RawBankNumber = value & BANK_MASK;
-            let bank_adress: u32;
-            if bank_number == 0 {
-                return;
-            } else if bank_number == 1 {
-                bank_adress = 0x4000;
-            } else {
-                bank_adress = 0x4000 * bank_number as u32;
-            }
-            unsafe {
-                self.rom.bank = self.rom.buffer.as_mut_ptr().offset(bank_adress as isize);
+    pub fn write_8(&mut self, address: u16, value: u8) {
+        if (self.dma.active() && address < HIGH_PAGE) || self.ppu_blocks(address) {
+            match address {
+                BCPD => self.bg_palettes.increment(),
+                OCPD => self.obj_palettes.increment(),
+                _ => {}
             }
+            return;
         }
-    }
-
-    pub fn write_8(&mut self, address: u16, value: u8) {
-        if address < 0x8000 {
-            self.write_to_rom_register(address, value);
-        } else {
-            self.main_memory[address as usize] = value;
+        match address {
+            0x0000..0x8000 => self.cartridge.write_rom(address, value),
+            VRAM_START..VRAM_END => {
+                self.vram[self.vram_bank as usize][(address - VRAM_START) as usize] = value
+            }
+            0xA000..0xC000 => self.cartridge.write_ram(address, value),
+            WRAM_START..ECHO_END => {
+                let (bank, offset) = self.wram_offset(address);
+                self.wram[bank][offset] = value;
+            }
+            VBK if self.cgb_mode => self.vram_bank = value & !VBK_UNUSED,
+            SVBK if self.cgb_mode => self.wram_bank = value & !SVBK_UNUSED,
+            HDMA1 if self.cgb_mode => self.hdma.set_source_high(value),
+            HDMA2 if self.cgb_mode => self.hdma.set_source_low(value),
+            HDMA3 if self.cgb_mode => self.hdma.set_destination_high(value),
+            HDMA4 if self.cgb_mode => self.hdma.set_destination_low(value),
+            HDMA5 if self.cgb_mode => {
+                let blocks = self.hdma.start(value);
+                self.copy_hdma_blocks(blocks);
+            }
+            BCPS if self.cgb_mode => self.bg_palettes.select(value),
+            BCPD if self.cgb_mode => self.bg_palettes.write(value),
+            OCPS if self.cgb_mode => self.obj_palettes.select(value),
+            OCPD if self.cgb_mode => self.obj_palettes.write(value),
+            BOOT_ROM_DISABLE if value != 0 => self.boot_rom = None,
+            STAT => {
+                let stat = &mut self.main_memory[address as usize];
+                *stat = (value & !STAT_READ_ONLY) | (*stat & STAT_READ_ONLY);
+            }
+            LY_POSITION => {}
+            DMA_REGISTER => {
+                self.main_memory[address as usize] = value;
+                self.dma.start(value);
+            }
+            _ => self.main_memory[address as usize] = value,
         }
     }
     pub fn write_16(&mut self, address: u16, value: u16) {
@@ -79,11 +219,174 @@ impl Memory {
         self.write_8(address + 1, (value & 0xFF) as u8);
     }
 
-    //set entire rom buffer
-    pub fn set_rom(&mut self, rom: [u8; 0x2FFFF]) {
-        self.rom.buffer = rom;
-        unsafe {
-            self.rom.bank = self.rom.buffer.as_mut_ptr().offset(0x4000);
+    //advance oam dma by cpu cycles
+    pub fn tick(&mut self, cycles: u32) {
+        self.cycles += cycles;
+        while self.cycles >= CYCLES_PER_MACHINE_CYCLE {
+            self.cycles -= CYCLES_PER_MACHINE_CYCLE;
+            if let Some((source, index)) = self.dma.step() {
+                let source = match source {
+                    ECHO_START.. => source - ECHO_OFFSET,
+                    _ => source,
+                };
+                let value = self.dma_read(source);
+                self.main_memory[(OAM_START + index) as usize] = value;
+                self.dma.last_byte = value;
+            }
+        }
+    }
+
+    //called by the ppu as it enters hblank, copies the next block of an hblank transfer
+    //unless the cpu is halted, which pauses it
+    pub fn hblank(&mut self, halted: bool) {
+        if self.cgb_mode && !halted && self.hdma.hblank_block() {
+            self.copy_hdma_blocks(1);
+        }
+    }
+
+    //cycles the cpu was stalled for since the last call
+    pub fn take_stall_cycles(&mut self) -> u32 {
+        std::mem::take(&mut self.stall_cycles)
+    }
+
+    pub fn double_speed(&self) -> bool {
+        self.cgb_mode && self.main_memory[KEY1 as usize] & DOUBLE_SPEED != 0
+    }
+
+    fn copy_hdma_blocks(&mut self, blocks: u16) {
+        for _ in 0..blocks {
+            let bank = self.vram_bank as usize;
+            for i in 0..HDMA_BLOCK_SIZE {
+                let value = self.dma_read(self.hdma.source.wrapping_add(i));
+                self.vram[bank][(self.hdma.destination + i) as usize] = value;
+            }
+            self.stall_cycles += Hdma::block_cycles(self.double_speed());
+            if !self.hdma.advance(VRAM_SIZE as u16) {
+                break;
+            }
         }
     }
+
+    //vram and palette ram are in use while drawing, oam from the start of the oam scan
+    fn ppu_blocks(&self, address: u16) -> bool {
+        if !self.access_blocking || self.main_memory[LCDC_REGISTER as usize] & LCD_ENABLE == 0 {
+            return false;
+        }
+        let mode = self.main_memory[STAT as usize] & MODE_FLAG;
+        match address {
+            VRAM_START..VRAM_END => mode == DRAWING_MODE,
+            OAM_START..OAM_END => mode == OAM_SCAN_MODE || mode == DRAWING_MODE,
+            BCPD | OCPD if self.cgb_mode => mode == DRAWING_MODE,
+            _ => false,
+        }
+    }
+
+    //let the cpu at vram and oam whatever the ppu is doing, for debugging
+    pub fn set_access_blocking(&mut self, access_blocking: bool) {
+        self.access_blocking = access_blocking;
+    }
+
+    //oam as the ppu sees it
+    pub fn read_oam(&self, address: u16) -> u8 {
+        self.main_memory[address as usize]
+    }
+
+    //vram as the ppu sees it, either bank regardless of VBK
+    pub fn read_vram(&self, bank: u8, address: u16) -> u8 {
+        self.vram[bank as usize & (VRAM_BANKS - 1)][(address - VRAM_START) as usize]
+    }
+
+    //15 bit colour of a background or object palette as the ppu sees it
+    pub fn bg_colour(&self, palette: u8, colour: u8) -> u16 {
+        self.bg_palettes.colour(palette, colour)
+    }
+
+    pub fn obj_colour(&self, palette: u8, colour: u8) -> u16 {
+        self.obj_palettes.colour(palette, colour)
+    }
+
+    pub fn cgb_mode(&self) -> bool {
+        self.cgb_mode
+    }
+
+    //enable the cgb vram and wram banks
+    pub fn set_cgb_mode(&mut self, cgb_mode: bool) {
+        self.cgb_mode = cgb_mode;
+        self.vram_bank = 0;
+        self.wram_bank = 0;
+    }
+
+    //bank and offset in it for an address in wram or its echo, bank 0 selects bank 1
+    fn wram_offset(&self, address: u16) -> (usize, usize) {
+        let address = match address {
+            WRAM_END.. => address - ECHO_OFFSET,
+            _ => address,
+        };
+        match address {
+            WRAM_BANKED_START.. if self.cgb_mode => (
+                (self.wram_bank as usize).max(1),
+                (address - WRAM_BANKED_START) as usize,
+            ),
+            WRAM_BANKED_START.. => (1, (address - WRAM_BANKED_START) as usize),
+            _ => (0, (address - WRAM_START) as usize),
+        }
+    }
+
+    //swap in the cartridge built from the loaded rom
+    pub fn set_cartridge(&mut self, cartridge: Box<dyn Mapper>) {
+        self.cartridge = cartridge;
+    }
+
+    //map a boot rom of BOOT_ROM_SIZE or CGB_BOOT_ROM_SIZE bytes over the cartridge
+    pub fn set_boot_rom(&mut self, boot_rom: Vec<u8>) {
+        self.boot_rom = Some(boot_rom);
+    }
+
+    fn read_boot_rom(&self, address: u16) -> Option<u8> {
+        let boot_rom = self.boot_rom.as_ref()?;
+        if BOOT_ROM_HOLE.contains(&address) {
+            return None;
+        }
+        boot_rom.get(address as usize).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //what the locked cart shows in place of the header page
+    const LOGO_PAGE: u8 = 0xAA;
+
+    //sachen cart that redirects the header page to its upper half while locked, the
+    //scrambling keeps bit 7 so the halves stay apart
+    fn sachen_memory() -> Memory {
+        let mut rom = vec![0; 0x8000];
+        rom[0x180..0x200].fill(LOGO_PAGE);
+        let mut mem = Memory::new();
+        mem.set_cartridge(cartridge::new(rom, MapperKind::SachenMmc1, RamSize::None));
+        mem
+    }
+
+    #[test]
+    fn dma_doesnt_count_as_rom_reads() {
+        let mut mem = sachen_memory();
+        //oam dma of 0x0100-0x019F, more reads than unlock the cart
+        mem.write_8(DMA_REGISTER, 0x01);
+        mem.tick(OAM_SIZE as u32 * 4 + 8);
+        assert!(!mem.dma.active());
+        assert_eq!(mem.read_oam(0xFE04), LOGO_PAGE);
+
+        //a general purpose hdma of the same page
+        mem.set_cgb_mode(true);
+        mem.write_8(HDMA1, 0x01);
+        mem.write_8(HDMA2, 0x00);
+        mem.write_8(HDMA3, 0x00);
+        mem.write_8(HDMA4, 0x00);
+        mem.write_8(HDMA5, 0x0F);
+        assert_eq!(mem.read_vram(0, 0x8004), LOGO_PAGE);
+
+        assert_eq!(mem.peek_8(0x0104), LOGO_PAGE);
+        assert_eq!(mem.read_8(0x0104), LOGO_PAGE);
+    }
 }
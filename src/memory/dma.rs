@@ -0,0 +1,157 @@
+//the transfer starts one machine cycle after the write to 0xFF46
+const STARTUP_CYCLES: u8 = 1;
+pub const OAM_SIZE: u16 = 0xA0;
+
+//oam dma state, counted in machine cycles with one byte copied per cycle
+pub struct OamDma {
+    source: u16,
+    //next byte to copy, the transfer runs while it's below OAM_SIZE
+    index: u16,
+    //a new transfer waits for its startup cycle while the old one keeps copying
+    pending: Option<(u16, u8)>,
+    //what was last put on the bus, the cpu reads it instead of the memory it asked for
+    pub last_byte: u8,
+}
+
+impl OamDma {
+    pub fn new() -> OamDma {
+        OamDma {
+            source: 0,
+            index: OAM_SIZE,
+            pending: None,
+            last_byte: 0xFF,
+        }
+    }
+
+    //written page becomes the upper byte of the source, restarting any transfer in progress
+    pub fn start(&mut self, page: u8) {
+        self.pending = Some(((page as u16) << 8, STARTUP_CYCLES));
+    }
+
+    //the cpu is locked out of everything but the 0xFF00 page while this holds
+    pub fn active(&self) -> bool {
+        self.index < OAM_SIZE
+    }
+
+    //advance one machine cycle, returning the source address and the oam offset of the
+    //byte to copy during it
+    pub fn step(&mut self) -> Option<(u16, u16)> {
+        let copy = self.active().then(|| {
+            let index = self.index;
+            self.index += 1;
+            (self.source + index, index)
+        });
+        self.pending = match self.pending {
+            Some((source, cycles)) if cycles <= 1 => {
+                self.source = source;
+                self.index = 0;
+                None
+            }
+            Some((source, cycles)) => Some((source, cycles - 1)),
+            None => None,
+        };
+        copy
+    }
+}
+
+pub const HDMA_BLOCK_SIZE: u16 = 0x10;
+//cpu cycles stalled per block, the copy takes as long in real time at double speed
+const HDMA_BLOCK_CYCLES: u32 = 32;
+//HDMA5 reads this once nothing is left to copy
+const HDMA_IDLE: u8 = 0x7F;
+const HDMA_HBLANK: u8 = 0b10000000;
+
+//cgb vram dma, either everything at once or a block every hblank
+pub struct Hdma {
+    pub source: u16,
+    //offset in vram
+    pub destination: u16,
+    //blocks left minus one, as HDMA5 shows them
+    remaining: u8,
+    hblank_active: bool,
+}
+
+impl Hdma {
+    pub fn new() -> Hdma {
+        Hdma {
+            source: 0,
+            destination: 0,
+            remaining: HDMA_IDLE,
+            hblank_active: false,
+        }
+    }
+
+    pub fn set_source_high(&mut self, value: u8) {
+        self.source = (self.source & 0x00FF) | (value as u16) << 8;
+    }
+
+    //the low 4 bits are ignored, blocks are aligned
+    pub fn set_source_low(&mut self, value: u8) {
+        self.source = (self.source & 0xFF00) | (value & 0xF0) as u16;
+    }
+
+    pub fn set_destination_high(&mut self, value: u8) {
+        self.destination = (self.destination & 0x00FF) | ((value & 0x1F) as u16) << 8;
+    }
+
+    pub fn set_destination_low(&mut self, value: u8) {
+        self.destination = (self.destination & 0xFF00) | (value & 0xF0) as u16;
+    }
+
+    //bit 7 clear while an hblank transfer runs, set once it finished or was cancelled
+    pub fn status(&self) -> u8 {
+        match self.hblank_active {
+            true => self.remaining,
+            false => HDMA_HBLANK | self.remaining,
+        }
+    }
+
+    //write to HDMA5, returns the number of blocks to copy right away for a general purpose
+    //transfer, writing with bit 7 clear during an hblank transfer cancels it instead
+    pub fn start(&mut self, value: u8) -> u16 {
+        let blocks = value & !HDMA_HBLANK;
+        if self.hblank_active && value & HDMA_HBLANK == 0 {
+            self.hblank_active = false;
+            return 0;
+        }
+        self.remaining = blocks;
+        if value & HDMA_HBLANK != 0 {
+            self.hblank_active = true;
+            return 0;
+        }
+        self.remaining = HDMA_IDLE;
+        blocks as u16 + 1
+    }
+
+    //whether a block should be copied for this hblank, counting it as done
+    pub fn hblank_block(&mut self) -> bool {
+        if !self.hblank_active {
+            return false;
+        }
+        self.remaining = self.remaining.wrapping_sub(1) & HDMA_IDLE;
+        if self.remaining == HDMA_IDLE {
+            self.hblank_active = false;
+        }
+        true
+    }
+
+    //after a block was copied, false once the transfer ran past the end of vram and stopped
+    pub fn advance(&mut self, vram_size: u16) -> bool {
+        self.source = self.source.wrapping_add(HDMA_BLOCK_SIZE);
+        self.destination += HDMA_BLOCK_SIZE;
+        if self.destination < vram_size {
+            return true;
+        }
+        self.destination = 0;
+        self.hblank_active = false;
+        self.remaining = HDMA_IDLE;
+        false
+    }
+
+    pub fn block_cycles(double_speed: bool) -> u32 {
+        match double_speed {
+            true => HDMA_BLOCK_CYCLES * 2,
+            false => HDMA_BLOCK_CYCLES,
+        }
+    }
+}
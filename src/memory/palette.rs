@@ -0,0 +1,64 @@
+//8 palettes of 4 colours, 2 bytes each
+const PALETTE_RAM_SIZE: usize = 64;
+const PALETTE_BYTES: u8 = 8;
+pub(crate) const AUTO_INCREMENT: u8 = 0b10000000;
+const INDEX: u8 = 0b00111111;
+//bit 6 of BCPS and OCPS isn't used and reads as 1
+const SELECTION_UNUSED: u8 = 0b01000000;
+
+//cgb colour palettes the cpu reaches a byte at a time through BCPS/BCPD or OCPS/OCPD,
+//colours are 15 bit little endian with red in the low bits and blue in the high ones
+pub struct PaletteRam {
+    data: [u8; PALETTE_RAM_SIZE],
+    //byte the data register reads and writes
+    index: u8,
+    //move on to the next byte after every write to the data register
+    auto_increment: bool,
+}
+
+impl PaletteRam {
+    //every colour starts out white
+    pub fn new() -> PaletteRam {
+        PaletteRam {
+            data: [0xFF; PALETTE_RAM_SIZE],
+            index: 0,
+            auto_increment: false,
+        }
+    }
+
+    //write to the selection register
+    pub fn select(&mut self, value: u8) {
+        self.index = value & INDEX;
+        self.auto_increment = value & AUTO_INCREMENT != 0;
+    }
+
+    pub fn selection(&self) -> u8 {
+        let auto_increment = match self.auto_increment {
+            true => AUTO_INCREMENT,
+            false => 0,
+        };
+        auto_increment | SELECTION_UNUSED | self.index
+    }
+
+    pub fn read(&self) -> u8 {
+        self.data[self.index as usize]
+    }
+
+    pub fn write(&mut self, value: u8) {
+        self.data[self.index as usize] = value;
+        self.increment();
+    }
+
+    //writes the ppu blocks during drawing are lost but still move the index on
+    pub fn increment(&mut self) {
+        if self.auto_increment {
+            self.index = (self.index + 1) & INDEX;
+        }
+    }
+
+    //15 bit value of one of the 4 colours of a palette
+    pub fn colour(&self, palette: u8, colour: u8) -> u16 {
+        let index = (palette * PALETTE_BYTES + colour * 2) as usize;
+        u16::from_le_bytes([self.data[index], self.data[index + 1]])
+    }
+}
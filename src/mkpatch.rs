@@ -0,0 +1,158 @@
+use std::fs;
+use std::path::Path;
+
+use crate::patch::{self, PatchError};
+use crate::rom;
+
+const USAGE: &str = "usage: mkpatch <original> <modified> <patch.ips|patch.bps>";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Ips,
+    Bps,
+}
+
+impl Format {
+    //picked by the extension of the patch file
+    pub fn from_path(path: &Path) -> Option<Format> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("bps") => Some(Format::Bps),
+            Some(extension) if extension.eq_ignore_ascii_case("ips") => Some(Format::Ips),
+            _ => None,
+        }
+    }
+}
+
+//the patch turning original into modified, which can be a rom file or the rom of a running
+//cartridge
+pub fn create(original: &[u8], modified: &[u8], format: Format) -> Result<Vec<u8>, PatchError> {
+    match format {
+        Format::Bps => Ok(patch::create_bps(original, modified)),
+        Format::Ips => patch::create_ips(original, modified),
+    }
+}
+
+//mkpatch <original> <modified> <patch>: write the patch turning one rom into the other,
+//ips or bps going by the extension, returns the process exit code
+pub fn run(args: &[String]) -> i32 {
+    let [original, modified, output] = args else {
+        eprintln!("{}", USAGE);
+        return 2;
+    };
+    let output = Path::new(output);
+    let Some(format) = Format::from_path(output) else {
+        eprintln!("{}", USAGE);
+        return 2;
+    };
+
+    let mut roms = Vec::new();
+    for path in [original, modified] {
+        match rom::load(Path::new(path), None) {
+            Ok(rom) => roms.push(rom),
+            Err(error) => {
+                eprintln!("{}: {}", path, error);
+                return 1;
+            }
+        }
+    }
+    let contents = match create(&roms[0], &roms[1], format) {
+        Ok(contents) => contents,
+        Err(error) => {
+            eprintln!("{}: {}", output.display(), error);
+            return 1;
+        }
+    };
+    if let Err(error) = fs::write(output, &contents) {
+        eprintln!("{}: {}", output.display(), error);
+        return 1;
+    }
+    println!("{} : {} bytes", output.display(), contents.len());
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::path::PathBuf;
+
+    fn test_dir(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("mkpatch-test-{}-{}", test, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn args(paths: &[&PathBuf]) -> Vec<String> {
+        paths
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn format_from_the_extension() {
+        assert_eq!(Format::from_path(Path::new("a.bps")), Some(Format::Bps));
+        assert_eq!(Format::from_path(Path::new("a.IPS")), Some(Format::Ips));
+        assert_eq!(Format::from_path(Path::new("a.ups")), None);
+        assert_eq!(Format::from_path(Path::new("bps")), None);
+    }
+
+    #[test]
+    fn patches_round_trip() {
+        let original = vec![0; 0x8000];
+        let mut modified = original.clone();
+        modified[0x150] = 0x42;
+        modified.extend([1, 2, 3]);
+        for format in [Format::Ips, Format::Bps] {
+            let contents = create(&original, &modified, format).unwrap();
+            assert_eq!(patch::apply(&original, &contents), Ok(modified.clone()));
+        }
+    }
+
+    #[test]
+    fn usage_errors() {
+        let dir = test_dir("usage");
+        let original = dir.join("original.gb");
+        assert_eq!(run(&[]), 2);
+        assert_eq!(run(&args(&[&original, &original])), 2);
+        let ups = dir.join("out.ups");
+        assert_eq!(run(&args(&[&original, &original, &ups])), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn writes_by_extension() {
+        let dir = test_dir("write");
+        let original = dir.join("original.gb");
+        let modified = dir.join("modified.gb");
+        fs::write(&original, [0; 0x100]).unwrap();
+        fs::write(&modified, [0x11; 0x100]).unwrap();
+        let ips = dir.join("out.ips");
+        let bps = dir.join("out.BPS");
+        assert_eq!(run(&args(&[&original, &modified, &ips])), 0);
+        assert_eq!(run(&args(&[&original, &modified, &bps])), 0);
+        assert!(fs::read(&ips).unwrap().starts_with(b"PATCH"));
+        assert!(fs::read(&bps).unwrap().starts_with(b"BPS1"));
+        let missing = dir.join("missing.gb");
+        assert_eq!(run(&args(&[&missing, &modified, &ips])), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn ips_too_large() {
+        let large = vec![0; 0x1000001];
+        assert_eq!(create(&[], &large, Format::Ips), Err(PatchError::TooLarge));
+        assert!(create(&[], &large, Format::Bps).is_ok());
+
+        //loading stops at the largest rom size, long before ips runs out
+        let dir = test_dir("large");
+        let original = dir.join("original.gb");
+        let modified = dir.join("modified.gb");
+        let ips = dir.join("out.ips");
+        fs::write(&original, [0; 0x100]).unwrap();
+        fs::write(&modified, &large).unwrap();
+        assert_eq!(run(&args(&[&original, &modified, &ips])), 1);
+        assert!(!ips.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,386 @@
+use crate::cartridge::header::{CartridgeHeader, CgbSupport, Licensee};
+use crate::cpu::Registers;
+use crate::memory::{Memory, DMA_REGISTER};
+use crate::ppu::{LY_POSITION, OBP0, OBP1, STAT};
+
+//where the dmg boot rom leaves the logo: 24 tiles from the cartridge then the (R)
+const LOGO_TILES: u16 = 0x8010;
+const REGISTERED_TILE: u16 = 0x8190;
+const REGISTERED: [u8; 8] = [0x3C, 0x42, 0xB9, 0xA5, 0xB9, 0xA5, 0x42, 0x3C];
+const LOGO_MAP_TOP: u16 = 0x9904;
+const LOGO_MAP_BOTTOM: u16 = 0x9924;
+const REGISTERED_MAP: u16 = 0x9910;
+const LOGO_ROW_TILES: u8 = 12;
+
+const OAM: std::ops::Range<usize> = 0xFE00..0xFEA0;
+const HRAM: std::ops::Range<usize> = 0xFF80..0xFFFF;
+
+const ZERO_FLAG: u8 = 0b10000000;
+const HALF_CARRY_FLAG: u8 = 0b00100000;
+const CARRY_FLAG: u8 = 0b00010000;
+
+//io registers as every boot rom leaves them, the per model ones are set on top
+const IO_REGISTERS: [(u16, u8); 32] = [
+    (0xFF00, 0xCF),
+    (0xFF01, 0x00),
+    (0xFF05, 0x00),
+    (0xFF06, 0x00),
+    (0xFF07, 0xF8),
+    (0xFF0F, 0xE1),
+    (0xFF10, 0x80),
+    (0xFF11, 0xBF),
+    (0xFF12, 0xF3),
+    (0xFF13, 0xFF),
+    (0xFF14, 0xBF),
+    (0xFF16, 0x3F),
+    (0xFF17, 0x00),
+    (0xFF18, 0xFF),
+    (0xFF19, 0xBF),
+    (0xFF1A, 0x7F),
+    (0xFF1B, 0xFF),
+    (0xFF1C, 0x9F),
+    (0xFF1D, 0xFF),
+    (0xFF1E, 0xBF),
+    (0xFF20, 0xFF),
+    (0xFF21, 0x00),
+    (0xFF22, 0x00),
+    (0xFF23, 0xBF),
+    (0xFF24, 0x77),
+    (0xFF25, 0xF3),
+    (0xFF40, 0x91),
+    (0xFF42, 0x00),
+    (0xFF43, 0x00),
+    (0xFF45, 0x00),
+    (0xFF47, 0xFC),
+    (0xFFFF, 0x00),
+];
+const DIV: u16 = 0xFF04;
+const NR52: u16 = 0xFF26;
+const SC: u16 = 0xFF02;
+//registers only the cgb has, with what they read after its boot rom
+const CGB_IO_REGISTERS: [(u16, u8); 2] = [(0xFF4D, 0x7E), (0xFF56, 0x3E)];
+
+//order models are tried in when none is forced, the first one the cartridge makes use of wins
+pub const DEFAULT_PREFERENCE: [Model; 3] = [Model::Cgb, Model::Sgb, Model::Dmg];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Model {
+    //first revision of the original game boy
+    Dmg0,
+    Dmg,
+    //game boy pocket and light
+    Mgb,
+    Sgb,
+    Sgb2,
+    Cgb,
+    //game boy advance running game boy software
+    Agb,
+}
+
+impl Model {
+    pub const ALL: [Model; 7] = [
+        Model::Dmg0,
+        Model::Dmg,
+        Model::Mgb,
+        Model::Sgb,
+        Model::Sgb2,
+        Model::Cgb,
+        Model::Agb,
+    ];
+
+    //name accepted by --model
+    pub fn from_name(name: &str) -> Option<Model> {
+        Some(match name.to_lowercase().as_str() {
+            "dmg0" => Model::Dmg0,
+            "dmg" => Model::Dmg,
+            "mgb" => Model::Mgb,
+            "sgb" => Model::Sgb,
+            "sgb2" => Model::Sgb2,
+            "cgb" => Model::Cgb,
+            "agb" => Model::Agb,
+            _ => return None,
+        })
+    }
+
+    //name accepted by from_name
+    pub fn name(&self) -> &'static str {
+        match self {
+            Model::Dmg0 => "dmg0",
+            Model::Dmg => "dmg",
+            Model::Mgb => "mgb",
+            Model::Sgb => "sgb",
+            Model::Sgb2 => "sgb2",
+            Model::Cgb => "cgb",
+            Model::Agb => "agb",
+        }
+    }
+
+    pub fn is_cgb(&self) -> bool {
+        matches!(self, Model::Cgb | Model::Agb)
+    }
+
+    pub fn is_sgb(&self) -> bool {
+        matches!(self, Model::Sgb | Model::Sgb2)
+    }
+
+    //comma separated names, for the preference order, or the first name that isn't a model
+    pub fn list_from_names(names: &str) -> Result<Vec<Model>, &str> {
+        names
+            .split(',')
+            .map(|name| Model::from_name(name.trim()).ok_or(name))
+            .collect()
+    }
+
+    //first model in preference order whose extra hardware the cartridge uses, color for cgb
+    //games and super game boy for sgb games, falling back to the first one that can run it
+    pub fn select(header: &CartridgeHeader, preference: &[Model]) -> Option<Model> {
+        let uses = |model: &Model| match model {
+            _ if model.is_cgb() => header.cgb != CgbSupport::None,
+            _ if model.is_sgb() => header.sgb,
+            _ => true,
+        };
+        preference
+            .iter()
+            .find(|model| model.can_run(header) && uses(model))
+            .or_else(|| preference.iter().find(|model| model.can_run(header)))
+            .copied()
+    }
+
+    //a cgb runs dmg games in a compatibility mode without its extra banks and palettes
+    pub fn cgb_mode(&self, header: &CartridgeHeader) -> bool {
+        self.is_cgb() && header.cgb != CgbSupport::None
+    }
+
+    //cgb only games lock up or show a warning screen on anything without color
+    pub fn can_run(&self, header: &CartridgeHeader) -> bool {
+        self.is_cgb() || header.cgb != CgbSupport::Only
+    }
+
+    //put the cpu and memory in the state the boot rom of this model leaves them in, some
+    //values depend on the cartridge header
+    pub fn post_boot(&self, header: &CartridgeHeader, registers: &mut Registers, mem: &mut Memory) {
+        let cgb_game = header.cgb != CgbSupport::None;
+        //a = 0x11 is how games tell they run on color hardware
+        let (af, bc, de, hl) = match self {
+            Model::Dmg0 => (0x0100, 0xFF13, 0x00C1, 0x8403),
+            Model::Dmg | Model::Mgb => {
+                let a = match self {
+                    Model::Mgb => 0xFF,
+                    _ => 0x01,
+                };
+                //the flags are left over from the header checksum check
+                let flags = match header.header_checksum {
+                    0 => ZERO_FLAG,
+                    _ => ZERO_FLAG | HALF_CARRY_FLAG | CARRY_FLAG,
+                };
+                (a << 8 | flags as u16, 0x0013, 0x00D8, 0x014D)
+            }
+            Model::Sgb => (0x0100, 0x0014, 0x0000, 0xC060),
+            Model::Sgb2 => (0xFF00, 0x0014, 0x0000, 0xC060),
+            Model::Cgb | Model::Agb => {
+                let (b, de, hl) = match cgb_game {
+                    true => (0x00, 0xFF56, 0x000D),
+                    false => {
+                        let b = dmg_game_b(header);
+                        //the palette picked for dmg games leaves hl pointing into it
+                        let hl = match b {
+                            0x43 | 0x58 => 0x991A,
+                            _ => 0x007C,
+                        };
+                        (b, 0x0008, hl)
+                    }
+                };
+                //the agb boot rom ends with an inc b, which also clears the zero flag
+                let (b, flags) = match self {
+                    Model::Agb => (b.wrapping_add(1), 0),
+                    _ => (b, ZERO_FLAG),
+                };
+                (0x1100 | flags as u16, (b as u16) << 8, de, hl)
+            }
+        };
+        registers.write_16("af", af);
+        registers.write_16("bc", bc);
+        registers.write_16("de", de);
+        registers.write_16("hl", hl);
+        registers.write_16("sp", 0xFFFE);
+        registers.write_16("pc", 0x0100);
+
+        for (address, value) in IO_REGISTERS {
+            mem.write_8(address, value);
+        }
+        //div is the upper byte of a counter that ran through the whole boot rom
+        let (div, stat, ly, dma, nr52, sc) = match self {
+            Model::Dmg0 => (0x18, 0x81, 0x91, 0xFF, 0xF1, 0x7E),
+            Model::Dmg | Model::Mgb => (0xAB, 0x85, 0x00, 0xFF, 0xF1, 0x7E),
+            Model::Sgb | Model::Sgb2 => (0xD8, 0x85, 0x00, 0xFF, 0xF0, 0x7E),
+            Model::Cgb | Model::Agb => (0x1E, 0x85, 0x00, 0x00, 0xF1, 0x7F),
+        };
+        mem.main_memory[DIV as usize] = div;
+        mem.main_memory[STAT as usize] = stat;
+        mem.main_memory[LY_POSITION as usize] = ly;
+        mem.main_memory[DMA_REGISTER as usize] = dma;
+        mem.main_memory[NR52 as usize] = nr52;
+        mem.main_memory[SC as usize] = sc;
+        //never written by the boot rom, these power up as 0xFF
+        mem.main_memory[OBP0 as usize] = 0xFF;
+        mem.main_memory[OBP1 as usize] = 0xFF;
+        if self.is_cgb() {
+            for (address, value) in CGB_IO_REGISTERS {
+                mem.main_memory[address as usize] = value;
+            }
+        }
+
+        //oam and hram power up random on the dmg and the cgb boot rom clears them, cleared
+        //is a state every model can be in
+        mem.main_memory[OAM].fill(0);
+        mem.main_memory[HRAM].fill(0);
+
+        //the logo stays in vram after it scrolled down, the cgb boot rom clears it
+        if !self.is_cgb() {
+            load_logo(header, mem);
+        }
+    }
+}
+
+//b after the cgb boot rom picked a palette for a dmg game: the sum of the title for
+//games published by nintendo, 0 for everyone else
+fn dmg_game_b(header: &CartridgeHeader) -> u8 {
+    match header.licensee {
+        Licensee::Old(0x01) => header.title_checksum(),
+        Licensee::New([b'0', b'1']) => header.title_checksum(),
+        _ => 0,
+    }
+}
+
+//the boot rom copies the logo from the cartridge, every bit doubled in width and height
+fn load_logo(header: &CartridgeHeader, mem: &mut Memory) {
+    let mut address = LOGO_TILES;
+    for &byte in header.logo() {
+        for nibble in [byte >> 4, byte & 0x0F] {
+            let row = double_bits(nibble);
+            for _ in 0..2 {
+                mem.write_8(address, row);
+                mem.write_8(address + 1, 0);
+                address += 2;
+            }
+        }
+    }
+    for (i, row) in REGISTERED.iter().enumerate() {
+        mem.write_8(REGISTERED_TILE + i as u16 * 2, *row);
+        mem.write_8(REGISTERED_TILE + i as u16 * 2 + 1, 0);
+    }
+
+    for i in 0..LOGO_ROW_TILES {
+        mem.write_8(LOGO_MAP_TOP + i as u16, i + 1);
+        mem.write_8(LOGO_MAP_BOTTOM + i as u16, LOGO_ROW_TILES + i + 1);
+    }
+    mem.write_8(REGISTERED_MAP, LOGO_ROW_TILES * 2 + 1);
+}
+
+fn double_bits(nibble: u8) -> u8 {
+    (0..4).fold(0, |doubled, bit| match nibble & (1 << bit) {
+        0 => doubled,
+        _ => doubled | 0b11 << (bit * 2),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::header::{self, HEADER_CHECKSUM, LOGO_START, TITLE_START};
+    use crate::cartridge::NINTENDO_LOGO;
+    use crate::cpu::Cpu;
+
+    fn header(title: &[u8], old_licensee: u8) -> CartridgeHeader {
+        let mut rom = vec![0; 0x8000];
+        rom[LOGO_START..LOGO_START + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        rom[TITLE_START..TITLE_START + title.len()].copy_from_slice(title);
+        rom[header::OLD_LICENSEE] = old_licensee;
+        rom[HEADER_CHECKSUM] = header::header_checksum(&rom);
+        CartridgeHeader::parse(&rom).unwrap()
+    }
+
+    fn boot(model: Model, header: &CartridgeHeader) -> (Cpu, Memory) {
+        let (mut cpu, mut mem) = (Cpu::new(), Memory::new());
+        mem.main_memory[OAM].fill(0x55);
+        mem.main_memory[HRAM].fill(0x55);
+        model.post_boot(header, &mut cpu.registers, &mut mem);
+        (cpu, mem)
+    }
+
+    #[test]
+    fn dmg_flags_from_the_header_checksum() {
+        let header = header(b"GAME", 0x00);
+        let (cpu, mem) = boot(Model::Dmg, &header);
+        let flags = match header.header_checksum {
+            0 => ZERO_FLAG,
+            _ => ZERO_FLAG | HALF_CARRY_FLAG | CARRY_FLAG,
+        };
+        assert_eq!(cpu.registers.read_16("af"), 0x0100 | flags as u16);
+        assert_eq!(cpu.registers.read_16("pc"), 0x0100);
+        assert!(mem.main_memory[OAM].iter().all(|&byte| byte == 0));
+        assert!(mem.main_memory[HRAM].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn dmg_leaves_the_logo_in_vram() {
+        let (_, mem) = boot(Model::Dmg, &header(b"GAME", 0x00));
+        //first logo byte is 0xCE, its high nibble doubled on two rows
+        let row = |i: u16| mem.read_vram(0, LOGO_TILES + i);
+        assert_eq!([row(0), row(1), row(2), row(3)], [0xF0, 0, 0xF0, 0]);
+        assert_eq!(mem.read_vram(0, LOGO_MAP_TOP), 1);
+    }
+
+    #[test]
+    fn cgb_b_from_nintendo_titles() {
+        let nintendo = header(b"TETRIS", 0x01);
+        let (cpu, _) = boot(Model::Cgb, &nintendo);
+        assert_eq!(
+            cpu.registers.read_16("bc") >> 8,
+            nintendo.title_checksum() as u16
+        );
+        assert_eq!(cpu.registers.read_16("af") >> 8, 0x11);
+
+        let (cpu, _) = boot(Model::Cgb, &header(b"TETRIS", 0x02));
+        assert_eq!(cpu.registers.read_16("bc"), 0x0000);
+        let (cpu, _) = boot(Model::Agb, &header(b"TETRIS", 0x02));
+        assert_eq!(cpu.registers.read_16("bc"), 0x0100);
+    }
+
+    #[test]
+    fn names() {
+        for model in Model::ALL {
+            assert_eq!(Model::from_name(model.name()), Some(model));
+        }
+        assert_eq!(
+            Model::list_from_names("cgb, DMG"),
+            Ok(vec![Model::Cgb, Model::Dmg])
+        );
+        assert_eq!(Model::list_from_names("cgb,gba"), Err("gba"));
+    }
+
+    #[test]
+    fn cgb_only_games_select_no_dmg_model() {
+        let mut header = header(b"GAME", 0x00);
+        header.cgb = CgbSupport::Only;
+        assert_eq!(
+            Model::select(&header, &[Model::Dmg, Model::Cgb]),
+            Some(Model::Cgb)
+        );
+        assert_eq!(Model::select(&header, &[Model::Dmg]), None);
+        assert!(!Model::Dmg.can_run(&header));
+    }
+
+    #[test]
+    fn cgb_only_games_forced_on_a_dmg_find_no_color() {
+        let mut header = header(b"GAME", 0x00);
+        header.cgb = CgbSupport::Only;
+        //the game reads a = 0x01 and shows its own lock-out screen, there's none to emulate
+        let (cpu, mem) = boot(Model::Dmg, &header);
+        assert_eq!(cpu.registers.read_16("af") >> 8, 0x01);
+        assert_eq!(cpu.registers.read_16("pc"), 0x0100);
+        assert!(!mem.cgb_mode());
+        let (cpu, _) = boot(Model::Cgb, &header);
+        assert_eq!(cpu.registers.read_16("af") >> 8, 0x11);
+    }
+}
@@ -0,0 +1,695 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::rom::MAX_ROM_SIZE;
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+//a record at this offset would read as the EOF marker
+const IPS_EOF_OFFSET: usize = 0x454F46;
+//ips offsets and the truncation size are 24 bits
+const IPS_MAX_SIZE: usize = 0x1000000;
+const IPS_MAX_RECORD: usize = 0xFFFF;
+//a run record costs 8 bytes, a plain one 5 plus the data
+const IPS_MIN_RUN: usize = 9;
+//unchanged bytes cheaper to repeat than to start a new record over
+const IPS_MAX_GAP: usize = 5;
+//shortest copy worth a bps command over plain target bytes, also the length hashed to find them
+const BPS_MIN_MATCH: usize = 4;
+//places tried per hashed 4 bytes when looking for the longest copy
+const BPS_CANDIDATES: usize = 32;
+const UPS_MAGIC: &[u8] = b"UPS1";
+const BPS_MAGIC: &[u8] = b"BPS1";
+//ups and bps end with the crc32 of the source, of the target and of the patch itself
+const FOOTER_SIZE: usize = 12;
+
+//looked for next to the rom when no patch is named, first one found wins
+const PATCH_EXTENSIONS: [&str; 3] = ["bps", "ups", "ips"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatchError {
+    UnknownFormat,
+    //the patch ends in the middle of a record or points outside the data
+    Malformed,
+    //ips can't address past 16MiB
+    TooLarge,
+    PatchChecksum { expected: u32, computed: u32 },
+    //the patch was made for another rom
+    SourceChecksum { expected: u32, computed: u32 },
+    TargetChecksum { expected: u32, computed: u32 },
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatchError::UnknownFormat => write!(f, "not an ips, ups or bps patch"),
+            PatchError::Malformed => write!(f, "patch is truncated or corrupt"),
+            PatchError::TooLarge => write!(f, "rom is too large for an ips patch (16MiB max)"),
+            PatchError::PatchChecksum { expected, computed } => write!(
+                f,
+                "patch crc is {:08X} but the patch adds up to {:08X}",
+                expected, computed
+            ),
+            PatchError::SourceChecksum { expected, computed } => write!(
+                f,
+                "patch expects a rom with crc {:08X} but this one is {:08X}",
+                expected, computed
+            ),
+            PatchError::TargetChecksum { expected, computed } => write!(
+                f,
+                "patched rom should have crc {:08X} but came out as {:08X}",
+                expected, computed
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+//patches named on the command line, or the one sitting next to the rom with the same name
+pub fn patches_for(rom_path: &Path, named: Vec<PathBuf>) -> Vec<PathBuf> {
+    if !named.is_empty() {
+        return named;
+    }
+    PATCH_EXTENSIONS
+        .iter()
+        .map(|extension| rom_path.with_extension(extension))
+        .find(|path| path.is_file())
+        .into_iter()
+        .collect()
+}
+
+//apply an ips, ups or bps patch told apart by its magic
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(UPS_MAGIC) {
+        apply_ups(rom, patch)
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(rom, patch)
+    } else {
+        Err(PatchError::UnknownFormat)
+    }
+}
+
+//records of 24 bit offset and 16 bit size followed by the bytes, a size of 0 means a run
+//of one byte repeated, then EOF and optionally the 24 bit size to truncate the rom to
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut out = rom.to_vec();
+    let mut reader = Reader::new(patch, IPS_MAGIC.len());
+    loop {
+        if reader.remaining().starts_with(IPS_EOF) {
+            reader.take(IPS_EOF.len())?;
+            break;
+        }
+        let offset = reader.be(3)?;
+        let size = reader.be(2)?;
+        let data = match size {
+            0 => {
+                let count = reader.be(2)?;
+                vec![reader.byte()?; count]
+            }
+            _ => reader.take(size)?.to_vec(),
+        };
+        if out.len() < offset + data.len() {
+            out.resize(offset + data.len(), 0);
+        }
+        out[offset..offset + data.len()].copy_from_slice(&data);
+    }
+    if reader.remaining().len() >= 3 {
+        out.truncate(reader.be(3)?);
+    }
+    Ok(out)
+}
+
+//xor of source and target for every hunk, hunks are placed by skipping bytes from the
+//end of the previous one and end on a zero byte
+fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let (body, target_crc) = check_footer(rom, patch)?;
+    let mut reader = Reader::new(body, UPS_MAGIC.len());
+    let _source_size = reader.varint()?;
+    let target_size = target_size(&mut reader)?;
+
+    let mut out = rom.to_vec();
+    out.resize(target_size, 0);
+    let mut position = 0usize;
+    while !reader.remaining().is_empty() {
+        position = position
+            .checked_add(reader.varint()?)
+            .ok_or(PatchError::Malformed)?;
+        loop {
+            let xor = reader.byte()?;
+            if position < target_size {
+                out[position] = rom.get(position).copied().unwrap_or(0) ^ xor;
+            }
+            position += 1;
+            if xor == 0 {
+                break;
+            }
+        }
+    }
+    check_target(&out, target_crc)?;
+    Ok(out)
+}
+
+//commands copying from the source, the patch or already written target, each with its
+//length packed with the command in the low 2 bits
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let (body, target_crc) = check_footer(rom, patch)?;
+    let mut reader = Reader::new(body, BPS_MAGIC.len());
+    let _source_size = reader.varint()?;
+    let target_size = target_size(&mut reader)?;
+    let metadata_size = reader.varint()?;
+    reader.take(metadata_size)?;
+
+    let mut out = Vec::with_capacity(target_size);
+    let mut source_offset = 0;
+    let mut target_offset = 0;
+    while !reader.remaining().is_empty() {
+        let action = reader.varint()?;
+        let length = (action >> 2) + 1;
+        if out.len() + length > target_size {
+            return Err(PatchError::Malformed);
+        }
+        match action & 0b11 {
+            //source read, the same bytes as in the rom
+            0 => {
+                let data = rom
+                    .get(out.len()..out.len() + length)
+                    .ok_or(PatchError::Malformed)?;
+                out.extend_from_slice(data);
+            }
+            //target read, new bytes from the patch
+            1 => out.extend_from_slice(reader.take(length)?),
+            //source copy, bytes from elsewhere in the rom
+            2 => {
+                source_offset = relative(source_offset, reader.varint()?)?;
+                let data = rom
+                    .get(source_offset..source_offset.saturating_add(length))
+                    .ok_or(PatchError::Malformed)?;
+                out.extend_from_slice(data);
+                source_offset += length;
+            }
+            //target copy, may overlap what it's writing so goes byte by byte
+            _ => {
+                target_offset = relative(target_offset, reader.varint()?)?;
+                for _ in 0..length {
+                    let byte = *out.get(target_offset).ok_or(PatchError::Malformed)?;
+                    out.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+    if out.len() != target_size {
+        return Err(PatchError::Malformed);
+    }
+    check_target(&out, target_crc)?;
+    Ok(out)
+}
+
+//smallest ips turning original into modified, bytes past the end of original are written
+//out when nonzero and the last one always so the rom grows to the right size, a shorter
+//modified rom gets the truncation size after the EOF marker
+pub fn create_ips(original: &[u8], modified: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let truncate = modified.len() < original.len();
+    if modified.len() > IPS_MAX_SIZE || (truncate && modified.len() >= IPS_MAX_SIZE) {
+        return Err(PatchError::TooLarge);
+    }
+    let differs = |i: usize| match original.get(i) {
+        Some(&byte) => byte != modified[i],
+        None => modified[i] != 0 || i + 1 == modified.len(),
+    };
+
+    let mut patch = IPS_MAGIC.to_vec();
+    let mut i = 0;
+    while i < modified.len() {
+        if !differs(i) {
+            i += 1;
+            continue;
+        }
+        //a few unchanged bytes in between cost no more than a new record
+        let mut end = i + 1;
+        while let Some(next) =
+            (end..(end + IPS_MAX_GAP + 1).min(modified.len())).find(|&j| differs(j))
+        {
+            end = next + 1;
+        }
+        //repeat the unchanged byte before rather than start a record on the marker
+        let start = if i == IPS_EOF_OFFSET { i - 1 } else { i };
+        write_ips_region(&mut patch, modified, start, end);
+        i = end;
+    }
+    patch.extend_from_slice(IPS_EOF);
+    if truncate {
+        patch.extend_from_slice(&(modified.len() as u32).to_be_bytes()[1..]);
+    }
+    Ok(patch)
+}
+
+//split a changed region into plain and run records, none of them starting on the marker
+fn write_ips_region(patch: &mut Vec<u8>, data: &[u8], start: usize, end: usize) {
+    let run_at = |position: usize| {
+        data[position..end.min(position + IPS_MAX_RECORD)]
+            .iter()
+            .take_while(|&&byte| byte == data[position])
+            .count()
+    };
+    let mut position = start;
+    while position < end {
+        let mut run = run_at(position);
+        if position + run == IPS_EOF_OFFSET && position + run < end {
+            run -= 1;
+        }
+        if run >= IPS_MIN_RUN && position != IPS_EOF_OFFSET {
+            patch.extend_from_slice(&(position as u32).to_be_bytes()[1..]);
+            patch.extend_from_slice(&[0, 0]);
+            patch.extend_from_slice(&(run as u16).to_be_bytes());
+            patch.push(data[position]);
+            position += run;
+            continue;
+        }
+
+        let mut stop = position + 1;
+        while stop < end
+            && stop - position < IPS_MAX_RECORD
+            && (stop == IPS_EOF_OFFSET || run_at(stop) < IPS_MIN_RUN)
+        {
+            stop += 1;
+        }
+        if stop == IPS_EOF_OFFSET && stop < end {
+            stop = match stop - position < IPS_MAX_RECORD {
+                true => stop + 1,
+                false => stop - 1,
+            };
+        }
+        patch.extend_from_slice(&(position as u32).to_be_bytes()[1..]);
+        patch.extend_from_slice(&((stop - position) as u16).to_be_bytes());
+        patch.extend_from_slice(&data[position..stop]);
+        position = stop;
+    }
+}
+
+//bps turning original into modified, greedily taking the longest of reading the rom in
+//place, copying from elsewhere in the rom or copying what was already written
+pub fn create_bps(original: &[u8], modified: &[u8]) -> Vec<u8> {
+    let mut patch = BPS_MAGIC.to_vec();
+    write_varint(&mut patch, original.len());
+    write_varint(&mut patch, modified.len());
+    //no metadata
+    write_varint(&mut patch, 0);
+
+    let mut sources: HashMap<u32, Vec<usize>> = HashMap::new();
+    for position in 0..original.len().saturating_sub(BPS_MIN_MATCH - 1) {
+        let candidates = sources.entry(match_key(original, position)).or_default();
+        if candidates.len() < BPS_CANDIDATES {
+            candidates.push(position);
+        }
+    }
+    let mut targets: HashMap<u32, Vec<usize>> = HashMap::new();
+    let mut indexed = 0;
+
+    let mut pending: Vec<u8> = Vec::new();
+    let mut source_offset = 0;
+    let mut target_offset = 0;
+    let mut position = 0;
+    while position < modified.len() {
+        //the target only holds what comes before position
+        while indexed + BPS_MIN_MATCH <= modified.len() && indexed < position {
+            targets
+                .entry(match_key(modified, indexed))
+                .or_default()
+                .push(indexed);
+            indexed += 1;
+        }
+
+        let rest = &modified[position..];
+        let mut best = (0, common(original.get(position..).unwrap_or(&[]), rest), 0);
+        if rest.len() >= BPS_MIN_MATCH {
+            let key = match_key(modified, position);
+            for &from in sources.get(&key).into_iter().flatten() {
+                let length = common(&original[from..], rest);
+                if length > best.1 {
+                    best = (2, length, from);
+                }
+            }
+            let recent = targets.get(&key).into_iter().flatten().rev();
+            for &from in recent.take(BPS_CANDIDATES) {
+                let length = common(&modified[from..], rest);
+                if length > best.1 {
+                    best = (3, length, from);
+                }
+            }
+        }
+
+        let (command, length, from) = best;
+        if length < BPS_MIN_MATCH {
+            pending.push(modified[position]);
+            position += 1;
+            continue;
+        }
+        write_target_read(&mut patch, &mut pending);
+        write_varint(&mut patch, (length - 1) << 2 | command);
+        match command {
+            2 => {
+                write_relative(&mut patch, source_offset, from);
+                source_offset = from + length;
+            }
+            3 => {
+                write_relative(&mut patch, target_offset, from);
+                target_offset = from + length;
+            }
+            _ => {}
+        }
+        position += length;
+    }
+    write_target_read(&mut patch, &mut pending);
+
+    patch.extend_from_slice(&crc32fast::hash(original).to_le_bytes());
+    patch.extend_from_slice(&crc32fast::hash(modified).to_le_bytes());
+    let patch_crc = crc32fast::hash(&patch);
+    patch.extend_from_slice(&patch_crc.to_le_bytes());
+    patch
+}
+
+fn match_key(data: &[u8], position: usize) -> u32 {
+    u32::from_le_bytes(data[position..position + BPS_MIN_MATCH].try_into().unwrap())
+}
+
+fn common(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+fn write_target_read(patch: &mut Vec<u8>, pending: &mut Vec<u8>) {
+    if pending.is_empty() {
+        return;
+    }
+    write_varint(patch, (pending.len() - 1) << 2 | 1);
+    patch.append(pending);
+}
+
+//signed distance with the sign in bit 0, the inverse of relative
+fn write_relative(patch: &mut Vec<u8>, offset: usize, to: usize) {
+    match to >= offset {
+        true => write_varint(patch, (to - offset) << 1),
+        false => write_varint(patch, (offset - to) << 1 | 1),
+    }
+}
+
+//the inverse of Reader::varint
+fn write_varint(patch: &mut Vec<u8>, value: usize) {
+    let mut value = value;
+    loop {
+        let low = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            patch.push(0x80 | low);
+            return;
+        }
+        patch.push(low);
+        value -= 1;
+    }
+}
+
+//verify the patch and source crcs, returns the patch without its footer and the target crc
+fn check_footer<'a>(rom: &[u8], patch: &'a [u8]) -> Result<(&'a [u8], u32), PatchError> {
+    if patch.len() < UPS_MAGIC.len() + FOOTER_SIZE {
+        return Err(PatchError::Malformed);
+    }
+    let word = |i: usize| u32::from_le_bytes(patch[i..i + 4].try_into().unwrap());
+    let footer = patch.len() - FOOTER_SIZE;
+    let (source_crc, target_crc, patch_crc) = (word(footer), word(footer + 4), word(footer + 8));
+
+    let computed = crc32fast::hash(&patch[..patch.len() - 4]);
+    if computed != patch_crc {
+        return Err(PatchError::PatchChecksum {
+            expected: patch_crc,
+            computed,
+        });
+    }
+    let computed = crc32fast::hash(rom);
+    if computed != source_crc {
+        return Err(PatchError::SourceChecksum {
+            expected: source_crc,
+            computed,
+        });
+    }
+    Ok((&patch[..footer], target_crc))
+}
+
+//ups and bps targets bigger than any rom are rejected before anything gets allocated
+fn target_size(reader: &mut Reader) -> Result<usize, PatchError> {
+    match reader.varint()? {
+        size if size > MAX_ROM_SIZE => Err(PatchError::Malformed),
+        size => Ok(size),
+    }
+}
+
+fn check_target(out: &[u8], target_crc: u32) -> Result<(), PatchError> {
+    let computed = crc32fast::hash(out);
+    if computed != target_crc {
+        return Err(PatchError::TargetChecksum {
+            expected: target_crc,
+            computed,
+        });
+    }
+    Ok(())
+}
+
+//bps offsets are signed, the sign in bit 0
+fn relative(offset: usize, value: usize) -> Result<usize, PatchError> {
+    let distance = value >> 1;
+    match value & 1 {
+        0 => offset.checked_add(distance),
+        _ => offset.checked_sub(distance),
+    }
+    .ok_or(PatchError::Malformed)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], position: usize) -> Reader<'a> {
+        Reader { data, position }
+    }
+
+    fn remaining(&self) -> &'a [u8] {
+        &self.data[self.position.min(self.data.len())..]
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], PatchError> {
+        let bytes = self
+            .data
+            .get(self.position..self.position.saturating_add(count))
+            .ok_or(PatchError::Malformed)?;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, PatchError> {
+        Ok(self.take(1)?[0])
+    }
+
+    //big endian number of count bytes, ips style
+    fn be(&mut self, count: usize) -> Result<usize, PatchError> {
+        Ok(self
+            .take(count)?
+            .iter()
+            .fold(0, |value, &byte| value << 8 | byte as usize))
+    }
+
+    //ups and bps number: 7 bits at a time, low bits first, with the high bit marking the
+    //last byte and one added per continuation so every value has a single encoding
+    fn varint(&mut self) -> Result<usize, PatchError> {
+        let mut value = 0usize;
+        let mut shift = 1usize;
+        loop {
+            let byte = self.byte()?;
+            value = (byte as usize & 0x7F)
+                .checked_mul(shift)
+                .and_then(|part| value.checked_add(part))
+                .ok_or(PatchError::Malformed)?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift.checked_mul(0x80).ok_or(PatchError::Malformed)?;
+            value = value.checked_add(shift).ok_or(PatchError::Malformed)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //ups or bps patch with the footer for turning rom into target
+    fn with_footer(mut patch: Vec<u8>, rom: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend(crc32fast::hash(rom).to_le_bytes());
+        patch.extend(crc32fast::hash(target).to_le_bytes());
+        patch.extend(crc32fast::hash(&patch).to_le_bytes());
+        patch
+    }
+
+    fn varints(magic: &[u8], values: &[usize]) -> Vec<u8> {
+        let mut patch = magic.to_vec();
+        for &value in values {
+            write_varint(&mut patch, value);
+        }
+        patch
+    }
+
+    #[test]
+    fn ips_records_runs_and_truncation() {
+        let rom = [0u8; 8];
+        let mut patch = IPS_MAGIC.to_vec();
+        //2 bytes at 1
+        patch.extend([0, 0, 1, 0, 2, 0xAA, 0xBB]);
+        //run of 3 at 6, growing the rom
+        patch.extend([0, 0, 6, 0, 0, 0, 3, 0xCC]);
+        patch.extend(IPS_EOF);
+        assert_eq!(
+            apply(&rom, &patch),
+            Ok(vec![0, 0xAA, 0xBB, 0, 0, 0, 0xCC, 0xCC, 0xCC])
+        );
+        patch.extend([0, 0, 4]);
+        assert_eq!(apply(&rom, &patch), Ok(vec![0, 0xAA, 0xBB, 0]));
+    }
+
+    #[test]
+    fn ips_cut_short() {
+        let mut patch = IPS_MAGIC.to_vec();
+        patch.extend([0, 0, 1, 0, 4, 0xAA]);
+        assert_eq!(apply(&[0; 8], &patch), Err(PatchError::Malformed));
+    }
+
+    #[test]
+    fn ups_hunks() {
+        let rom = [1u8, 2, 3, 4];
+        let target = [1u8, 0x12, 3, 4, 0, 5];
+        //skip 1, xor 0x10, then skip 2 to the grown bytes
+        let mut patch = varints(UPS_MAGIC, &[4, 6, 1]);
+        patch.extend([0x10, 0]);
+        write_varint(&mut patch, 2);
+        patch.extend([5, 0]);
+        let patch = with_footer(patch, &rom, &target);
+        assert_eq!(apply(&rom, &patch), Ok(target.to_vec()));
+    }
+
+    #[test]
+    fn bps_commands() {
+        let rom = [1u8, 2, 3, 4];
+        let target = [1u8, 2, 9, 9, 9, 3, 4];
+        //source read of 2, target read of 1, target copy of 2 from 2, source copy of 2 from 2
+        let mut patch = varints(BPS_MAGIC, &[4, 7, 0, 1 << 2]);
+        write_varint(&mut patch, 1);
+        patch.push(9);
+        patch.extend(varints(&[], &[(1 << 2) | 3, 2 << 1, (1 << 2) | 2, 2 << 1]));
+        let patch = with_footer(patch, &rom, &target);
+        assert_eq!(apply(&rom, &patch), Ok(target.to_vec()));
+    }
+
+    #[test]
+    fn huge_targets_are_rejected() {
+        let rom = [0u8; 4];
+        let patch = with_footer(varints(UPS_MAGIC, &[4, usize::MAX >> 8]), &rom, &[]);
+        assert_eq!(apply(&rom, &patch), Err(PatchError::Malformed));
+        let patch = with_footer(varints(BPS_MAGIC, &[4, MAX_ROM_SIZE + 1, 0]), &rom, &[]);
+        assert_eq!(apply(&rom, &patch), Err(PatchError::Malformed));
+    }
+
+    #[test]
+    fn unknown_format() {
+        assert_eq!(apply(&[0; 4], b"NOPE"), Err(PatchError::UnknownFormat));
+    }
+
+    //bytes that repeat, change and move around like a hacked rom
+    fn roms() -> (Vec<u8>, Vec<u8>) {
+        let original = (0..0x4000u32)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect::<Vec<_>>();
+        let mut modified = original.clone();
+        modified[0x10..0x20].fill(0xFF);
+        modified[0x100] ^= 1;
+        modified.copy_within(0x2000..0x2400, 0x3000);
+        (original, modified)
+    }
+
+    #[test]
+    fn ips_round_trip() {
+        let (original, modified) = roms();
+        for target in [
+            modified.clone(),
+            [modified.as_slice(), &[0; 0x100], &[1]].concat(),
+            modified[..0x3000].to_vec(),
+        ] {
+            let patch = create_ips(&original, &target).unwrap();
+            assert_eq!(apply(&original, &patch), Ok(target));
+        }
+    }
+
+    #[test]
+    fn ips_round_trip_around_the_eof_offset() {
+        let original = vec![0u8; IPS_EOF_OFFSET + 0x10];
+        let mut modified = original.clone();
+        modified[IPS_EOF_OFFSET - 2..IPS_EOF_OFFSET + 2].fill(0x55);
+        let patch = create_ips(&original, &modified).unwrap();
+        assert_eq!(apply(&original, &patch), Ok(modified.clone()));
+        modified[IPS_EOF_OFFSET - 2] = 0;
+        modified[IPS_EOF_OFFSET - 1] = 0;
+        let patch = create_ips(&original, &modified).unwrap();
+        assert_eq!(apply(&original, &patch), Ok(modified));
+    }
+
+    #[test]
+    fn ips_too_large() {
+        assert_eq!(
+            create_ips(&[], &vec![0; IPS_MAX_SIZE + 1]),
+            Err(PatchError::TooLarge)
+        );
+    }
+
+    #[test]
+    fn bps_round_trip() {
+        let (original, modified) = roms();
+        for target in [
+            modified.clone(),
+            [modified.as_slice(), &[9; 0x100]].concat(),
+            modified[0x1000..0x3000].to_vec(),
+            Vec::new(),
+        ] {
+            let patch = create_bps(&original, &target);
+            assert_eq!(apply(&original, &patch), Ok(target));
+        }
+    }
+
+    #[test]
+    fn checksum_mismatches() {
+        let (original, modified) = roms();
+        let patch = create_bps(&original, &modified);
+        let mut other = original.clone();
+        other[0] ^= 1;
+        assert!(matches!(
+            apply(&other, &patch),
+            Err(PatchError::SourceChecksum { .. })
+        ));
+
+        let mut corrupt = patch.clone();
+        corrupt[BPS_MAGIC.len() + 4] ^= 1;
+        assert!(matches!(
+            apply(&original, &corrupt),
+            Err(PatchError::PatchChecksum { .. })
+        ));
+
+        //a patch whose footer claims a different target
+        let body = &patch[..patch.len() - FOOTER_SIZE];
+        let wrong = with_footer(body.to_vec(), &original, &original);
+        assert!(matches!(
+            apply(&original, &wrong),
+            Err(PatchError::TargetChecksum { .. })
+        ));
+    }
+}
@@ -1,42 +1,165 @@
+mod fifo;
+
 use crate::memory::Memory;
+use fifo::PixelFifo;
+
+//how mode 3 turns tiles into pixels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Renderer {
+    //the whole line at once at the start of mode 3, with its length estimated
+    Scanline,
+    //dot by dot through the pixel fifos, picks up registers changed in the middle of a line
+    Fifo,
+}
+
+impl Renderer {
+    pub const ALL: [Renderer; 2] = [Renderer::Scanline, Renderer::Fifo];
+
+    //name accepted by --renderer
+    pub fn from_name(name: &str) -> Option<Renderer> {
+        Some(match name.to_lowercase().as_str() {
+            "scanline" => Renderer::Scanline,
+            "fifo" => Renderer::Fifo,
+            _ => return None,
+        })
+    }
+
+    //name accepted by from_name
+    pub fn name(&self) -> &'static str {
+        match self {
+            Renderer::Scanline => "scanline",
+            Renderer::Fifo => "fifo",
+        }
+    }
+}
 
 pub struct Ppu {
-    temp: u8,
     oams: [Oam; 40],
     background_line: [u8; 160],
     window_line: [u8; 160],
+    //colour numbers before bgp of whatever background or window pixel is on top, for
+    //objects that hide behind them
+    bg_colours: [u8; 160],
+    //cgb map attributes of the same pixels
+    bg_attributes: [u8; 160],
+    //shade of the object shown at each pixel, None where the background shows through
+    object_line: [Option<u8>; 160],
+    //objects found on the current line by the oam scan, in drawing priority
+    line_objects: Vec<Oam>,
+    //first pixel of the line covered by the window, if it was drawn on this line
+    window_start: Option<u8>,
+    //window row to draw next, only counts lines the window actually showed up on
+    window_line_counter: u8,
+    //set once ly matched wy during the frame, the window can't show before that
+    window_y_triggered: bool,
     vx: u8,
     vy: u8,
+    //dots into the current line
+    dots: u32,
+    //length of the drawing mode on the current line
+    drawing_dots: u32,
+    //the stat interrupt only fires when this goes from low to high
+    stat_line: bool,
+    lcd_on: bool,
+    renderer: Renderer,
+    fifo: PixelFifo,
+    //shade of every pixel of the last line drawn, in cgb mode the lines hold colour numbers
+    //and the colours from palette ram go in colour_line
+    line: [u8; 160],
+    colour_line: [u16; 160],
+    //lines of the frame being drawn, row by row
+    frame: [u8; PIXEL_SIZE as usize],
+    colour_frame: [u16; PIXEL_SIZE as usize],
+    //the last frame finished, what the lcd shows
+    completed: [u8; PIXEL_SIZE as usize],
+    colour_completed: [u16; PIXEL_SIZE as usize],
+    //whether the last frame finished was drawn in cgb mode
+    cgb_frame: bool,
+    //set when a frame was finished and not picked up yet
+    frame_ready: bool,
 }
 
-const LCDC_REGISTER: u16 = 0xFF40;
+pub(crate) const LCDC_REGISTER: u16 = 0xFF40;
 
 const OAM_MEM_START: u16 = 0xFE00;
 
 //define bitmask for each flag to access them through and operation
-const LCD_ENABLE: u8 = 0b10000000;
+pub(crate) const LCD_ENABLE: u8 = 0b10000000;
 const WINDOW_TILE_MAP: u8 = 0b01000000;
 const WINDOW_ENABLE: u8 = 0b00100000;
 const BG_AND_WINDOW_TILE_DATA: u8 = 0b00010000;
 const BG_TILE_MAP: u8 = 0b00001000;
 const OBJ_ENABLE: u8 = 0b00000100;
 const OBJ_SIZE: u8 = 0b00000010;
-const BG_AND_WINDOW_TILE_MAP: u8 = 0b00000001;
+//on dmg clearing it blanks background and window
+const BG_AND_WINDOW_ENABLE: u8 = 0b00000001;
 
-const STAT: u16 = 0xFF41;
-const MODE_FLAG: u8 = 0b00000011;
+pub(crate) const STAT: u16 = 0xFF41;
+pub(crate) const MODE_FLAG: u8 = 0b00000011;
 const LYC_FLAG: u8 = 0b00000100;
 const HBLANK_FLAG: u8 = 0b00001000;
 const VBLANK_FLAG: u8 = 0b00010000;
 const OAM_FLAG: u8 = 0b00100000;
 const LYC_INTERRUPT: u8 = 0b01000000;
-const PIXEL_WIDTH: u16 = 160;
-const PIXEL_HEIGHT: u16 = 144;
-const PIXEL_SIZE: u16 = PIXEL_WIDTH * PIXEL_HEIGHT;
+const HBLANK_MODE: u8 = 0;
+const VBLANK_MODE: u8 = 1;
+pub(crate) const OAM_SCAN_MODE: u8 = 2;
+pub(crate) const DRAWING_MODE: u8 = 3;
+//mode and ly compare bits are the ppu's, and so is ly
+pub(crate) const STAT_READ_ONLY: u8 = MODE_FLAG | LYC_FLAG;
+pub const PIXEL_WIDTH: u16 = 160;
+pub const PIXEL_HEIGHT: u16 = 144;
+pub const PIXEL_SIZE: u16 = PIXEL_WIDTH * PIXEL_HEIGHT;
+//shades 0-3 from lightest to darkest as rgb, what frame_rgba8888 and frame_rgb565 use
+//unless given other colours
+pub const GREYSCALE: [[u8; 3]; 4] = [
+    [0xFF, 0xFF, 0xFF],
+    [0xAA, 0xAA, 0xAA],
+    [0x55, 0x55, 0x55],
+    [0x00, 0x00, 0x00],
+];
 
-const LY_POSITION: u16 = 0xFF44;
+pub(crate) const LY_POSITION: u16 = 0xFF44;
+const LYC_POSITION: u16 = 0xFF45;
 const SCY_POSITION: u16 = 0xFF42;
 const SCX_POSITION: u16 = 0xFF43;
+const BGP: u16 = 0xFF47;
+const WY_POSITION: u16 = 0xFF4A;
+const WX_POSITION: u16 = 0xFF4B;
+//wx is the left edge of the window plus 7, past 166 it is off screen
+const WINDOW_X_OFFSET: u8 = 7;
+const WINDOW_X_MAX: u8 = 166;
+
+const TILE_MAP_0: u16 = 0x9800;
+const TILE_MAP_1: u16 = 0x9C00;
+//tile data is either 0x8000-0x8FFF with unsigned indices or 0x8800-0x97FF with signed
+//indices around 0x9000
+const TILE_DATA_UNSIGNED: u16 = 0x8000;
+const TILE_DATA_SIGNED: u16 = 0x9000;
+const TILE_BYTES: u16 = 16;
+const MAP_WIDTH: u16 = 32;
+
+pub(crate) const OBP0: u16 = 0xFF48;
+pub(crate) const OBP1: u16 = 0xFF49;
+//oam positions are the screen position plus 16 down and 8 right
+const OBJ_Y_OFFSET: u8 = 16;
+const OBJ_X_OFFSET: u8 = 8;
+const OBJ_WIDTH: u8 = 8;
+const OBJECTS_PER_LINE: usize = 10;
+
+const INTERRUPT_FLAG: u16 = 0xFF0F;
+const VBLANK_INTERRUPT: u8 = 0b00000001;
+const LCD_INTERRUPT: u8 = 0b00000010;
+
+const LINE_DOTS: u32 = 456;
+const OAM_SCAN_DOTS: u32 = 80;
+//drawing takes at least 172 dots, longer with fine scroll, the window and objects
+const DRAWING_DOTS: u32 = 172;
+const WINDOW_DOTS: u32 = 6;
+const OBJECT_DOTS: u32 = 6;
+//lines 144-153 are vblank
+const VBLANK_START: u8 = 144;
+const LINES: u8 = 154;
 
 #[derive(Clone, Copy)]
 struct Oam {
@@ -52,22 +175,273 @@ const X_FLIP: u8 = 0b00100000;
 const PALETTE: u8 = 0b00010000;
 const VRAM_BANK: u8 = 0b00001000;
 const CGB_PALETTE: u8 = 0b00000111;
-
-type Tile = [u8; 16];
+//what cgb colours show with the lcd off
+const WHITE: u16 = 0x7FFF;
 
 impl Ppu {
     pub fn new() -> Ppu {
         Ppu {
-            temp: 0,
             oams: [Oam {
                 y_pos: 0,
                 x_pos: 0,
                 tile_indx: 0,
                 flags: 0,
             }; 40],
+            background_line: [0; 160],
+            window_line: [0; 160],
+            bg_colours: [0; 160],
+            bg_attributes: [0; 160],
+            object_line: [None; 160],
+            line_objects: Vec::new(),
+            window_start: None,
+            window_line_counter: 0,
+            window_y_triggered: false,
+            vx: 0,
+            vy: 0,
+            dots: 0,
+            drawing_dots: DRAWING_DOTS,
+            stat_line: false,
+            lcd_on: false,
+            renderer: Renderer::Scanline,
+            fifo: PixelFifo::new(),
+            line: [0; 160],
+            colour_line: [0; 160],
+            frame: [0; PIXEL_SIZE as usize],
+            colour_frame: [0; PIXEL_SIZE as usize],
+            completed: [0; PIXEL_SIZE as usize],
+            colour_completed: [0; PIXEL_SIZE as usize],
+            cgb_frame: false,
+            frame_ready: false,
+        }
+    }
+
+    //advance by dots, which are cpu cycles at normal speed and half of them at double speed,
+    //halted tells whether the cpu is halted, which pauses hblank vram dma
+    pub fn step(&mut self, mem: &mut Memory, dots: u32, halted: bool) {
+        if mem.read_8(LCDC_REGISTER) & LCD_ENABLE == 0 {
+            if self.lcd_on {
+                self.turn_off(mem);
+            }
+            return;
+        }
+        if !self.lcd_on {
+            self.turn_on(mem);
+        }
+        for _ in 0..dots {
+            self.dot(mem, halted);
+        }
+    }
+
+    fn dot(&mut self, mem: &mut Memory, halted: bool) {
+        self.dots += 1;
+        let ly = mem.main_memory[LY_POSITION as usize];
+        if ly < VBLANK_START {
+            if self.dots == OAM_SCAN_DOTS {
+                self.start_drawing(mem);
+            } else if mem.main_memory[STAT as usize] & MODE_FLAG == DRAWING_MODE
+                && self.drawing_dot(mem)
+            {
+                set_mode(mem, HBLANK_MODE);
+                mem.hblank(halted);
+                let start = ly as usize * PIXEL_WIDTH as usize;
+                self.frame[start..start + PIXEL_WIDTH as usize].copy_from_slice(&self.line);
+                self.colour_frame[start..start + PIXEL_WIDTH as usize]
+                    .copy_from_slice(&self.colour_line);
+            }
+        }
+        if self.dots == LINE_DOTS {
+            self.dots = 0;
+            let ly = (ly + 1) % LINES;
+            mem.main_memory[LY_POSITION as usize] = ly;
+            match ly {
+                0 => {
+                    self.start_frame();
+                    self.start_line(mem);
+                }
+                1..VBLANK_START => self.start_line(mem),
+                VBLANK_START => {
+                    self.completed = self.frame;
+                    self.colour_completed = self.colour_frame;
+                    self.cgb_frame = mem.cgb_mode();
+                    self.frame_ready = true;
+                    set_mode(mem, VBLANK_MODE);
+                    mem.main_memory[INTERRUPT_FLAG as usize] |= VBLANK_INTERRUPT;
+                }
+                _ => {}
+            }
+        }
+        self.update_stat(mem);
+    }
+
+    fn start_line(&mut self, mem: &mut Memory) {
+        set_mode(mem, OAM_SCAN_MODE);
+        self.scan_oam(mem);
+    }
+
+    //whether a frame was finished since the last call, at the start of vblank or when the
+    //lcd was turned off
+    pub fn take_frame_ready(&mut self) -> bool {
+        std::mem::take(&mut self.frame_ready)
+    }
+
+    //shade 0-3 of every pixel of the last finished frame, row by row, in cgb mode the colour
+    //number within the pixel's palette
+    pub fn frame(&self) -> &[u8; PIXEL_SIZE as usize] {
+        &self.completed
+    }
+
+    //15 bit colour of every pixel of the last finished frame, only drawn in cgb mode
+    pub fn colour_frame(&self) -> &[u16; PIXEL_SIZE as usize] {
+        &self.colour_completed
+    }
+
+    //the last finished frame as 4 bytes of red, green, blue and alpha per pixel, colours
+    //gives the rgb of each dmg shade while cgb frames have their own
+    pub fn frame_rgba8888(&self, colours: &[[u8; 3]; 4]) -> Vec<u8> {
+        (0..PIXEL_SIZE as usize)
+            .flat_map(|i| {
+                let [r, g, b] = self.pixel_rgb(i, colours);
+                [r, g, b, 0xFF]
+            })
+            .collect()
+    }
+
+    //the last finished frame with 5 bits of red, 6 of green and 5 of blue per pixel
+    pub fn frame_rgb565(&self, colours: &[[u8; 3]; 4]) -> Vec<u16> {
+        (0..PIXEL_SIZE as usize)
+            .map(|i| {
+                let [r, g, b] = self.pixel_rgb(i, colours);
+                (r as u16 >> 3) << 11 | (g as u16 >> 2) << 5 | b as u16 >> 3
+            })
+            .collect()
+    }
+
+    fn pixel_rgb(&self, i: usize, colours: &[[u8; 3]; 4]) -> [u8; 3] {
+        match self.cgb_frame {
+            //5 bits per channel, the top bits repeated below to reach full brightness
+            true => {
+                let colour = self.colour_frame()[i];
+                [colour, colour >> 5, colour >> 10].map(|channel| {
+                    let channel = (channel & 0x1F) as u8;
+                    channel << 3 | channel >> 2
+                })
+            }
+            false => colours[self.frame()[i] as usize],
+        }
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+    }
+
+    fn start_drawing(&mut self, mem: &mut Memory) {
+        match self.renderer {
+            //the line is drawn in one go at the start of drawing
+            Renderer::Scanline => {
+                self.render_line(mem);
+                self.drawing_dots = self.drawing_dots(mem);
+            }
+            Renderer::Fifo => {
+                self.check_window_y(mem);
+                let window_row = self.window_y_triggered.then_some(self.window_line_counter);
+                self.fifo.start_line(mem, window_row, &self.line_objects);
+            }
+        }
+        set_mode(mem, DRAWING_MODE);
+    }
+
+    //one dot of mode 3, true when it is over
+    fn drawing_dot(&mut self, mem: &Memory) -> bool {
+        match self.renderer {
+            Renderer::Scanline => self.dots == OAM_SCAN_DOTS + self.drawing_dots,
+            Renderer::Fifo => {
+                let done = self.fifo.dot(mem, &mut self.line, &mut self.colour_line);
+                if done && self.fifo.window_drawn {
+                    self.window_line_counter += 1;
+                }
+                done
+            }
         }
     }
 
+    fn render_line(&mut self, mem: &Memory) {
+        self.load_backgroundline(mem);
+        self.load_windowline(mem);
+        self.load_objectline(mem);
+        for (i, pixel) in self.line.iter_mut().enumerate() {
+            let window = matches!(self.window_start, Some(start) if i >= start as usize);
+            *pixel = match (self.object_line[i], window) {
+                (Some(object), _) => object,
+                (None, true) => self.window_line[i],
+                (None, false) => self.background_line[i],
+            };
+        }
+    }
+
+    //fine scroll throws away pixels at the start of the line, the window restarts the
+    //fetcher and every object on screen stalls it
+    fn drawing_dots(&self, mem: &Memory) -> u32 {
+        let scroll = (mem.read_8(SCX_POSITION) % 8) as u32;
+        let window = match self.window_start {
+            Some(_) => WINDOW_DOTS,
+            None => 0,
+        };
+        let objects = match mem.read_8(LCDC_REGISTER) & OBJ_ENABLE {
+            0 => 0,
+            _ => self
+                .line_objects
+                .iter()
+                .filter(|oam| oam.x_pos < PIXEL_WIDTH as u8 + OBJ_X_OFFSET)
+                .count() as u32,
+        };
+        DRAWING_DOTS + scroll + window + objects * OBJECT_DOTS
+    }
+
+    //the lcd comes back on at the start of line 0
+    fn turn_on(&mut self, mem: &mut Memory) {
+        self.lcd_on = true;
+        self.dots = 0;
+        mem.main_memory[LY_POSITION as usize] = 0;
+        self.start_frame();
+        self.start_line(mem);
+        self.update_stat(mem);
+    }
+
+    //with the lcd off ly stays at 0 in hblank and the screen goes blank
+    fn turn_off(&mut self, mem: &mut Memory) {
+        self.lcd_on = false;
+        self.completed = [0; PIXEL_SIZE as usize];
+        self.colour_completed = [WHITE; PIXEL_SIZE as usize];
+        self.cgb_frame = mem.cgb_mode();
+        self.frame_ready = true;
+        self.dots = 0;
+        self.stat_line = false;
+        mem.main_memory[LY_POSITION as usize] = 0;
+        set_mode(mem, HBLANK_MODE);
+    }
+
+    //compare ly to lyc and request the stat interrupt when any enabled source turns on
+    //while none already was, so one source blocks the others until they all go low
+    fn update_stat(&mut self, mem: &mut Memory) {
+        let ly = mem.main_memory[LY_POSITION as usize];
+        let lyc = mem.main_memory[LYC_POSITION as usize];
+        let mut stat = mem.main_memory[STAT as usize] & !LYC_FLAG;
+        if ly == lyc {
+            stat |= LYC_FLAG;
+        }
+        mem.main_memory[STAT as usize] = stat;
+        let line = match stat & MODE_FLAG {
+            HBLANK_MODE => stat & HBLANK_FLAG != 0,
+            VBLANK_MODE => stat & VBLANK_FLAG != 0,
+            OAM_SCAN_MODE => stat & OAM_FLAG != 0,
+            _ => false,
+        } || (stat & LYC_FLAG != 0 && stat & LYC_INTERRUPT != 0);
+        if line && !self.stat_line {
+            mem.main_memory[INTERRUPT_FLAG as usize] |= LCD_INTERRUPT;
+        }
+        self.stat_line = line;
+    }
+
     fn load_oam(&mut self, mem: &Memory) {
         let mut oams = [Oam {
             y_pos: 0,
@@ -76,27 +450,1152 @@ impl Ppu {
             flags: 0,
         }; 40];
 
-        for i in 0..39 {
-            let oam = &mut oams[i];
+        for (i, oam) in oams.iter_mut().enumerate() {
             let oam_addr = OAM_MEM_START + (i * 4) as u16;
-            oam.y_pos = mem.read_8(oam_addr);
-            oam.x_pos = mem.read_8(oam_addr + 1);
-            oam.tile_indx = mem.read_8(oam_addr + 2);
-            oam.flags = mem.read_8(oam_addr + 3);
+            oam.y_pos = mem.read_oam(oam_addr);
+            oam.x_pos = mem.read_oam(oam_addr + 1);
+            oam.tile_indx = mem.read_oam(oam_addr + 2);
+            oam.flags = mem.read_oam(oam_addr + 3);
         }
 
         self.oams = oams;
     }
 
+    //background colour of each pixel of the current line, after bgp on dmg
     fn load_backgroundline(&mut self, mem: &Memory) {
+        let lcdc = mem.read_8(LCDC_REGISTER);
+        let bgp = mem.read_8(BGP);
+        let cgb = mem.cgb_mode();
         let mut background_line: [u8; 160] = [0; 160];
-        let y = self.vy;
-        let x = self.vx;
-        let ty = y >> 3;
-        for i in 0..159 {
-            let x_pos = x.wrapping_add(i as u8);
-            let tx = x_pos >> 3;
-            let addr = 0x9800 + (ty * 0x20) + tx;
+        let mut bg_colours: [u8; 160] = [0; 160];
+        let mut bg_attributes: [u8; 160] = [0; 160];
+        //on cgb bit 0 only takes priority away from the background and window
+        if cgb || lcdc & BG_AND_WINDOW_ENABLE != 0 {
+            let map = match lcdc & BG_TILE_MAP {
+                0 => TILE_MAP_0,
+                _ => TILE_MAP_1,
+            };
+            self.vy = mem
+                .read_8(LY_POSITION)
+                .wrapping_add(mem.read_8(SCY_POSITION));
+            self.vx = mem.read_8(SCX_POSITION);
+            for (i, pixel) in background_line.iter_mut().enumerate() {
+                let x = self.vx.wrapping_add(i as u8);
+                let (colour, attributes) = tile_map_pixel(mem, lcdc, map, x, self.vy);
+                bg_colours[i] = colour;
+                bg_attributes[i] = attributes;
+                *pixel = match cgb {
+                    true => {
+                        self.colour_line[i] = mem.bg_colour(attributes & CGB_PALETTE, colour);
+                        colour
+                    }
+                    false => apply_palette(bgp, colour),
+                };
+            }
+        }
+        self.background_line = background_line;
+        self.bg_colours = bg_colours;
+        self.bg_attributes = bg_attributes;
+    }
+
+    //the window only shows up once ly matched wy during the frame
+    fn check_window_y(&mut self, mem: &Memory) {
+        if mem.read_8(LY_POSITION) == mem.read_8(WY_POSITION) {
+            self.window_y_triggered = true;
+        }
+    }
+
+    //the window starts over from its first row every frame
+    fn start_frame(&mut self) {
+        self.window_line_counter = 0;
+        self.window_y_triggered = false;
+    }
+
+    //window colour of each pixel of the current line from window_start on, after bgp on dmg,
+    //goes after the background since it covers it in bg_colours
+    fn load_windowline(&mut self, mem: &Memory) {
+        let lcdc = mem.read_8(LCDC_REGISTER);
+        let cgb = mem.cgb_mode();
+        let wx = mem.read_8(WX_POSITION);
+        self.check_window_y(mem);
+        let mut window_line: [u8; 160] = [0; 160];
+        self.window_start = None;
+        //on dmg the window goes away with the background
+        if self.window_y_triggered
+            && lcdc & WINDOW_ENABLE != 0
+            && (cgb || lcdc & BG_AND_WINDOW_ENABLE != 0)
+            && wx <= WINDOW_X_MAX
+        {
+            let bgp = mem.read_8(BGP);
+            let map = match lcdc & WINDOW_TILE_MAP {
+                0 => TILE_MAP_0,
+                _ => TILE_MAP_1,
+            };
+            //below 7 the window still starts at the left edge but its first columns are
+            //cut off
+            let start = wx.saturating_sub(WINDOW_X_OFFSET);
+            for (i, pixel) in window_line.iter_mut().enumerate().skip(start as usize) {
+                let x = i as u8 + WINDOW_X_OFFSET - wx;
+                let (colour, attributes) =
+                    tile_map_pixel(mem, lcdc, map, x, self.window_line_counter);
+                self.bg_colours[i] = colour;
+                self.bg_attributes[i] = attributes;
+                *pixel = match cgb {
+                    true => {
+                        self.colour_line[i] = mem.bg_colour(attributes & CGB_PALETTE, colour);
+                        colour
+                    }
+                    false => apply_palette(bgp, colour),
+                };
+            }
+            self.window_start = Some(start);
+            self.window_line_counter += 1;
+        }
+        self.window_line = window_line;
+    }
+
+    //the first 10 objects in oam overlapping the current line, whether they are on screen
+    //horizontally or not
+    fn scan_oam(&mut self, mem: &Memory) {
+        self.load_oam(mem);
+        let height = object_height(mem.read_8(LCDC_REGISTER));
+        let line = mem.read_8(LY_POSITION).wrapping_add(OBJ_Y_OFFSET);
+        let mut objects = self
+            .oams
+            .iter()
+            .filter(|oam| line.wrapping_sub(oam.y_pos) < height)
+            .take(OBJECTS_PER_LINE)
+            .copied()
+            .collect::<Vec<_>>();
+        //on dmg the object further left wins, the stable sort leaves ties in oam order, on
+        //cgb oam order alone decides
+        if !mem.cgb_mode() {
+            objects.sort_by_key(|oam| oam.x_pos);
+        }
+        self.line_objects = objects;
+    }
+
+    //shade of the objects found by the oam scan over the current line, after obp0 or obp1 on
+    //dmg, goes after the background and window since objects can hide behind them
+    fn load_objectline(&mut self, mem: &Memory) {
+        let lcdc = mem.read_8(LCDC_REGISTER);
+        let cgb = mem.cgb_mode();
+        let mut object_line: [Option<u8>; 160] = [None; 160];
+        if lcdc & OBJ_ENABLE != 0 {
+            let height = object_height(lcdc);
+            let line = mem.read_8(LY_POSITION).wrapping_add(OBJ_Y_OFFSET);
+            let obp0 = mem.read_8(OBP0);
+            let obp1 = mem.read_8(OBP1);
+            for (i, pixel) in object_line.iter_mut().enumerate() {
+                let screen_x = i as u8 + OBJ_X_OFFSET;
+                //a transparent pixel lets the next object in priority through
+                let found = self.line_objects.iter().find_map(|oam| {
+                    let x = screen_x.wrapping_sub(oam.x_pos);
+                    let y = line.wrapping_sub(oam.y_pos);
+                    if x >= OBJ_WIDTH || y >= height {
+                        return None;
+                    }
+                    match object_pixel(mem, oam, x, y, height) {
+                        0 => None,
+                        colour => Some((oam, colour)),
+                    }
+                });
+                let Some((oam, colour)) = found else {
+                    continue;
+                };
+                if !object_on_top(
+                    cgb,
+                    lcdc,
+                    self.bg_colours[i],
+                    self.bg_attributes[i],
+                    oam.flags,
+                ) {
+                    continue;
+                }
+                *pixel = Some(match cgb {
+                    true => {
+                        self.colour_line[i] = mem.obj_colour(oam.flags & CGB_PALETTE, colour);
+                        colour
+                    }
+                    false => {
+                        let palette = match oam.flags & PALETTE {
+                            0 => obp0,
+                            _ => obp1,
+                        };
+                        apply_palette(palette, colour)
+                    }
+                });
+            }
+        }
+        self.object_line = object_line;
+    }
+}
+
+fn set_mode(mem: &mut Memory, mode: u8) {
+    let stat = &mut mem.main_memory[STAT as usize];
+    *stat = (*stat & !MODE_FLAG) | mode;
+}
+
+fn object_height(lcdc: u8) -> u8 {
+    match lcdc & OBJ_SIZE {
+        0 => 8,
+        _ => 16,
+    }
+}
+
+//whether an object pixel shows over the background or window pixel under it, the priority
+//bit puts the background on top of it unless the background is colour 0, on cgb the map
+//attributes can do the same and clearing lcdc bit 0 puts objects on top regardless
+fn object_on_top(cgb: bool, lcdc: u8, bg_colour: u8, bg_attributes: u8, flags: u8) -> bool {
+    match cgb {
+        true => {
+            lcdc & BG_AND_WINDOW_ENABLE == 0
+                || bg_colour == 0
+                || (flags | bg_attributes) & PRIORITY == 0
+        }
+        false => bg_colour == 0 || flags & PRIORITY == 0,
+    }
+}
+
+//colour number 0-3 at x, y of an object, tiles always come from 0x8000 and tall objects
+//use the even tile of a pair for their top half
+fn object_pixel(mem: &Memory, oam: &Oam, x: u8, y: u8, height: u8) -> u8 {
+    let (x, y) = flip(oam.flags, x, y, height);
+    let tile = match height {
+        16 => oam.tile_indx & 0xFE,
+        _ => oam.tile_indx,
+    };
+    //dmg games leave garbage in the cgb bits
+    let bank = match mem.cgb_mode() {
+        true => vram_bank(oam.flags),
+        false => 0,
+    };
+    tile_pixel(
+        mem,
+        bank,
+        TILE_DATA_UNSIGNED + tile as u16 * TILE_BYTES,
+        x,
+        y,
+    )
+}
+
+//colour number 0-3 at x, y of the 256x256 picture a tile map describes, with the map
+//attributes in cgb mode
+fn tile_map_pixel(mem: &Memory, lcdc: u8, map: u16, x: u8, y: u8) -> (u8, u8) {
+    let tile_x = (x / 8) as u16;
+    let tile_y = (y / 8) as u16;
+    let address = map + tile_y * MAP_WIDTH + tile_x;
+    let tile = mem.read_vram(0, address);
+    let attributes = map_attributes(mem, address);
+    let (x, y) = flip(attributes, x % 8, y % 8, 8);
+    let colour = tile_pixel(mem, vram_bank(attributes), tile_address(lcdc, tile), x, y);
+    (colour, attributes)
+}
+
+//on cgb the map entry at the same address in vram bank 1 holds the tile's palette, bank,
+//flips and priority in the same bits as object flags
+fn map_attributes(mem: &Memory, address: u16) -> u8 {
+    match mem.cgb_mode() {
+        true => mem.read_vram(1, address),
+        false => 0,
+    }
+}
+
+fn vram_bank(flags: u8) -> u8 {
+    match flags & VRAM_BANK {
+        0 => 0,
+        _ => 1,
+    }
+}
+
+//x, y within a tile or object of the given height after the flips in its flags
+fn flip(flags: u8, x: u8, y: u8, height: u8) -> (u8, u8) {
+    let x = match flags & X_FLIP {
+        0 => x,
+        _ => OBJ_WIDTH - 1 - x,
+    };
+    let y = match flags & Y_FLIP {
+        0 => y,
+        _ => height - 1 - y,
+    };
+    (x, y)
+}
+
+//where the background and window find a tile from the map
+fn tile_address(lcdc: u8, tile: u8) -> u16 {
+    match lcdc & BG_AND_WINDOW_TILE_DATA {
+        0 => TILE_DATA_SIGNED.wrapping_add_signed(tile as i8 as i16 * TILE_BYTES as i16),
+        _ => TILE_DATA_UNSIGNED + tile as u16 * TILE_BYTES,
+    }
+}
+
+//a tile row is two bytes, the low and high bit of every pixel with the leftmost in bit 7
+fn tile_pixel(mem: &Memory, bank: u8, tile_address: u16, x: u8, y: u8) -> u8 {
+    let row = tile_address + y as u16 * 2;
+    let low = mem.read_vram(bank, row);
+    let high = mem.read_vram(bank, row + 1);
+    let bit = 7 - x;
+    ((high >> bit) & 1) << 1 | ((low >> bit) & 1)
+}
+
+//shade for a colour number, two bits per colour in the palette register
+fn apply_palette(palette: u8, colour: u8) -> u8 {
+    (palette >> (colour * 2)) & 0b11
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::palette::AUTO_INCREMENT;
+    use crate::memory::{BCPD, BCPS, OCPD, OCPS, VBK};
+
+    const IDENTITY_PALETTE: u8 = 0b11100100;
+
+    //tile whose every row is the given pair of bit planes
+    fn fill_tile(mem: &mut Memory, address: u16, low: u8, high: u8) {
+        for row in 0..8 {
+            mem.write_8(address + row * 2, low);
+            mem.write_8(address + row * 2 + 1, high);
+        }
+    }
+
+    fn memory(lcdc: u8) -> Memory {
+        let mut mem = Memory::new();
+        mem.write_8(LCDC_REGISTER, lcdc);
+        mem.write_8(BGP, IDENTITY_PALETTE);
+        mem
+    }
+
+    fn render(mem: &Memory) -> [u8; 160] {
+        let mut ppu = Ppu::new();
+        ppu.load_backgroundline(mem);
+        ppu.background_line
+    }
+
+    #[test]
+    fn unsigned_tile_data() {
+        let mut mem = memory(LCD_ENABLE | BG_AND_WINDOW_TILE_DATA | BG_AND_WINDOW_ENABLE);
+        //colours 0 1 2 3 0 1 2 3
+        fill_tile(
+            &mut mem,
+            TILE_DATA_UNSIGNED + TILE_BYTES,
+            0b01010101,
+            0b00110011,
+        );
+        for tile in 0..MAP_WIDTH {
+            mem.write_8(TILE_MAP_0 + tile, 1);
+        }
+        let line = render(&mem);
+        assert_eq!(line[..8], [0, 1, 2, 3, 0, 1, 2, 3]);
+        assert_eq!(line[152..], [0, 1, 2, 3, 0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn signed_tile_data() {
+        let mut mem = memory(LCD_ENABLE | BG_AND_WINDOW_ENABLE);
+        //tile 0x80 is -128, the first one at 0x8800, and tile 0 sits at 0x9000
+        fill_tile(&mut mem, 0x8800, 0xFF, 0x00);
+        fill_tile(&mut mem, TILE_DATA_SIGNED, 0x00, 0xFF);
+        mem.write_8(TILE_MAP_0, 0x80);
+        mem.write_8(TILE_MAP_0 + 1, 0x00);
+        let line = render(&mem);
+        assert_eq!(line[..8], [1; 8]);
+        assert_eq!(line[8..16], [2; 8]);
+    }
+
+    #[test]
+    fn tile_map_select() {
+        let mut mem =
+            memory(LCD_ENABLE | BG_TILE_MAP | BG_AND_WINDOW_TILE_DATA | BG_AND_WINDOW_ENABLE);
+        fill_tile(&mut mem, TILE_DATA_UNSIGNED + TILE_BYTES, 0xFF, 0xFF);
+        mem.write_8(TILE_MAP_0, 1);
+        mem.write_8(TILE_MAP_1 + 1, 1);
+        let line = render(&mem);
+        assert_eq!(line[..8], [0; 8]);
+        assert_eq!(line[8..16], [3; 8]);
+    }
+
+    #[test]
+    fn fine_scroll() {
+        let mut mem = memory(LCD_ENABLE | BG_AND_WINDOW_TILE_DATA | BG_AND_WINDOW_ENABLE);
+        //only the top row of tile 1 is set, and only its leftmost pixel
+        mem.write_8(TILE_DATA_UNSIGNED + TILE_BYTES + 2 * 3, 0b10000000);
+        //tile column 1, tile row 2
+        mem.write_8(TILE_MAP_0 + 2 * MAP_WIDTH + 1, 1);
+        //line 5 + 14 is row 3 of tile row 2, x 8 is at screen x 5
+        mem.write_8(SCY_POSITION, 14);
+        mem.write_8(SCX_POSITION, 3);
+        mem.main_memory[LY_POSITION as usize] = 5;
+        let line = render(&mem);
+        assert_eq!(line.iter().position(|&pixel| pixel != 0), Some(5));
+        assert_eq!(line.iter().filter(|&&pixel| pixel != 0).count(), 1);
+    }
+
+    #[test]
+    fn scroll_wraps_around_the_map() {
+        let mut mem = memory(LCD_ENABLE | BG_AND_WINDOW_TILE_DATA | BG_AND_WINDOW_ENABLE);
+        fill_tile(&mut mem, TILE_DATA_UNSIGNED + TILE_BYTES, 0xFF, 0x00);
+        mem.write_8(TILE_MAP_0, 1);
+        //x 252 to 255 are the last tile, then x 0 comes back around at screen x 4
+        mem.write_8(SCX_POSITION, 252);
+        let line = render(&mem);
+        assert_eq!(line[..4], [0; 4]);
+        assert_eq!(line[4..12], [1; 8]);
+    }
+
+    #[test]
+    fn palette_and_disable() {
+        let mut mem = memory(LCD_ENABLE | BG_AND_WINDOW_TILE_DATA | BG_AND_WINDOW_ENABLE);
+        fill_tile(&mut mem, TILE_DATA_UNSIGNED, 0b01010101, 0b00110011);
+        mem.write_8(BGP, 0b00011011);
+        assert_eq!(render(&mem)[..4], [3, 2, 1, 0]);
+
+        mem.write_8(LCDC_REGISTER, LCD_ENABLE | BG_AND_WINDOW_TILE_DATA);
+        assert_eq!(render(&mem), [0; 160]);
+    }
+
+    const WINDOW_LCDC: u8 = LCD_ENABLE
+        | WINDOW_TILE_MAP
+        | WINDOW_ENABLE
+        | BG_AND_WINDOW_TILE_DATA
+        | BG_AND_WINDOW_ENABLE;
+
+    //window map at 0x9C00 made of tile 1, whose rows are the colour of their row number
+    fn window_memory() -> Memory {
+        let mut mem = memory(WINDOW_LCDC);
+        for row in 0..8 {
+            let address = TILE_DATA_UNSIGNED + TILE_BYTES + row * 2;
+            mem.write_8(address, if row & 1 != 0 { 0xFF } else { 0 });
+            mem.write_8(address + 1, if row & 2 != 0 { 0xFF } else { 0 });
+        }
+        for tile in 0..MAP_WIDTH * 32 {
+            mem.write_8(TILE_MAP_1 + tile, 1);
+        }
+        mem
+    }
+
+    fn render_window(ppu: &mut Ppu, mem: &mut Memory, ly: u8) -> Option<u8> {
+        mem.main_memory[LY_POSITION as usize] = ly;
+        ppu.load_windowline(mem);
+        ppu.window_start
+    }
+
+    #[test]
+    fn window_at_wx_7_covers_the_line() {
+        let mut mem = window_memory();
+        mem.write_8(WX_POSITION, 7);
+        let mut ppu = Ppu::new();
+        assert_eq!(render_window(&mut ppu, &mut mem, 0), Some(0));
+        assert_eq!(ppu.window_line, [0; 160]);
+        assert_eq!(render_window(&mut ppu, &mut mem, 1), Some(0));
+        assert_eq!(ppu.window_line, [1; 160]);
+    }
+
+    #[test]
+    fn window_waits_for_wy() {
+        let mut mem = window_memory();
+        mem.write_8(WX_POSITION, 7);
+        mem.write_8(WY_POSITION, 10);
+        let mut ppu = Ppu::new();
+        assert_eq!(render_window(&mut ppu, &mut mem, 9), None);
+        assert_eq!(render_window(&mut ppu, &mut mem, 10), Some(0));
+        //moving wy afterwards doesn't hide it again for the rest of the frame
+        mem.write_8(WY_POSITION, 100);
+        assert_eq!(render_window(&mut ppu, &mut mem, 11), Some(0));
+        assert_eq!(ppu.window_line[0], 1);
+
+        ppu.start_frame();
+        assert_eq!(render_window(&mut ppu, &mut mem, 12), None);
+    }
+
+    #[test]
+    fn line_counter_skips_lines_without_window() {
+        let mut mem = window_memory();
+        mem.write_8(WX_POSITION, 7);
+        let mut ppu = Ppu::new();
+        render_window(&mut ppu, &mut mem, 0);
+        mem.write_8(LCDC_REGISTER, WINDOW_LCDC & !WINDOW_ENABLE);
+        assert_eq!(render_window(&mut ppu, &mut mem, 1), None);
+        mem.write_8(WX_POSITION, WINDOW_X_MAX + 1);
+        mem.write_8(LCDC_REGISTER, WINDOW_LCDC);
+        assert_eq!(render_window(&mut ppu, &mut mem, 2), None);
+        //line 3 of the screen shows row 1 of the window
+        mem.write_8(WX_POSITION, 7);
+        render_window(&mut ppu, &mut mem, 3);
+        assert_eq!(ppu.window_line, [1; 160]);
+    }
+
+    #[test]
+    fn window_x_position() {
+        let mut mem = window_memory();
+        let mut ppu = Ppu::new();
+        render_window(&mut ppu, &mut mem, 0);
+        render_window(&mut ppu, &mut mem, 1);
+        //row 2 is colour 2, the window starts 20 pixels in
+        mem.write_8(WX_POSITION, 27);
+        assert_eq!(render_window(&mut ppu, &mut mem, 2), Some(20));
+        assert_eq!(ppu.window_line[..20], [0; 20]);
+        assert_eq!(ppu.window_line[20..], [2; 140]);
+        //a single column at the right edge
+        mem.write_8(WX_POSITION, WINDOW_X_MAX);
+        assert_eq!(render_window(&mut ppu, &mut mem, 3), Some(159));
+        assert_eq!(ppu.window_line[159], 3);
+    }
+
+    #[test]
+    fn window_left_of_wx_7_is_cut_off() {
+        let mut mem = window_memory();
+        //only the first column of tile 2 is set, tile 2 is the second on the row
+        mem.write_8(TILE_DATA_UNSIGNED + TILE_BYTES * 2, 0b10000000);
+        mem.write_8(TILE_MAP_1 + 1, 2);
+        mem.write_8(WX_POSITION, 3);
+        let mut ppu = Ppu::new();
+        assert_eq!(render_window(&mut ppu, &mut mem, 0), Some(0));
+        //window x 8 lands 4 pixels earlier than with wx at 7
+        assert_eq!(
+            ppu.window_line.iter().position(|&pixel| pixel != 0),
+            Some(4)
+        );
+    }
+
+    const OBJ_LCDC: u8 = LCD_ENABLE | BG_AND_WINDOW_TILE_DATA | BG_AND_WINDOW_ENABLE | OBJ_ENABLE;
+
+    fn set_object(mem: &mut Memory, index: u16, y: u8, x: u8, tile: u8, flags: u8) {
+        let address = OAM_MEM_START + index * 4;
+        for (i, value) in [y, x, tile, flags].into_iter().enumerate() {
+            mem.write_8(address + i as u16, value);
+        }
+    }
+
+    fn object_memory(lcdc: u8) -> Memory {
+        let mut mem = memory(lcdc);
+        mem.write_8(OBP0, IDENTITY_PALETTE);
+        mem.write_8(OBP1, IDENTITY_PALETTE);
+        //tile 1 is colour 1 all over, tile 2 colour 2, tile 3 colour 3
+        for tile in 1..4 {
+            let (low, high) = (tile as u8 & 1, tile as u8 >> 1);
+            fill_tile(
+                &mut mem,
+                TILE_DATA_UNSIGNED + tile * TILE_BYTES,
+                low * 0xFF,
+                high * 0xFF,
+            );
+        }
+        mem
+    }
+
+    fn render_objects(mem: &mut Memory, ly: u8) -> Ppu {
+        mem.main_memory[LY_POSITION as usize] = ly;
+        let mut ppu = Ppu::new();
+        ppu.load_backgroundline(mem);
+        ppu.load_windowline(mem);
+        ppu.scan_oam(mem);
+        ppu.load_objectline(mem);
+        ppu
+    }
+
+    #[test]
+    fn all_40_objects_are_loaded() {
+        let mut mem = object_memory(OBJ_LCDC);
+        set_object(&mut mem, 39, 16, 8, 1, 0);
+        let ppu = render_objects(&mut mem, 0);
+        assert_eq!(ppu.object_line[0], Some(1));
+    }
+
+    #[test]
+    fn ten_objects_per_line() {
+        let mut mem = object_memory(OBJ_LCDC);
+        //objects off screen to the left still count
+        set_object(&mut mem, 0, 16, 0, 1, 0);
+        for i in 1..12 {
+            set_object(&mut mem, i, 16, i as u8 * 8, 1, 0);
+        }
+        let ppu = render_objects(&mut mem, 0);
+        assert_eq!(ppu.line_objects.len(), OBJECTS_PER_LINE);
+        //object 9 is the last one drawn, at screen x 64
+        assert_eq!(ppu.object_line[64..72], [Some(1); 8]);
+        assert_eq!(ppu.object_line[72], None);
+        //the objects are 8 tall
+        let ppu = render_objects(&mut mem, 8);
+        assert!(ppu.line_objects.is_empty());
+    }
+
+    #[test]
+    fn leftmost_object_wins() {
+        let mut mem = object_memory(OBJ_LCDC);
+        set_object(&mut mem, 0, 16, 12, 1, 0);
+        set_object(&mut mem, 1, 16, 10, 2, 0);
+        //same x as object 1 but later in oam
+        set_object(&mut mem, 2, 16, 10, 3, 0);
+        let ppu = render_objects(&mut mem, 0);
+        assert_eq!(
+            ppu.object_line[1..11],
+            [
+                None,
+                Some(2),
+                Some(2),
+                Some(2),
+                Some(2),
+                Some(2),
+                Some(2),
+                Some(2),
+                Some(2),
+                Some(1)
+            ]
+        );
+    }
+
+    #[test]
+    fn transparent_pixels_show_the_next_object() {
+        let mut mem = object_memory(OBJ_LCDC);
+        //tile 4 only has its left half set
+        fill_tile(&mut mem, TILE_DATA_UNSIGNED + 4 * TILE_BYTES, 0xF0, 0xF0);
+        set_object(&mut mem, 0, 16, 8, 4, 0);
+        set_object(&mut mem, 1, 16, 9, 1, 0);
+        let ppu = render_objects(&mut mem, 0);
+        assert_eq!(ppu.object_line[3], Some(3));
+        assert_eq!(ppu.object_line[4..9], [Some(1); 5]);
+    }
+
+    #[test]
+    fn flips_and_palettes() {
+        let mut mem = object_memory(OBJ_LCDC);
+        //tile 4 has only its top left pixel set
+        mem.write_8(TILE_DATA_UNSIGNED + 4 * TILE_BYTES, 0b10000000);
+        set_object(&mut mem, 0, 16, 8, 4, X_FLIP | Y_FLIP | PALETTE);
+        mem.write_8(OBP1, 0b00001000);
+        let ppu = render_objects(&mut mem, 0);
+        assert_eq!(ppu.object_line[..8], [None; 8]);
+        let ppu = render_objects(&mut mem, 7);
+        assert_eq!(
+            ppu.object_line[..8],
+            [None, None, None, None, None, None, None, Some(2)]
+        );
+    }
+
+    #[test]
+    fn background_priority() {
+        let mut mem = object_memory(OBJ_LCDC);
+        //background colour 3 on the left 8 pixels, colour 0 after
+        mem.write_8(TILE_MAP_0, 3);
+        mem.write_8(BGP, 0);
+        set_object(&mut mem, 0, 16, 12, 1, PRIORITY);
+        let ppu = render_objects(&mut mem, 0);
+        assert_eq!(
+            ppu.object_line[4..12],
+            [None, None, None, None, Some(1), Some(1), Some(1), Some(1)]
+        );
+
+        mem.write_8(LCDC_REGISTER, OBJ_LCDC & !OBJ_ENABLE);
+        let ppu = render_objects(&mut mem, 0);
+        assert_eq!(ppu.object_line, [None; 160]);
+    }
+
+    #[test]
+    fn tall_objects() {
+        let mut mem = object_memory(OBJ_LCDC | OBJ_SIZE);
+        //the low bit of the tile index is ignored, tile 2 on top of tile 3
+        set_object(&mut mem, 0, 16, 8, 3, 0);
+        set_object(&mut mem, 1, 16, 16, 3, Y_FLIP);
+        let ppu = render_objects(&mut mem, 0);
+        assert_eq!(ppu.object_line[0], Some(2));
+        assert_eq!(ppu.object_line[8], Some(3));
+        let ppu = render_objects(&mut mem, 15);
+        assert_eq!(ppu.object_line[0], Some(3));
+        assert_eq!(ppu.object_line[8], Some(2));
+    }
+
+    fn mode(mem: &Memory) -> u8 {
+        mem.read_8(STAT) & MODE_FLAG
+    }
+
+    fn ly(mem: &Memory) -> u8 {
+        mem.read_8(LY_POSITION)
+    }
+
+    fn take_interrupts(mem: &mut Memory) -> u8 {
+        std::mem::take(&mut mem.main_memory[INTERRUPT_FLAG as usize])
+    }
+
+    #[test]
+    fn modes_through_a_line() {
+        let mut mem = memory(LCD_ENABLE | BG_AND_WINDOW_ENABLE);
+        let mut ppu = Ppu::new();
+        ppu.step(&mut mem, 0, false);
+        assert_eq!((ly(&mem), mode(&mem)), (0, OAM_SCAN_MODE));
+        ppu.step(&mut mem, OAM_SCAN_DOTS - 1, false);
+        assert_eq!(mode(&mem), OAM_SCAN_MODE);
+        ppu.step(&mut mem, 1, false);
+        assert_eq!(mode(&mem), DRAWING_MODE);
+        ppu.step(&mut mem, DRAWING_DOTS - 1, false);
+        assert_eq!(mode(&mem), DRAWING_MODE);
+        ppu.step(&mut mem, 1, false);
+        assert_eq!(mode(&mem), HBLANK_MODE);
+        ppu.step(
+            &mut mem,
+            LINE_DOTS - OAM_SCAN_DOTS - DRAWING_DOTS - 1,
+            false,
+        );
+        assert_eq!((ly(&mem), mode(&mem)), (0, HBLANK_MODE));
+        ppu.step(&mut mem, 1, false);
+        assert_eq!((ly(&mem), mode(&mem)), (1, OAM_SCAN_MODE));
+    }
+
+    #[test]
+    fn drawing_gets_longer_with_scroll_and_objects() {
+        let mut mem = memory(LCD_ENABLE | BG_AND_WINDOW_ENABLE | OBJ_ENABLE);
+        mem.write_8(SCX_POSITION, 3);
+        set_object(&mut mem, 0, 16, 8, 0, 0);
+        //off screen to the right, found by the scan but never fetched
+        set_object(&mut mem, 1, 16, 200, 0, 0);
+        let mut ppu = Ppu::new();
+        ppu.step(
+            &mut mem,
+            OAM_SCAN_DOTS + DRAWING_DOTS + 3 + OBJECT_DOTS - 1,
+            false,
+        );
+        assert_eq!(mode(&mem), DRAWING_MODE);
+        ppu.step(&mut mem, 1, false);
+        assert_eq!(mode(&mem), HBLANK_MODE);
+    }
+
+    #[test]
+    fn vblank_and_frame_length() {
+        let mut mem = memory(LCD_ENABLE | BG_AND_WINDOW_ENABLE);
+        let mut ppu = Ppu::new();
+        ppu.step(&mut mem, LINE_DOTS * VBLANK_START as u32 - 1, false);
+        assert_eq!(take_interrupts(&mut mem) & VBLANK_INTERRUPT, 0);
+        ppu.step(&mut mem, 1, false);
+        assert_eq!((ly(&mem), mode(&mem)), (VBLANK_START, VBLANK_MODE));
+        assert_eq!(take_interrupts(&mut mem), VBLANK_INTERRUPT);
+        ppu.step(
+            &mut mem,
+            LINE_DOTS * (LINES - VBLANK_START) as u32 - 1,
+            false,
+        );
+        assert_eq!((ly(&mem), mode(&mem)), (LINES - 1, VBLANK_MODE));
+        ppu.step(&mut mem, 1, false);
+        assert_eq!((ly(&mem), mode(&mem)), (0, OAM_SCAN_MODE));
+        assert_eq!(take_interrupts(&mut mem), 0);
+    }
+
+    #[test]
+    fn lyc_compare() {
+        let mut mem = memory(LCD_ENABLE | BG_AND_WINDOW_ENABLE);
+        mem.write_8(LYC_POSITION, 2);
+        mem.write_8(STAT, LYC_INTERRUPT);
+        let mut ppu = Ppu::new();
+        ppu.step(&mut mem, LINE_DOTS * 2 - 1, false);
+        assert_eq!(mem.read_8(STAT) & LYC_FLAG, 0);
+        assert_eq!(take_interrupts(&mut mem), 0);
+        ppu.step(&mut mem, 1, false);
+        assert_eq!(mem.read_8(STAT) & LYC_FLAG, LYC_FLAG);
+        assert_eq!(take_interrupts(&mut mem), LCD_INTERRUPT);
+        ppu.step(&mut mem, LINE_DOTS, false);
+        assert_eq!(mem.read_8(STAT) & LYC_FLAG, 0);
+        //the cpu can't clear the mode or compare bits
+        mem.write_8(STAT, 0);
+        assert_eq!(mode(&mem), OAM_SCAN_MODE);
+        mem.write_8(LY_POSITION, 100);
+        assert_eq!(ly(&mem), 3);
+    }
+
+    #[test]
+    fn stat_interrupt_blocking() {
+        let mut mem = memory(LCD_ENABLE | BG_AND_WINDOW_ENABLE);
+        //ly matches lyc through all of line 0, so the hblank and oam scan sources that turn
+        //on during it don't make the line go high again
+        mem.write_8(STAT, LYC_INTERRUPT | HBLANK_FLAG | OAM_FLAG);
+        let mut ppu = Ppu::new();
+        ppu.step(&mut mem, 1, false);
+        assert_eq!(take_interrupts(&mut mem), LCD_INTERRUPT);
+        ppu.step(&mut mem, LINE_DOTS - 1, false);
+        assert_eq!(take_interrupts(&mut mem), 0);
+        //hblank to oam scan on line 1 without the line dropping in between
+        ppu.step(&mut mem, OAM_SCAN_DOTS + DRAWING_DOTS, false);
+        assert_eq!(take_interrupts(&mut mem), LCD_INTERRUPT);
+        ppu.step(&mut mem, LINE_DOTS - OAM_SCAN_DOTS - DRAWING_DOTS, false);
+        assert_eq!(take_interrupts(&mut mem), 0);
+    }
+
+    #[test]
+    fn lcd_off_resets_ly() {
+        let mut mem = memory(LCD_ENABLE | BG_AND_WINDOW_ENABLE);
+        let mut ppu = Ppu::new();
+        ppu.step(&mut mem, LINE_DOTS * 10 + 100, false);
+        mem.write_8(LCDC_REGISTER, BG_AND_WINDOW_ENABLE);
+        ppu.step(&mut mem, 4, false);
+        assert_eq!((ly(&mem), mode(&mem)), (0, HBLANK_MODE));
+        mem.write_8(LCDC_REGISTER, LCD_ENABLE | BG_AND_WINDOW_ENABLE);
+        ppu.step(&mut mem, LINE_DOTS - 1, false);
+        assert_eq!(ly(&mem), 0);
+        ppu.step(&mut mem, 1, false);
+        assert_eq!(ly(&mem), 1);
+    }
+
+    //draw line ly with a fresh ppu, giving the line and how long mode 3 took
+    fn draw_line(mem: &mut Memory, renderer: Renderer, ly: u8) -> ([u8; 160], u32) {
+        let (ppu, dots) = drawn_ppu(mem, renderer, ly);
+        (ppu.line, dots)
+    }
+
+    fn drawn_ppu(mem: &mut Memory, renderer: Renderer, ly: u8) -> (Ppu, u32) {
+        let mut ppu = Ppu::new();
+        ppu.set_renderer(renderer);
+        ppu.step(mem, LINE_DOTS * ly as u32 + OAM_SCAN_DOTS, false);
+        let mut dots = 0;
+        while mode(mem) == DRAWING_MODE {
+            ppu.step(mem, 1, false);
+            dots += 1;
+        }
+        (ppu, dots)
+    }
+
+    //background scrolled by a non multiple of 8, a window and objects on both
+    fn busy_memory() -> Memory {
+        let mut mem = object_memory(OBJ_LCDC | WINDOW_ENABLE | WINDOW_TILE_MAP);
+        fill_tile(
+            &mut mem,
+            TILE_DATA_UNSIGNED + 4 * TILE_BYTES,
+            0b01010101,
+            0b00110011,
+        );
+        for tile in 0..MAP_WIDTH * 32 {
+            mem.write_8(TILE_MAP_0 + tile, (tile % 5) as u8);
+            mem.write_8(TILE_MAP_1 + tile, 4 - (tile % 3) as u8);
+        }
+        mem.write_8(SCX_POSITION, 13);
+        mem.write_8(SCY_POSITION, 3);
+        mem.write_8(WY_POSITION, 0);
+        mem.write_8(WX_POSITION, 100);
+        mem.write_8(BGP, 0b00100111);
+        mem.write_8(OBP1, 0b11000110);
+        set_object(&mut mem, 0, 16, 4, 4, 0);
+        set_object(&mut mem, 1, 18, 30, 2, PALETTE | X_FLIP);
+        set_object(&mut mem, 2, 16, 34, 3, PRIORITY);
+        set_object(&mut mem, 3, 16, 104, 4, Y_FLIP);
+        set_object(&mut mem, 4, 16, 170, 1, 0);
+        mem
+    }
+
+    #[test]
+    fn fifo_matches_scanline() {
+        for wx in [0, 3, 7, 100, WINDOW_X_MAX, WINDOW_X_MAX + 1] {
+            let mut mem = busy_memory();
+            mem.write_8(WX_POSITION, wx);
+            for ly in [0, 5] {
+                let (scanline, _) = draw_line(&mut mem, Renderer::Scanline, ly);
+                let (fifo, _) = draw_line(&mut mem, Renderer::Fifo, ly);
+                assert_eq!(fifo, scanline, "wx {} ly {}", wx, ly);
+            }
+        }
+    }
+
+    #[test]
+    fn fifo_drawing_length() {
+        let mut mem = memory(LCD_ENABLE | BG_AND_WINDOW_ENABLE);
+        assert_eq!(draw_line(&mut mem, Renderer::Fifo, 0).1, DRAWING_DOTS);
+        mem.write_8(SCX_POSITION, 5);
+        assert_eq!(draw_line(&mut mem, Renderer::Fifo, 0).1, DRAWING_DOTS + 5);
+        let scroll_only = draw_line(&mut mem, Renderer::Fifo, 0).1;
+        mem.write_8(
+            LCDC_REGISTER,
+            LCD_ENABLE | BG_AND_WINDOW_ENABLE | OBJ_ENABLE,
+        );
+        set_object(&mut mem, 0, 16, 50, 0, 0);
+        let one_object = draw_line(&mut mem, Renderer::Fifo, 0).1;
+        assert!(one_object >= scroll_only + OBJECT_DOTS);
+        assert!(one_object <= scroll_only + 11);
+    }
+
+    #[test]
+    fn fifo_picks_up_palette_changes_mid_line() {
+        let mut mem = memory(LCD_ENABLE | BG_AND_WINDOW_TILE_DATA | BG_AND_WINDOW_ENABLE);
+        fill_tile(&mut mem, TILE_DATA_UNSIGNED, 0xFF, 0xFF);
+        let mut ppu = Ppu::new();
+        ppu.set_renderer(Renderer::Fifo);
+        ppu.step(&mut mem, OAM_SCAN_DOTS + DRAWING_DOTS / 2, false);
+        mem.write_8(BGP, 0);
+        ppu.step(&mut mem, DRAWING_DOTS, false);
+        assert_eq!(ppu.line[0], 3);
+        assert_eq!(ppu.line[159], 0);
+        let changed = ppu.line.iter().position(|&pixel| pixel == 0).unwrap();
+        assert!(ppu.line[changed..].iter().all(|&pixel| pixel == 0));
+    }
+
+    #[test]
+    fn renderer_names() {
+        for renderer in Renderer::ALL {
+            assert_eq!(Renderer::from_name(renderer.name()), Some(renderer));
+        }
+        assert_eq!(Renderer::from_name("FIFO"), Some(Renderer::Fifo));
+        assert_eq!(Renderer::from_name("scanline"), Some(Renderer::Scanline));
+        assert_eq!(Renderer::from_name("ntsc"), None);
+    }
+
+    const FRAME_DOTS: u32 = LINE_DOTS * LINES as u32;
+
+    #[test]
+    fn frames() {
+        for renderer in [Renderer::Scanline, Renderer::Fifo] {
+            //the window map is filled with tile 1, use it for the background too
+            let mut mem = window_memory();
+            mem.write_8(LCDC_REGISTER, WINDOW_LCDC & !WINDOW_ENABLE | BG_TILE_MAP);
+            let mut ppu = Ppu::new();
+            ppu.set_renderer(renderer);
+            ppu.step(&mut mem, LINE_DOTS * VBLANK_START as u32 - 1, false);
+            assert!(!ppu.take_frame_ready());
+            ppu.step(&mut mem, 1, false);
+            assert!(ppu.take_frame_ready());
+            assert!(!ppu.take_frame_ready());
+            for (i, &shade) in ppu.frame().iter().enumerate() {
+                let y = i / PIXEL_WIDTH as usize;
+                assert_eq!(shade, (y % 8) as u8 & 0b11);
+            }
+            ppu.step(&mut mem, FRAME_DOTS - 1, false);
+            assert!(!ppu.take_frame_ready());
+            ppu.step(&mut mem, 1, false);
+            assert!(ppu.take_frame_ready());
+
+            mem.write_8(LCDC_REGISTER, 0);
+            ppu.step(&mut mem, 1, false);
+            assert!(ppu.take_frame_ready());
+            assert_eq!(ppu.frame(), &[0; PIXEL_SIZE as usize]);
+        }
+    }
+
+    #[test]
+    fn frame_colour_formats() {
+        let mut mem = window_memory();
+        mem.write_8(LCDC_REGISTER, WINDOW_LCDC & !WINDOW_ENABLE | BG_TILE_MAP);
+        let mut ppu = Ppu::new();
+        ppu.step(&mut mem, LINE_DOTS * VBLANK_START as u32, false);
+        let row = PIXEL_WIDTH as usize;
+
+        let rgba = ppu.frame_rgba8888(&GREYSCALE);
+        assert_eq!(rgba.len(), PIXEL_SIZE as usize * 4);
+        assert_eq!(rgba[..4], [0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(rgba[row * 4..row * 4 + 4], [0xAA, 0xAA, 0xAA, 0xFF]);
+        assert_eq!(rgba[row * 3 * 4..row * 3 * 4 + 4], [0x00, 0x00, 0x00, 0xFF]);
+
+        let colours = [
+            [0xFF, 0x00, 0x00],
+            [0x00, 0xFF, 0x00],
+            [0x00, 0x00, 0xFF],
+            [0x08, 0x04, 0x08],
+        ];
+        let rgb565 = ppu.frame_rgb565(&colours);
+        assert_eq!(rgb565.len(), PIXEL_SIZE as usize);
+        assert_eq!(rgb565[0], 0xF800);
+        assert_eq!(rgb565[row], 0x07E0);
+        assert_eq!(rgb565[row * 2], 0x001F);
+        assert_eq!(rgb565[row * 3], 0x0821);
+    }
+
+    const RENDERERS: [Renderer; 2] = [Renderer::Scanline, Renderer::Fifo];
+
+    //a different colour for every palette and colour number, objects with the top bit set
+    fn bg_rgb(palette: u8, colour: u8) -> u16 {
+        (palette as u16) << 8 | (colour as u16) << 2 | 1
+    }
+
+    fn obj_rgb(palette: u8, colour: u8) -> u16 {
+        bg_rgb(palette, colour) | 0x4000
+    }
+
+    fn cgb_memory(lcdc: u8) -> Memory {
+        let mut mem = object_memory(lcdc);
+        load_cgb_palettes(&mut mem);
+        mem
+    }
+
+    fn load_cgb_palettes(mem: &mut Memory) {
+        mem.set_cgb_mode(true);
+        for (select, data, rgb) in [
+            (BCPS, BCPD, bg_rgb as fn(u8, u8) -> u16),
+            (OCPS, OCPD, obj_rgb),
+        ] {
+            mem.write_8(select, AUTO_INCREMENT);
+            for palette in 0..8 {
+                for colour in 0..4 {
+                    for byte in rgb(palette, colour).to_le_bytes() {
+                        mem.write_8(data, byte);
+                    }
+                }
+            }
+        }
+    }
+
+    fn write_bank_1(mem: &mut Memory, address: u16, value: u8) {
+        mem.write_8(VBK, 1);
+        mem.write_8(address, value);
+        mem.write_8(VBK, 0);
+    }
+
+    #[test]
+    fn palette_ram() {
+        let mut mem = memory(LCD_ENABLE);
+        mem.set_cgb_mode(true);
+        mem.write_8(BCPS, AUTO_INCREMENT | 0x3E);
+        mem.write_8(BCPD, 0x12);
+        mem.write_8(BCPD, 0x34);
+        //the index wraps around, bit 6 reads as 1
+        assert_eq!(mem.read_8(BCPS), AUTO_INCREMENT | 0x40);
+        assert_eq!(mem.bg_colour(7, 3), 0x3412);
+        mem.write_8(BCPS, 0x3F);
+        assert_eq!(mem.read_8(BCPD), 0x34);
+        mem.write_8(BCPD, 0x56);
+        mem.write_8(BCPD, 0x78);
+        assert_eq!(mem.read_8(BCPS), 0x7F);
+        assert_eq!(mem.bg_colour(7, 3), 0x7812);
+        //object palettes are separate
+        mem.write_8(OCPS, 0x3F);
+        assert_ne!(mem.read_8(OCPD), 0x78);
+
+        //while drawing the cpu can't get at them but writes still move the index on
+        mem.write_8(OCPS, AUTO_INCREMENT);
+        set_mode(&mut mem, DRAWING_MODE);
+        assert_eq!(mem.read_8(OCPD), 0xFF);
+        mem.write_8(OCPD, 0x00);
+        set_mode(&mut mem, HBLANK_MODE);
+        assert_eq!(mem.read_8(OCPS), AUTO_INCREMENT | 0x41);
+        assert_eq!(mem.obj_colour(0, 0), 0xFFFF);
+    }
+
+    #[test]
+    fn cgb_background_attributes() {
+        for renderer in RENDERERS {
+            let mut mem = cgb_memory(LCD_ENABLE | BG_AND_WINDOW_TILE_DATA | BG_AND_WINDOW_ENABLE);
+            //tile 1 in bank 1 has colour 3 on its left half only, the bank 0 one is colour 1
+            for row in 0..16 {
+                write_bank_1(&mut mem, TILE_DATA_UNSIGNED + TILE_BYTES + row, 0xF0);
+            }
+            mem.write_8(TILE_MAP_0, 1);
+            write_bank_1(&mut mem, TILE_MAP_0, 5 | VRAM_BANK | X_FLIP);
+            //tile 5 has only its top row set
+            mem.write_8(TILE_DATA_UNSIGNED + 5 * TILE_BYTES, 0xFF);
+            mem.write_8(TILE_MAP_0 + 1, 5);
+            write_bank_1(&mut mem, TILE_MAP_0 + 1, 2 | Y_FLIP);
+
+            let (ppu, _) = drawn_ppu(&mut mem, renderer, 0);
+            assert_eq!(ppu.line[..8], [0, 0, 0, 0, 3, 3, 3, 3], "{:?}", renderer);
+            assert_eq!(ppu.colour_line[0], bg_rgb(5, 0));
+            assert_eq!(ppu.colour_line[4], bg_rgb(5, 3));
+            assert_eq!(ppu.colour_line[8], bg_rgb(2, 0));
+            let (ppu, _) = drawn_ppu(&mut mem, renderer, 7);
+            assert_eq!(ppu.line[8..16], [1; 8], "{:?}", renderer);
+            assert_eq!(ppu.colour_line[8], bg_rgb(2, 1));
+        }
+    }
+
+    #[test]
+    fn cgb_objects() {
+        for renderer in RENDERERS {
+            let mut mem = cgb_memory(OBJ_LCDC);
+            //on cgb oam order wins over x
+            set_object(&mut mem, 0, 16, 12, 1, 2);
+            set_object(&mut mem, 1, 16, 10, 2, 3);
+            //the bank bit picks the tile from bank 1, which is empty
+            set_object(&mut mem, 2, 16, 40, 3, VRAM_BANK);
+            let (ppu, _) = drawn_ppu(&mut mem, renderer, 0);
+            assert_eq!(
+                ppu.line[2..12],
+                [2, 2, 1, 1, 1, 1, 1, 1, 1, 1],
+                "{:?}",
+                renderer
+            );
+            assert_eq!(ppu.colour_line[2], obj_rgb(3, 2));
+            assert_eq!(ppu.colour_line[4], obj_rgb(2, 1));
+            assert_eq!(ppu.colour_line[32], bg_rgb(0, 0));
+        }
+    }
+
+    #[test]
+    fn cgb_priority() {
+        for renderer in RENDERERS {
+            let lcdc = OBJ_LCDC;
+            let mut mem = cgb_memory(lcdc);
+            //background colour 3 everywhere, with the priority attribute on the second tile
+            for tile in 0..MAP_WIDTH {
+                mem.write_8(TILE_MAP_0 + tile, 3);
+            }
+            write_bank_1(&mut mem, TILE_MAP_0 + 1, PRIORITY);
+            set_object(&mut mem, 0, 16, 8, 1, 0);
+            set_object(&mut mem, 1, 16, 16, 1, 0);
+            set_object(&mut mem, 2, 16, 24, 1, PRIORITY);
+            let (ppu, _) = drawn_ppu(&mut mem, renderer, 0);
+            assert_eq!(ppu.line[..8], [1; 8], "{:?}", renderer);
+            assert_eq!(ppu.line[8..24], [3; 16], "{:?}", renderer);
+            assert_eq!(ppu.colour_line[8], bg_rgb(0, 3));
+
+            //with bit 0 clear the background still shows but objects go on top of it
+            mem.write_8(LCDC_REGISTER, lcdc & !BG_AND_WINDOW_ENABLE);
+            let (ppu, _) = drawn_ppu(&mut mem, renderer, 0);
+            assert_eq!(ppu.line[..24], [1; 24], "{:?}", renderer);
+            assert_eq!(ppu.colour_line[8], obj_rgb(0, 1));
+            assert_eq!(ppu.line[24], 3);
+            assert_eq!(ppu.colour_line[24], bg_rgb(0, 3));
+        }
+    }
+
+    #[test]
+    fn cgb_frame_colours() {
+        let mut mem = cgb_memory(LCD_ENABLE | BG_AND_WINDOW_ENABLE);
+        mem.write_8(BCPS, 0);
+        mem.write_8(BCPD, 0xE0);
+        mem.write_8(BCPS, 1);
+        mem.write_8(BCPD, 0x03);
+        let mut ppu = Ppu::new();
+        ppu.step(&mut mem, LINE_DOTS * VBLANK_START as u32, false);
+        assert!(ppu.take_frame_ready());
+        assert_eq!(ppu.colour_frame()[0], 0x03E0);
+        assert_eq!(
+            ppu.frame_rgba8888(&GREYSCALE)[..4],
+            [0x00, 0xFF, 0x00, 0xFF]
+        );
+        assert_eq!(ppu.frame_rgb565(&GREYSCALE)[0], 0x07E0);
+
+        mem.write_8(LCDC_REGISTER, 0);
+        ppu.step(&mut mem, 1, false);
+        assert_eq!(ppu.frame_rgba8888(&GREYSCALE)[..4], [0xFF; 4]);
+    }
+
+    #[test]
+    fn fifo_matches_scanline_in_cgb_mode() {
+        let mut mem = busy_memory();
+        load_cgb_palettes(&mut mem);
+        for tile in 0..MAP_WIDTH * 32 {
+            let attributes = match tile % 7 {
+                0 => PRIORITY | 1,
+                1 => X_FLIP | Y_FLIP | 2,
+                2 => VRAM_BANK | 3,
+                _ => tile as u8 & CGB_PALETTE,
+            };
+            write_bank_1(&mut mem, TILE_MAP_0 + tile, attributes);
+            write_bank_1(&mut mem, TILE_MAP_1 + tile, attributes ^ X_FLIP);
+        }
+        fill_tile(&mut mem, TILE_DATA_UNSIGNED + TILE_BYTES, 0x0F, 0x3C);
+        for tile in 0..5 {
+            let address = TILE_DATA_UNSIGNED + tile * TILE_BYTES;
+            mem.write_8(VBK, 1);
+            fill_tile(&mut mem, address, 0b10110001, 0b01100110);
+            mem.write_8(VBK, 0);
+        }
+        for lcdc in [
+            OBJ_LCDC | WINDOW_ENABLE | WINDOW_TILE_MAP,
+            OBJ_LCDC & !BG_AND_WINDOW_ENABLE,
+        ] {
+            mem.write_8(LCDC_REGISTER, lcdc);
+            for ly in [0, 1, 5] {
+                let (scanline, _) = drawn_ppu(&mut mem, Renderer::Scanline, ly);
+                let (fifo, _) = drawn_ppu(&mut mem, Renderer::Fifo, ly);
+                assert_eq!(fifo.line, scanline.line, "lcdc {:X} ly {}", lcdc, ly);
+                assert_eq!(
+                    fifo.colour_line, scanline.colour_line,
+                    "lcdc {:X} ly {}",
+                    lcdc, ly
+                );
+            }
         }
     }
 }
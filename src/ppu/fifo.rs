@@ -0,0 +1,346 @@
+use std::collections::VecDeque;
+
+use super::{
+    apply_palette, map_attributes, object_height, object_on_top, object_pixel, tile_address,
+    vram_bank, Oam, BGP, BG_AND_WINDOW_ENABLE, BG_TILE_MAP, CGB_PALETTE, LCDC_REGISTER,
+    LY_POSITION, MAP_WIDTH, OBJ_ENABLE, OBJ_WIDTH, OBJ_X_OFFSET, OBJ_Y_OFFSET, OBP0, OBP1, PALETTE,
+    PIXEL_WIDTH, SCX_POSITION, SCY_POSITION, TILE_MAP_0, TILE_MAP_1, WINDOW_ENABLE,
+    WINDOW_TILE_MAP, WINDOW_X_MAX, WINDOW_X_OFFSET, WX_POSITION, X_FLIP, Y_FLIP,
+};
+use crate::memory::Memory;
+
+//every fetcher step but the push takes 2 dots
+const FETCH_STEP_DOTS: u8 = 2;
+//the first tile of a line is fetched twice, the first one is thrown away
+const LINE_START_DOTS: u8 = 6;
+const OBJECT_FETCH_DOTS: u8 = 6;
+const TILE_WIDTH: usize = 8;
+
+#[derive(Clone, Copy, PartialEq)]
+enum FetchStep {
+    Tile,
+    DataLow,
+    DataHigh,
+    //waits for the background fifo to run empty
+    Push,
+}
+
+#[derive(Clone, Copy, Default)]
+struct ObjectPixel {
+    colour: u8,
+    flags: u8,
+    //place of the object in drawing priority, the lower one wins where objects overlap
+    rank: usize,
+}
+
+//dot by dot mode 3: a fetcher fills the background fifo one tile at a time, objects are
+//fetched into their own fifo as the lcd reaches them, and one pixel a dot is mixed from
+//the two with the registers as they are at that dot
+pub struct PixelFifo {
+    //colour numbers with the cgb map attributes of their tile
+    background: VecDeque<(u8, u8)>,
+    objects: VecDeque<ObjectPixel>,
+    step: FetchStep,
+    //dots spent on the current fetcher step
+    step_dots: u8,
+    //tile column the fetcher is at, relative to scx or to the window's left edge
+    fetcher_x: u8,
+    tile: u8,
+    attributes: u8,
+    low: u8,
+    high: u8,
+    //pixels already sent to the lcd
+    x: u8,
+    //pixels still to drop from the front of the background fifo, for fine scroll
+    discard: u8,
+    //dots left before anything happens, at the start of the line
+    stall: u8,
+    //window row while the window is being fetched, None for the background
+    window: Option<u8>,
+    //the window row to use if the window starts on this line
+    window_row: Option<u8>,
+    //whether the window made it onto this line, for the window line counter
+    pub window_drawn: bool,
+    //objects of the oam scan from left to right, with their rank in drawing priority
+    objects_by_x: Vec<(usize, Oam)>,
+    //next of them not yet fetched
+    next_object: usize,
+    //object being fetched with its rank, and the dots left
+    object_fetch: Option<(usize, Oam, u8)>,
+}
+
+impl PixelFifo {
+    pub fn new() -> PixelFifo {
+        PixelFifo {
+            background: VecDeque::with_capacity(16),
+            objects: VecDeque::with_capacity(16),
+            step: FetchStep::Tile,
+            step_dots: 0,
+            fetcher_x: 0,
+            tile: 0,
+            attributes: 0,
+            low: 0,
+            high: 0,
+            x: 0,
+            discard: 0,
+            stall: 0,
+            window: None,
+            window_row: None,
+            window_drawn: false,
+            objects_by_x: Vec::new(),
+            next_object: 0,
+            object_fetch: None,
+        }
+    }
+
+    //get ready to draw a line, window_row is the row of the window to draw if it was
+    //triggered by wy this frame and objects are the ones the oam scan found in drawing
+    //priority
+    pub fn start_line(&mut self, mem: &Memory, window_row: Option<u8>, objects: &[Oam]) {
+        let mut objects_by_x = objects.iter().copied().enumerate().collect::<Vec<_>>();
+        objects_by_x.sort_by_key(|(_, oam)| oam.x_pos);
+        *self = PixelFifo {
+            discard: mem.read_8(SCX_POSITION) % 8,
+            stall: LINE_START_DOTS,
+            window_row,
+            objects_by_x,
+            ..PixelFifo::new()
+        };
+    }
+
+    //run one dot of mode 3 into the shades of line, and in cgb mode the colours of
+    //colour_line, returns true once the 160th pixel is out
+    pub fn dot(
+        &mut self,
+        mem: &Memory,
+        line: &mut [u8; 160],
+        colour_line: &mut [u16; 160],
+    ) -> bool {
+        if self.stall > 0 {
+            self.stall -= 1;
+            return false;
+        }
+        //the fetcher and the lcd both stop while an object is fetched
+        if let Some((rank, oam, dots)) = self.object_fetch {
+            match dots {
+                1 => {
+                    self.object_fetch = None;
+                    self.merge_object(mem, rank, oam);
+                }
+                _ => self.object_fetch = Some((rank, oam, dots - 1)),
+            }
+            return false;
+        }
+        if self.start_window(mem) {
+            return false;
+        }
+        self.fetch(mem);
+        //checked after the fetcher so an object at the first pixel isn't missed on the dot
+        //the first tile is pushed
+        if self.discard == 0 && self.start_object_fetch(mem) {
+            return false;
+        }
+        self.output(mem, line, colour_line)
+    }
+
+    //the next object whose left edge the lcd reached, objects hanging off the left edge are
+    //all fetched at the first pixel
+    fn start_object_fetch(&mut self, mem: &Memory) -> bool {
+        if mem.read_8(LCDC_REGISTER) & OBJ_ENABLE == 0 || self.background.is_empty() {
+            return false;
+        }
+        match self.objects_by_x.get(self.next_object) {
+            Some(&(rank, oam)) if oam.x_pos <= self.x + OBJ_X_OFFSET => {
+                self.next_object += 1;
+                self.object_fetch = Some((rank, oam, OBJECT_FETCH_DOTS));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    //a pixel already in the object fifo stays unless it's transparent or the new object
+    //comes first in drawing priority, which on dmg it never does as it is further right
+    fn merge_object(&mut self, mem: &Memory, rank: usize, oam: Oam) {
+        let height = object_height(mem.read_8(LCDC_REGISTER));
+        let y = mem
+            .read_8(LY_POSITION)
+            .wrapping_add(OBJ_Y_OFFSET)
+            .wrapping_sub(oam.y_pos);
+        if y >= height {
+            return;
+        }
+        //columns already left of the lcd aren't drawn
+        let skip = (self.x + OBJ_X_OFFSET).saturating_sub(oam.x_pos);
+        while self.objects.len() < OBJ_WIDTH as usize {
+            self.objects.push_back(ObjectPixel::default());
+        }
+        for x in skip..OBJ_WIDTH {
+            let slot = &mut self.objects[(x - skip) as usize];
+            let colour = object_pixel(mem, &oam, x, y, height);
+            if colour != 0 && (slot.colour == 0 || rank < slot.rank) {
+                *slot = ObjectPixel {
+                    colour,
+                    flags: oam.flags,
+                    rank,
+                };
+            }
+        }
+    }
+
+    //switch the fetcher to the window once the lcd reaches wx, throwing away the
+    //background pixels already fetched
+    fn start_window(&mut self, mem: &Memory) -> bool {
+        let Some(row) = self.window_row else {
+            return false;
+        };
+        let lcdc = mem.read_8(LCDC_REGISTER);
+        let wx = mem.read_8(WX_POSITION);
+        if self.window.is_some()
+            || lcdc & WINDOW_ENABLE == 0
+            || (!mem.cgb_mode() && lcdc & BG_AND_WINDOW_ENABLE == 0)
+            || wx > WINDOW_X_MAX
+            || self.x + WINDOW_X_OFFSET < wx
+        {
+            return false;
+        }
+        self.window = Some(row);
+        self.window_drawn = true;
+        self.background.clear();
+        self.step = FetchStep::Tile;
+        self.step_dots = 0;
+        self.fetcher_x = 0;
+        //below 7 the first columns of the window are cut off instead
+        self.discard = match self.x {
+            0 => WINDOW_X_OFFSET.saturating_sub(wx),
+            _ => 0,
+        };
+        true
+    }
+
+    fn fetch(&mut self, mem: &Memory) {
+        if self.step == FetchStep::Push {
+            if self.background.is_empty() {
+                for x in 0..TILE_WIDTH {
+                    let bit = match self.attributes & X_FLIP {
+                        0 => TILE_WIDTH - 1 - x,
+                        _ => x,
+                    };
+                    let colour = ((self.high >> bit) & 1) << 1 | ((self.low >> bit) & 1);
+                    self.background.push_back((colour, self.attributes));
+                }
+                self.fetcher_x = self.fetcher_x.wrapping_add(1);
+                self.step = FetchStep::Tile;
+            }
+            return;
+        }
+        self.step_dots += 1;
+        if self.step_dots < FETCH_STEP_DOTS {
+            return;
+        }
+        self.step_dots = 0;
+        let lcdc = mem.read_8(LCDC_REGISTER);
+        self.step = match self.step {
+            FetchStep::Tile => {
+                let address = self.map_address(mem, lcdc);
+                self.tile = mem.read_vram(0, address);
+                self.attributes = map_attributes(mem, address);
+                FetchStep::DataLow
+            }
+            FetchStep::DataLow => {
+                let bank = vram_bank(self.attributes);
+                self.low = mem.read_vram(bank, self.row_address(mem, lcdc));
+                FetchStep::DataHigh
+            }
+            FetchStep::DataHigh => {
+                let bank = vram_bank(self.attributes);
+                self.high = mem.read_vram(bank, self.row_address(mem, lcdc) + 1);
+                FetchStep::Push
+            }
+            FetchStep::Push => FetchStep::Push,
+        };
+    }
+
+    //scx and scy are read at every tile, which is what lets games scroll mid line
+    fn map_address(&self, mem: &Memory, lcdc: u8) -> u16 {
+        let (map, x, y) = match self.window {
+            Some(row) => (
+                match lcdc & WINDOW_TILE_MAP {
+                    0 => TILE_MAP_0,
+                    _ => TILE_MAP_1,
+                },
+                self.fetcher_x,
+                row,
+            ),
+            None => (
+                match lcdc & BG_TILE_MAP {
+                    0 => TILE_MAP_0,
+                    _ => TILE_MAP_1,
+                },
+                (mem.read_8(SCX_POSITION) / 8).wrapping_add(self.fetcher_x),
+                self.background_y(mem),
+            ),
+        };
+        map + (y / 8) as u16 * MAP_WIDTH + (x as u16 % MAP_WIDTH)
+    }
+
+    fn row_address(&self, mem: &Memory, lcdc: u8) -> u16 {
+        let y = match self.window {
+            Some(row) => row,
+            None => self.background_y(mem),
+        };
+        let y = match self.attributes & Y_FLIP {
+            0 => y % 8,
+            _ => 7 - y % 8,
+        };
+        tile_address(lcdc, self.tile) + y as u16 * 2
+    }
+
+    fn background_y(&self, mem: &Memory) -> u8 {
+        mem.read_8(LY_POSITION)
+            .wrapping_add(mem.read_8(SCY_POSITION))
+    }
+
+    //mix the front of both fifos into the next pixel with the palettes as they are now
+    fn output(&mut self, mem: &Memory, line: &mut [u8; 160], colour_line: &mut [u16; 160]) -> bool {
+        let Some((colour, attributes)) = self.background.pop_front() else {
+            return false;
+        };
+        if self.discard > 0 {
+            self.discard -= 1;
+            return false;
+        }
+        let lcdc = mem.read_8(LCDC_REGISTER);
+        let cgb = mem.cgb_mode();
+        //on dmg the background is blank with bit 0 clear, objects still show
+        let colour = match cgb || lcdc & BG_AND_WINDOW_ENABLE != 0 {
+            true => colour,
+            false => 0,
+        };
+        let object = self.objects.pop_front().unwrap_or_default();
+        let object_shown = object.colour != 0
+            && lcdc & OBJ_ENABLE != 0
+            && object_on_top(cgb, lcdc, colour, attributes, object.flags);
+        let x = self.x as usize;
+        match (object_shown, cgb) {
+            (true, true) => {
+                line[x] = object.colour;
+                colour_line[x] = mem.obj_colour(object.flags & CGB_PALETTE, object.colour);
+            }
+            (true, false) => {
+                let palette = match object.flags & PALETTE {
+                    0 => OBP0,
+                    _ => OBP1,
+                };
+                line[x] = apply_palette(mem.read_8(palette), object.colour);
+            }
+            (false, true) => {
+                line[x] = colour;
+                colour_line[x] = mem.bg_colour(attributes & CGB_PALETTE, colour);
+            }
+            (false, false) => line[x] = apply_palette(mem.read_8(BGP), colour),
+        }
+        self.x += 1;
+        self.x == PIXEL_WIDTH as u8
+    }
+}
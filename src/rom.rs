@@ -0,0 +1,246 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Cursor, Read};
+use std::path::Path;
+
+use flate2::read::MultiGzDecoder;
+use zip::ZipArchive;
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+//what an archive without any entry starts with
+const EMPTY_ZIP_MAGIC: &[u8] = b"PK\x05\x06";
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+const ROM_EXTENSIONS: [&str; 2] = ["gb", "gbc"];
+//largest rom a cartridge header can declare, anything bigger is read no further than this
+pub(crate) const MAX_ROM_SIZE: usize = 0x800000;
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    //the archive itself is broken
+    Zip(zip::result::ZipError),
+    //the archive holds no .gb or .gbc
+    NoRom,
+    //the archive holds more than one and none was named
+    SeveralRoms(Vec<String>),
+    //the entry named by the user isn't in the archive
+    MissingEntry(String),
+    //more than MAX_ROM_SIZE bytes, after decompressing
+    TooLarge,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(error) => write!(f, "{}", error),
+            LoadError::Zip(error) => write!(f, "bad zip archive: {}", error),
+            LoadError::NoRom => write!(f, "archive doesn't contain a .gb or .gbc file"),
+            LoadError::SeveralRoms(names) => write!(
+                f,
+                "archive contains several roms, pick one with --entry=<name>: {}",
+                names.join(", ")
+            ),
+            LoadError::MissingEntry(name) => write!(f, "archive has no entry named {}", name),
+            LoadError::TooLarge => write!(f, "rom is larger than {}MiB", MAX_ROM_SIZE >> 20),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(error: io::Error) -> LoadError {
+        LoadError::Io(error)
+    }
+}
+
+impl From<zip::result::ZipError> for LoadError {
+    fn from(error: zip::result::ZipError) -> LoadError {
+        LoadError::Zip(error)
+    }
+}
+
+//read a rom from a plain file, a gzip file, or a zip archive told apart by their magic
+//bytes, entry picks the file inside a zip when there is more than one rom in it
+pub fn load(path: &Path, entry: Option<&str>) -> Result<Vec<u8>, LoadError> {
+    //a zip holds a bit more than the rom in it, and compression never makes anything much
+    //bigger, so twice the largest rom is plenty for either
+    let data = read_capped(File::open(path)?, 2 * MAX_ROM_SIZE)?;
+    if data.starts_with(ZIP_MAGIC) || data.starts_with(EMPTY_ZIP_MAGIC) {
+        return unzip(data, entry);
+    }
+    if data.starts_with(GZIP_MAGIC) {
+        return read_capped(MultiGzDecoder::new(data.as_slice()), MAX_ROM_SIZE);
+    }
+    match data.len() > MAX_ROM_SIZE {
+        true => Err(LoadError::TooLarge),
+        false => Ok(data),
+    }
+}
+
+//read everything unless there is more than max, without trusting any declared size
+fn read_capped(reader: impl Read, max: usize) -> Result<Vec<u8>, LoadError> {
+    let mut data = Vec::new();
+    reader.take(max as u64 + 1).read_to_end(&mut data)?;
+    match data.len() > max {
+        true => Err(LoadError::TooLarge),
+        false => Ok(data),
+    }
+}
+
+fn unzip(data: Vec<u8>, entry: Option<&str>) -> Result<Vec<u8>, LoadError> {
+    let mut archive = ZipArchive::new(Cursor::new(data))?;
+    let name = {
+        let files = archive
+            .file_names()
+            .filter(|name| !name.ends_with('/'))
+            .collect::<Vec<_>>();
+        match entry {
+            //either the full path inside the archive or just the file name
+            Some(entry) => files
+                .iter()
+                .find(|name| **name == entry || file_name(name) == entry)
+                .ok_or_else(|| LoadError::MissingEntry(entry.to_string()))?
+                .to_string(),
+            None => {
+                let mut roms = files.into_iter().filter(|name| is_rom_name(name));
+                match (roms.next(), roms.next()) {
+                    (None, _) => return Err(LoadError::NoRom),
+                    (Some(name), None) => name.to_string(),
+                    (Some(first), Some(second)) => {
+                        let mut names = vec![first.to_string(), second.to_string()];
+                        names.extend(roms.map(String::from));
+                        return Err(LoadError::SeveralRoms(names));
+                    }
+                }
+            }
+        }
+    };
+    let file = archive.by_name(&name)?;
+    read_capped(file, MAX_ROM_SIZE)
+}
+
+fn file_name(name: &str) -> &str {
+    name.rsplit('/').next().unwrap_or(name)
+}
+
+fn is_rom_name(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| {
+            ROM_EXTENSIONS
+                .iter()
+                .any(|rom| extension.eq_ignore_ascii_case(rom))
+        })
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    //a directory of its own per test, since they run at the same time
+    fn test_dir(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rom-test-{}-{}", test, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_file(dir: &Path, name: &str, data: &[u8]) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn plain_and_gzip() {
+        let dir = test_dir("gzip");
+        let path = write_file(&dir, "plain.gb", &[1, 2, 3]);
+        assert_eq!(load(&path, None).unwrap(), [1, 2, 3]);
+        let path = write_file(&dir, "packed.gb.gz", &gzip(&[4, 5, 6]));
+        assert_eq!(load(&path, None).unwrap(), [4, 5, 6]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn zip_with_one_rom() {
+        let dir = test_dir("one");
+        let data = zip(&[("readme.txt", b"hi"), ("dir/game.GBC", &[7, 8])]);
+        let path = write_file(&dir, "one.zip", &data);
+        assert_eq!(load(&path, None).unwrap(), [7, 8]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn zip_with_several_roms() {
+        let dir = test_dir("several");
+        let data = zip(&[("a.gb", &[1]), ("dir/b.gb", &[2])]);
+        let path = write_file(&dir, "several.zip", &data);
+        assert!(matches!(
+            load(&path, None),
+            Err(LoadError::SeveralRoms(names)) if names == ["a.gb", "dir/b.gb"]
+        ));
+        assert_eq!(load(&path, Some("b.gb")).unwrap(), [2]);
+        assert_eq!(load(&path, Some("dir/b.gb")).unwrap(), [2]);
+        assert!(matches!(
+            load(&path, Some("c.gb")),
+            Err(LoadError::MissingEntry(name)) if name == "c.gb"
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn zip_without_a_rom() {
+        let dir = test_dir("none");
+        let path = write_file(&dir, "none.zip", &zip(&[("readme.txt", b"hi")]));
+        assert!(matches!(load(&path, None), Err(LoadError::NoRom)));
+        let path = write_file(&dir, "empty.zip", &zip(&[]));
+        assert!(matches!(load(&path, None), Err(LoadError::NoRom)));
+        let path = write_file(&dir, "broken.zip", b"PK\x03\x04broken");
+        assert!(matches!(load(&path, None), Err(LoadError::Zip(_))));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn decompressed_size_is_capped() {
+        let dir = test_dir("capped");
+        let largest = vec![0; MAX_ROM_SIZE];
+        let path = write_file(&dir, "largest.gb.gz", &gzip(&largest));
+        assert_eq!(load(&path, None).unwrap().len(), MAX_ROM_SIZE);
+
+        let too_large = vec![0; MAX_ROM_SIZE + 1];
+        let path = write_file(&dir, "bomb.gb.gz", &gzip(&too_large));
+        assert!(matches!(load(&path, None), Err(LoadError::TooLarge)));
+        let path = write_file(&dir, "bomb.zip", &zip(&[("bomb.gb", &too_large)]));
+        assert!(matches!(load(&path, None), Err(LoadError::TooLarge)));
+        let path = write_file(&dir, "huge.gb", &too_large);
+        assert!(matches!(load(&path, None), Err(LoadError::TooLarge)));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
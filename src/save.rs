@@ -0,0 +1,153 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::cartridge::Mapper;
+
+//how often changed save data is written back while running
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+//battery backed save data in a .sav next to the rom: the raw save data followed by
+//the mapper rtc footer, like other emulators lay it out
+pub struct SaveFile {
+    path: PathBuf,
+    //the game changed the save data since it was last written
+    dirty: bool,
+    last_flush: Instant,
+}
+
+impl SaveFile {
+    pub fn for_rom(rom_path: &Path) -> SaveFile {
+        SaveFile {
+            path: rom_path.with_extension("sav"),
+            dirty: false,
+            last_flush: Instant::now(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    //fill the cartridge from the .sav if there is one
+    pub fn load(&mut self, mapper: &mut dyn Mapper) -> io::Result<()> {
+        let data = match fs::read(&self.path) {
+            Ok(data) => data,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(error) => return Err(error),
+        };
+        let len = mapper.save_data().len().min(data.len());
+        mapper.load_save_data(&data[..len]);
+        mapper.load_rtc_footer(&data[len..]);
+        Ok(())
+    }
+
+    //write the save data now, changed or not
+    pub fn flush(&mut self, mapper: &mut dyn Mapper) -> io::Result<()> {
+        self.dirty |= mapper.take_dirty();
+        //a failed write is retried at the next interval rather than every frame
+        self.last_flush = Instant::now();
+        write_atomically(&self.path, &contents(mapper))?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    //call regularly while running, writes once the interval passed if the game changed something
+    pub fn flush_if_due(&mut self, mapper: &mut dyn Mapper) -> io::Result<()> {
+        self.dirty |= mapper.take_dirty();
+        if !self.dirty || self.last_flush.elapsed() < FLUSH_INTERVAL {
+            return Ok(());
+        }
+        self.flush(mapper)
+    }
+
+    //when the .sav can't be written, put the save in the temp directory instead so it
+    //isn't lost on exit, returns where it went
+    pub fn rescue(&self, mapper: &dyn Mapper) -> io::Result<PathBuf> {
+        let name = self.path.file_name().unwrap_or("rom.sav".as_ref());
+        let path = env::temp_dir().join(name);
+        write_atomically(&path, &contents(mapper))?;
+        Ok(path)
+    }
+}
+
+fn contents(mapper: &dyn Mapper) -> Vec<u8> {
+    let mut contents = mapper.save_data().to_vec();
+    contents.extend(mapper.rtc_footer());
+    contents
+}
+
+//write next to the old save and swap it in, a crash leaves either one intact
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    //carts with neither save data nor a clock have nothing to keep
+    if contents.is_empty() {
+        return Ok(());
+    }
+    let temp_path = path.with_extension("sav.tmp");
+    let mut file = File::create(&temp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&temp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::header::RamSize;
+    use crate::cartridge::{self, MapperKind};
+
+    fn mbc1() -> Box<dyn Mapper> {
+        let mut mapper = cartridge::new(vec![0; 0x8000], MapperKind::Mbc1, RamSize::from_byte(2));
+        mapper.write_rom(0x0000, 0x0A);
+        mapper
+    }
+
+    fn save_file(name: &str) -> SaveFile {
+        let dir = env::temp_dir().join(format!("save-test-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        SaveFile::for_rom(&dir.join("game.gb"))
+    }
+
+    #[test]
+    fn flush_and_load() {
+        let mut save = save_file("flush");
+        let mut mapper = mbc1();
+        mapper.write_ram(0xA123, 0x45);
+        save.flush(mapper.as_mut()).unwrap();
+        assert_eq!(fs::read(save.path()).unwrap().len(), 0x2000);
+
+        let mut loaded = mbc1();
+        save.load(loaded.as_mut()).unwrap();
+        assert_eq!(loaded.read_ram(0xA123), 0x45);
+        fs::remove_dir_all(save.path().parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn only_dirty_saves_are_flushed_when_due() {
+        let mut save = save_file("due");
+        let mut mapper = mbc1();
+        save.last_flush -= FLUSH_INTERVAL;
+        save.flush_if_due(mapper.as_mut()).unwrap();
+        assert!(!save.path().exists());
+        //changes wait for the interval
+        mapper.write_ram(0xA000, 0x12);
+        save.last_flush = Instant::now();
+        save.flush_if_due(mapper.as_mut()).unwrap();
+        assert!(!save.path().exists());
+        save.last_flush -= FLUSH_INTERVAL;
+        save.flush_if_due(mapper.as_mut()).unwrap();
+        assert_eq!(fs::read(save.path()).unwrap()[0], 0x12);
+        fs::remove_dir_all(save.path().parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn missing_save_is_not_an_error() {
+        let mut save = save_file("missing");
+        let mut mapper = mbc1();
+        save.load(mapper.as_mut()).unwrap();
+        assert_eq!(mapper.read_ram(0xA000), 0);
+        fs::remove_dir_all(save.path().parent().unwrap()).unwrap();
+    }
+}